@@ -1,59 +1,63 @@
-use crate::anthropic::tools::editor::Editor;
+use crate::anthropic::tools::{bash::Bash, editor::Editor, web_search::WebSearch};
 use crate::core::{
-    Error,
+    Error, ProviderErrorKind,
     llm::{
-        self, AssistantContent, Content as LlmContent, Function, Hyperparams,
-        Message as LlmMessage, Model, Usage as LlmUsage, UserContent,
+        self, AssistantContent, Citation, Content as LlmContent, Function, Hyperparams,
+        Message as LlmMessage, Model, ModelInfo, Pricing, ReasoningEffort, StopReason,
+        Usage as LlmUsage, UserContent,
     },
-    tool::ProviderTool,
+    tool::{BoxedProviderTool, ProviderTool},
+    transport::{HttpRequest, HttpResponse, Method},
 };
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::{Arc, Mutex};
 
-use super::{AnthropicModel, Claude};
+use super::{Anthropic, Claude};
 
 #[derive(Clone)]
 pub struct ClaudeModel {
-    client: Client,
-    api_key: String,
+    client: Anthropic,
     model: Claude,
-    system_prompt: Option<String>,
+    system_prompt: Vec<String>,
     hyperparams: Hyperparams,
+    last_request_id: Arc<Mutex<Option<String>>>,
 }
 
 impl ClaudeModel {
     pub fn new(
-        client: Client,
-        api_key: String,
+        client: Anthropic,
         model: Claude,
-        system_prompt: Option<String>,
+        system_prompt: Vec<String>,
         hyperparams: Hyperparams,
     ) -> Self {
         Self {
             client,
-            api_key,
             model,
             system_prompt,
             hyperparams,
+            last_request_id: Arc::new(Mutex::new(None)),
         }
     }
+
+    /// The ID Anthropic gave the last request sent by this model, if any. Quote this when
+    /// reporting problems to Anthropic's support.
+    pub fn last_request_id(&self) -> Option<String> {
+        self.last_request_id.lock().unwrap().clone()
+    }
 }
 
-impl Model for ClaudeModel {
-    async fn call(
+impl ClaudeModel {
+    /// Build the request for a new message, which is also used to count tokens.
+    pub(super) fn new_messages(
         &self,
-        messages: impl AsRef<[LlmMessage]>,
-        functions: impl AsRef<[Function]>,
-    ) -> Result<llm::Completion, Error> {
-        let anthropic_messages = messages
-            .as_ref()
-            .iter()
-            .map(map_llm_message_to_anthropic)
-            .collect::<Vec<_>>();
+        messages: &[LlmMessage],
+        functions: &[Function],
+    ) -> NewMessages {
+        let anthropic_messages =
+            merge_consecutive(messages.iter().map(map_llm_message_to_anthropic));
 
         let anthropic_tools = functions
-            .as_ref()
             .iter()
             .map(|f| match f {
                 Function::Local {
@@ -75,55 +79,323 @@ impl Model for ClaudeModel {
             })
             .collect::<Vec<_>>();
 
-        let payload = NewMessages {
+        let thinking = self.thinking();
+        // Thinking only works at the default temperature
+        let temperature = match thinking {
+            Some(_) => None,
+            None => Some(self.hyperparams.temperature),
+        };
+        let max_tokens = self.response_max_tokens();
+
+        NewMessages {
             model: self.model.to_string(),
-            max_tokens: self.hyperparams.max_tokens,
-            temperature: Some(self.hyperparams.temperature),
-            system: self.system_prompt.clone(),
+            max_tokens,
+            // Anthropic has no seed, so `hyperparams.seed` can't be passed on
+            temperature,
+            thinking,
+            // Each section is sent as its own text block, which keeps them clearly separated
+            system: self
+                .system_prompt
+                .iter()
+                .map(|text| Content::Text {
+                    text: text.clone(),
+                    citations: None,
+                })
+                .collect(),
             messages: anthropic_messages,
             tools: anthropic_tools,
+            metadata: self
+                .client
+                .user_id
+                .clone()
+                .map(|user_id| Metadata { user_id }),
+        }
+    }
+
+    async fn post(&self, path: &str, body: String) -> Result<HttpResponse, Error> {
+        let req = HttpRequest {
+            method: Method::Post,
+            url: format!("{}{}", self.client.base_url, path),
+            headers: headers(&self.client, Some(&self.model)),
+            body,
         };
+        let resp = self.client.transport.send(req).await?;
+        let request_id = resp
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("request-id"))
+            .map(|(_, value)| value.clone());
+        *self.last_request_id.lock().unwrap() = request_id;
+        Ok(resp)
+    }
 
-        let body = serde_json::to_string(&payload)?;
-        let req = self
+    fn thinking(&self) -> Option<Thinking> {
+        self.hyperparams
+            .reasoning_effort
+            .filter(|_| self.supports_thinking())
+            .map(|effort| Thinking::Enabled {
+                budget_tokens: thinking_budget(effort),
+            })
+    }
+
+    fn supports_thinking(&self) -> bool {
+        match self.model {
+            Claude::Custom(_) => self.client.custom_models_think,
+            _ => self.model.supports_thinking(),
+        }
+    }
+
+    /// The max tokens to request, which has to make room for thinking since it counts towards it.
+    fn response_max_tokens(&self) -> u32 {
+        match self.thinking() {
+            Some(Thinking::Enabled { budget_tokens }) => {
+                (self.hyperparams.max_tokens + budget_tokens).min(self.max_output_tokens())
+            }
+            None => self.hyperparams.max_tokens,
+        }
+    }
+
+    /// The most tokens a single response may use, taking the long output beta into account. An
+    /// unknown model's limit is taken to be what was asked for, unless it's allowed to think like
+    /// the newest known model.
+    fn max_output_tokens(&self) -> u32 {
+        if self
             .client
-            .post("https://api.anthropic.com/v1/messages")
-            .body(body)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json");
-        let resp = req.send().await?.text().await?;
-        let completion: Completion = serde_json::from_str(&resp)?;
-
-        match completion {
-            Completion::Message {
-                content,
-                id: _,
-                model: _,
-                stop_reason: _,
-                stop_sequence: _,
-                usage,
-            } => {
-                let llm_content = content
-                    .into_iter()
-                    .map(map_anthropic_content_to_llm)
-                    .collect();
-                Ok(llm::Completion {
-                    usage: LlmUsage {
-                        input_tokens: usage.input_tokens,
-                        output_tokens: usage.output_tokens,
-                    },
-                    content: llm_content,
-                })
+            .betas
+            .iter()
+            .any(|b| b.starts_with("output-128k"))
+        {
+            return 128000;
+        }
+        match self.model.max_output_tokens() {
+            Some(max) => max,
+            None if self.client.custom_models_think => Claude::ThreeDotSevenSonnet
+                .max_output_tokens()
+                .unwrap_or(self.hyperparams.max_tokens),
+            None => self.hyperparams.max_tokens,
+        }
+    }
+
+    /// Describe an error returned by the API, alongside the ID of the request that caused it.
+    fn error(&self, error: ErrorInfo) -> Error {
+        let mut error = Error::from(error);
+        if let (Error::Provider { message, .. }, Some(request_id)) =
+            (&mut error, self.last_request_id())
+        {
+            message.push_str(&format!(" (request ID {})", request_id));
+        }
+        error
+    }
+}
+
+const TOKEN_EFFICIENT_TOOLS: &str = "token-efficient-tools-2025-02-19";
+
+/// The headers of a request, which has the betas of the given model if it's for one.
+pub(super) fn headers(client: &Anthropic, model: Option<&Claude>) -> Vec<(String, String)> {
+    let mut headers = vec![
+        ("x-api-key".to_string(), client.api_key.clone()),
+        ("anthropic-version".to_string(), "2023-06-01".to_string()),
+        ("content-type".to_string(), "application/json".to_string()),
+    ];
+    let mut betas = client.betas.clone();
+    if client.token_efficient_tools
+        && model.is_some_and(|model| model.supports_token_efficient_tools())
+        && !betas.iter().any(|b| b == TOKEN_EFFICIENT_TOOLS)
+    {
+        betas.push(TOKEN_EFFICIENT_TOOLS.to_string());
+    }
+    if !betas.is_empty() {
+        headers.push(("anthropic-beta".to_string(), betas.join(",")));
+    }
+    for (name, value) in &client.headers {
+        headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        headers.push((name.clone(), value.clone()));
+    }
+    headers
+}
+
+/// List the models available to the client's API key, newest first.
+pub async fn list_models(client: &Anthropic) -> Result<Vec<ModelInfo>, Error> {
+    let req = HttpRequest {
+        method: Method::Get,
+        url: format!("{}/v1/models?limit=1000", client.base_url),
+        headers: headers(client, None),
+        body: String::new(),
+    };
+    let resp = client.transport.send(req).await?;
+    match serde_json::from_str(&resp.body)? {
+        ModelList::Models { data } => Ok(data
+            .into_iter()
+            .map(|m| ModelInfo {
+                id: m.id,
+                display_name: m.display_name,
+            })
+            .collect()),
+        ModelList::Error { error } => Err(error.into()),
+    }
+}
+
+impl Model for ClaudeModel {
+    async fn call(
+        &self,
+        messages: impl AsRef<[LlmMessage]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<llm::Completion, Error> {
+        let mut payload = self.new_messages(messages.as_ref(), functions.as_ref());
+        let mut total = LlmUsage::default();
+        loop {
+            let body = serde_json::to_string(&payload)?;
+            log::debug!("Sending a {} byte message request", body.len());
+            if body.len() > self.client.max_request_bytes {
+                return Err(Error::Provider {
+                    kind: ProviderErrorKind::RequestTooLarge,
+                    message: format!(
+                        "The request is {} bytes, over the limit of {} bytes, so it wasn't sent. \
+                        The history is probably holding a huge tool result or file, so start a \
+                        new conversation or raise the limit.",
+                        body.len(),
+                        self.client.max_request_bytes
+                    ),
+                });
+            }
+            if let Some(limiter) = &self.client.limiter {
+                limiter.acquire().await;
+            }
+            let resp = self.post("/v1/messages", body).await?;
+            let completion: Completion = serde_json::from_str(&resp.body)?;
+
+            match completion {
+                Completion::Message {
+                    content,
+                    id,
+                    model: _,
+                    stop_reason,
+                    stop_sequence: _,
+                    usage,
+                } => {
+                    log::debug!(
+                        "Anthropic request {} returned message {}",
+                        self.last_request_id().as_deref().unwrap_or("(unknown)"),
+                        id
+                    );
+                    total.input_tokens += usage.input_tokens;
+                    total.output_tokens += usage.output_tokens;
+                    // A tool call that was cut off has incomplete input, and unlike text it can't be
+                    // continued by prefilling it into the next request. Instead, ask again with more
+                    // room so that a half-written input (i.e a truncated file) never reaches a tool.
+                    let truncated = stop_reason == "max_tokens"
+                        && matches!(content.last(), Some(Content::ToolUse { .. }));
+                    let limit = self.max_output_tokens();
+                    if truncated && payload.max_tokens < limit {
+                        payload.max_tokens = (payload.max_tokens * 2).min(limit);
+                        log::info!(
+                            "Tool call was cut off by the token limit, retrying with {} max tokens",
+                            payload.max_tokens
+                        );
+                        continue;
+                    }
+                    let llm_content = content
+                        .into_iter()
+                        .map(map_anthropic_content_to_llm)
+                        .collect();
+                    return Ok(llm::Completion {
+                        usage: total,
+                        content: llm_content,
+                        stop_reason: map_anthropic_stop_reason_to_llm(stop_reason),
+                    });
+                }
+                Completion::Error { error } => return Err(self.error(error)),
             }
-            Completion::Error { error } => Err(Error::Provider(error.message)),
         }
     }
+
+    async fn count_tokens(
+        &self,
+        messages: impl AsRef<[LlmMessage]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<Option<u32>, Error> {
+        let payload = self.new_messages(messages.as_ref(), functions.as_ref());
+        let payload = CountTokens {
+            model: payload.model,
+            system: payload.system,
+            messages: payload.messages,
+            tools: payload.tools,
+        };
+        let body = serde_json::to_string(&payload)?;
+        let resp = self.post("/v1/messages/count_tokens", body).await?;
+        match serde_json::from_str::<TokenCount>(&resp.body)? {
+            TokenCount::Count { input_tokens } => Ok(Some(input_tokens)),
+            TokenCount::Error { error } => Err(self.error(error)),
+        }
+    }
+
+    fn max_tokens(&self) -> Option<u32> {
+        Some(self.response_max_tokens())
+    }
+
+    fn pricing(&self) -> Option<Pricing> {
+        match self.model {
+            Claude::ThreeDotFiveSonnet | Claude::ThreeDotSevenSonnet => Some(Pricing {
+                input: 3.0,
+                output: 15.0,
+            }),
+            Claude::Custom(_) => None,
+        }
+    }
+
+    fn provider_tools(&self) -> Vec<BoxedProviderTool<'static>> {
+        vec![
+            BoxedProviderTool::new(Editor::new(
+                self.model.clone(),
+                self.client.editor_config,
+                self.client.reviewer.clone(),
+                self.client.edit_history(),
+            )),
+            BoxedProviderTool::new(Bash::new(self.model.clone())),
+            BoxedProviderTool::new(WebSearch),
+        ]
+    }
+
+    fn supports_provider_tool(&self, id: &str) -> bool {
+        self.provider_tools().iter().any(|tool| tool.id() == id)
+    }
+
+    fn supports_prefill(&self) -> bool {
+        self.thinking().is_none()
+    }
 }
 
-impl AnthropicModel for ClaudeModel {
-    fn editor<'a, 'b>(&'a self) -> impl ProviderTool + 'b {
-        Editor::new(self.model)
+/// Convert a complete response from the API, i.e one fetched from a batch.
+pub(super) fn map_anthropic_completion_to_llm(
+    completion: Completion,
+) -> Result<llm::Completion, Error> {
+    match completion {
+        Completion::Message {
+            content,
+            stop_reason,
+            usage,
+            ..
+        } => Ok(llm::Completion {
+            usage: LlmUsage {
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+            },
+            content: content
+                .into_iter()
+                .map(map_anthropic_content_to_llm)
+                .collect(),
+            stop_reason: map_anthropic_stop_reason_to_llm(stop_reason),
+        }),
+        Completion::Error { error } => Err(error.into()),
+    }
+}
+
+fn map_anthropic_stop_reason_to_llm(stop_reason: String) -> StopReason {
+    match stop_reason.as_str() {
+        "end_turn" => StopReason::EndTurn,
+        "tool_use" => StopReason::FunctionCall,
+        "max_tokens" => StopReason::MaxTokens,
+        _ => StopReason::Other(stop_reason),
     }
 }
 
@@ -144,44 +416,139 @@ fn map_llm_message_to_anthropic(msg: &LlmMessage) -> Message {
     }
 }
 
+/// Merge messages from the same role that follow each other, which Anthropic rejects, i.e when a
+/// prompt is sent after a turn that was cut short before the model answered.
+fn merge_consecutive(messages: impl Iterator<Item = Message>) -> Vec<Message> {
+    let mut merged: Vec<Message> = Vec::new();
+    for message in messages {
+        match (merged.last_mut(), message) {
+            (Some(Message::User { content }), Message::User { content: more }) => {
+                content.extend(more);
+                // Tool results have to come before anything else in a user message
+                content.sort_by_key(|c| !matches!(c, Content::ToolResult { .. }));
+            }
+            (Some(Message::Assistant { content }), Message::Assistant { content: more }) => {
+                content.extend(more);
+            }
+            (_, message) => merged.push(message),
+        }
+    }
+    merged
+}
+
 fn map_llm_user_content_to_anthropic(content: &UserContent) -> Content {
     match content {
-        UserContent::Input(LlmContent::Text(text)) => Content::Text { text: text.clone() },
+        UserContent::Input(content) => map_llm_content_to_anthropic(content),
         UserContent::FunctionResult { id, result } => Content::ToolResult {
             tool_use_id: id.clone(),
             is_error: result.is_err(),
             content: match result {
-                Ok(texts) => texts
+                Ok(content) => content
                     .iter()
-                    .map(|t| {
-                        let inner_text = match t {
-                            LlmContent::Text(s) => s.clone(),
-                        };
-                        Box::new(Some(Content::Text { text: inner_text }))
-                    })
+                    .map(map_llm_tool_result_to_anthropic)
                     .collect(),
-                Err(LlmContent::Text(text)) => {
-                    vec![Box::new(Some(Content::Text { text: text.clone() }))]
-                }
+                Err(content) => vec![map_llm_tool_result_to_anthropic(content)],
+            },
+        },
+    }
+}
+
+fn map_llm_content_to_anthropic(content: &LlmContent) -> Content {
+    match content {
+        LlmContent::Text(text) => Content::Text {
+            text: text.clone(),
+            citations: None,
+        },
+        LlmContent::Image { media_type, data } => Content::Image {
+            source: ImageSource::Base64 {
+                media_type: media_type.clone(),
+                data: data.clone(),
             },
         },
+        LlmContent::Json(value) => Content::Text {
+            text: value.to_string(),
+            citations: None,
+        },
+    }
+}
+
+fn map_llm_tool_result_to_anthropic(content: &LlmContent) -> ToolResultContent {
+    match content {
+        LlmContent::Text(text) => ToolResultContent::Text { text: text.clone() },
+        LlmContent::Image { media_type, data } => ToolResultContent::Image {
+            source: ImageSource::Base64 {
+                media_type: media_type.clone(),
+                data: data.clone(),
+            },
+        },
+        LlmContent::Json(value) => ToolResultContent::Text {
+            text: value.to_string(),
+        },
     }
 }
 
 fn map_llm_assistant_content_to_anthropic(content: &AssistantContent) -> Content {
     match content {
-        AssistantContent::Output(LlmContent::Text(text)) => Content::Text { text: text.clone() },
+        AssistantContent::Output(content) => map_llm_content_to_anthropic(content),
         AssistantContent::FunctionCall { id, name, input } => Content::ToolUse {
             id: id.clone(),
             name: name.clone(),
             input: input.clone(),
         },
+        AssistantContent::Reasoning {
+            redacted: true,
+            signature,
+            ..
+        } => Content::RedactedThinking {
+            data: signature.clone(),
+        },
+        AssistantContent::Reasoning {
+            text, signature, ..
+        } => Content::Thinking {
+            thinking: text.clone(),
+            signature: signature.clone(),
+        },
+        // Citations are only for the user, so the model just gets its own text back
+        AssistantContent::Cited { text, .. } => Content::Text {
+            text: text.clone(),
+            citations: None,
+        },
     }
 }
 
 fn map_anthropic_content_to_llm(content: Content) -> AssistantContent {
     match content {
-        Content::Text { text } => AssistantContent::Output(LlmContent::Text(text)),
+        Content::Text {
+            text,
+            citations: Some(citations),
+        } if !citations.is_empty() => AssistantContent::Cited {
+            text,
+            citations: citations
+                .into_iter()
+                .map(|c| Citation {
+                    cited_text: c.cited_text,
+                    title: c.document_title.or(c.title),
+                    url: c.url,
+                })
+                .collect(),
+        },
+        Content::Text { text, .. } => AssistantContent::Output(LlmContent::Text(text)),
+        Content::Image {
+            source: ImageSource::Base64 { media_type, data },
+        } => AssistantContent::Output(LlmContent::Image { media_type, data }),
+        Content::Thinking {
+            thinking,
+            signature,
+        } => AssistantContent::Reasoning {
+            text: thinking,
+            signature,
+            redacted: false,
+        },
+        Content::RedactedThinking { data } => AssistantContent::Reasoning {
+            text: String::new(),
+            signature: data,
+            redacted: true,
+        },
         Content::ToolUse { id, name, input } => AssistantContent::FunctionCall { id, name, input },
         Content::ToolResult {
             tool_use_id,
@@ -191,6 +558,33 @@ fn map_anthropic_content_to_llm(content: Content) -> AssistantContent {
             "[ToolResult for {}: is_error={}, content={:?}]",
             tool_use_id, is_error, content
         ))),
+        // Server tools were already run by Anthropic, so all that's left to do is keep a record
+        // of what they did in the conversation.
+        Content::ServerToolUse { id: _, name, input } => {
+            AssistantContent::Output(LlmContent::Text(format!("[Used {}: {}]", name, input)))
+        }
+        Content::WebSearchToolResult {
+            tool_use_id: _,
+            content,
+        } => AssistantContent::Output(LlmContent::Text(describe_web_search_results(&content))),
+    }
+}
+
+fn describe_web_search_results(content: &Value) -> String {
+    match content.as_array() {
+        Some(results) => {
+            let mut out = String::from("[Web search results]");
+            for result in results {
+                let title = result["title"].as_str().unwrap_or("Untitled");
+                let url = result["url"].as_str().unwrap_or_default();
+                out.push_str(&format!("\n- {} ({})", title, url));
+            }
+            out
+        }
+        None => format!(
+            "[Web search failed: {}]",
+            content["error_code"].as_str().unwrap_or("unknown error")
+        ),
     }
 }
 
@@ -199,12 +593,52 @@ fn map_anthropic_content_to_llm(content: Content) -> AssistantContent {
 pub struct NewMessages {
     pub model: String,
     pub max_tokens: u32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub system: Vec<Content>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<Thinking>,
     pub messages: Vec<Message>,
     pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Metadata {
+    pub user_id: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Thinking {
+    Enabled { budget_tokens: u32 },
+}
+
+fn thinking_budget(effort: ReasoningEffort) -> u32 {
+    match effort {
+        ReasoningEffort::Low => 1024,
+        ReasoningEffort::Medium => 4096,
+        ReasoningEffort::High => 16384,
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CountTokens {
+    pub model: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub system: Vec<Content>,
+    pub messages: Vec<Message>,
+    pub tools: Vec<Tool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum TokenCount {
+    Count { input_tokens: u32 },
+    Error { error: ErrorInfo },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -231,18 +665,65 @@ pub enum Completion {
     },
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ModelList {
+    Models { data: Vec<ModelEntry> },
+    Error { error: ErrorInfo },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ModelEntry {
+    id: String,
+    display_name: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub struct ErrorInfo {
+    #[serde(rename = "type", default)]
+    kind: String,
     message: String,
 }
 
+impl From<ErrorInfo> for Error {
+    fn from(error: ErrorInfo) -> Error {
+        let kind = match error.kind.as_str() {
+            "invalid_request_error" => ProviderErrorKind::InvalidRequest,
+            "authentication_error" => ProviderErrorKind::Authentication,
+            "permission_error" => ProviderErrorKind::Permission,
+            "not_found_error" => ProviderErrorKind::NotFound,
+            "request_too_large" => ProviderErrorKind::RequestTooLarge,
+            "rate_limit_error" => ProviderErrorKind::RateLimit,
+            "overloaded_error" => ProviderErrorKind::Overloaded,
+            "api_error" => ProviderErrorKind::Internal,
+            _ => ProviderErrorKind::Other,
+        };
+        Error::Provider {
+            kind,
+            message: error.message,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum Content {
     Text {
         text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        citations: Option<Vec<CitationInfo>>,
+    },
+    Image {
+        source: ImageSource,
+    },
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
     },
     ToolUse {
         id: String,
@@ -252,10 +733,46 @@ pub enum Content {
     ToolResult {
         tool_use_id: String,
         is_error: bool,
-        content: Vec<Box<Option<Content>>>,
+        content: Vec<ToolResultContent>,
+    },
+    ServerToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    WebSearchToolResult {
+        tool_use_id: String,
+        content: Value,
     },
 }
 
+/// A block in the content of a tool result, which can only be text or an image.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ToolResultContent {
+    Text { text: String },
+    Image { source: ImageSource },
+}
+
+/// A citation on a text block. Its type decides which location fields are present, but only the
+/// ones common to every type are needed here.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CitationInfo {
+    cited_text: String,
+    #[serde(default)]
+    document_title: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ImageSource {
+    Base64 { media_type: String, data: String },
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub struct Usage {
@@ -273,3 +790,495 @@ pub struct Tool {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub input_schema: Option<serde_json::Value>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::agent::{Agent, Verbosity};
+    use crate::core::fallback::Fallback;
+    use crate::core::limiter::SharedLimiter;
+    use crate::core::llm::Provider;
+    use crate::core::tool::Toolbox;
+    use crate::core::transport::HttpTransport;
+    use serde_json::json;
+    use std::collections::{HashMap, VecDeque};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    // Answers with the given bodies in order, and keeps every request it was sent and when.
+    #[derive(Default)]
+    struct Canned {
+        bodies: Mutex<VecDeque<Value>>,
+        requests: Mutex<Vec<HttpRequest>>,
+        sent_at: Mutex<Vec<Instant>>,
+    }
+
+    impl Canned {
+        fn new(bodies: Vec<Value>) -> Arc<Self> {
+            Arc::new(Self {
+                bodies: Mutex::new(bodies.into()),
+                ..Self::default()
+            })
+        }
+
+        fn bodies(&self) -> Vec<Value> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|r| serde_json::from_str(&r.body).unwrap())
+                .collect()
+        }
+    }
+
+    impl HttpTransport for Canned {
+        fn send<'a>(
+            &'a self,
+            request: HttpRequest,
+        ) -> Pin<Box<dyn Future<Output = Result<HttpResponse, Error>> + Send + 'a>> {
+            self.requests.lock().unwrap().push(request);
+            self.sent_at.lock().unwrap().push(Instant::now());
+            let body = self.bodies.lock().unwrap().pop_front().unwrap_or_default();
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: 200,
+                    headers: Vec::new(),
+                    body: body.to_string(),
+                })
+            })
+        }
+    }
+
+    fn message(stop_reason: &str, content: Value, output_tokens: u32) -> Value {
+        json!({
+            "type": "message",
+            "id": "msg_1",
+            "model": "claude",
+            "stop_reason": stop_reason,
+            "stop_sequence": null,
+            "content": content,
+            "usage": { "input_tokens": 10, "output_tokens": output_tokens }
+        })
+    }
+
+    fn model(client: Anthropic, claude: Claude) -> ClaudeModel {
+        ClaudeModel::new(client, claude, Vec::new(), Hyperparams::deterministic(4096))
+    }
+
+    #[test]
+    fn unknown_models_only_think_when_allowed() {
+        let hyperparams = Hyperparams {
+            reasoning_effort: Some(ReasoningEffort::Low),
+            ..Hyperparams::deterministic(4096)
+        };
+        let haiku = Claude::custom("claude-3-5-haiku-20241022").unwrap();
+        let request = |client: Anthropic| {
+            ClaudeModel::new(client, haiku.clone(), Vec::new(), hyperparams).new_messages(&[], &[])
+        };
+        let plain = request(Anthropic::new("key".to_string()));
+        assert!(plain.thinking.is_none());
+        assert_eq!(plain.max_tokens, 4096);
+
+        let thinking = request(Anthropic::new("key".to_string()).custom_models_think(true));
+        assert!(thinking.thinking.is_some());
+        assert_eq!(
+            thinking.max_tokens,
+            4096 + thinking_budget(ReasoningEffort::Low)
+        );
+    }
+
+    #[test]
+    fn thinking_models_cant_be_prefilled() {
+        let model = |reasoning_effort| {
+            let hyperparams = Hyperparams {
+                reasoning_effort,
+                ..Hyperparams::deterministic(4096)
+            };
+            let client = Anthropic::new("key".to_string());
+            ClaudeModel::new(client, Claude::ThreeDotSevenSonnet, Vec::new(), hyperparams)
+        };
+        assert!(model(None).supports_prefill());
+        assert!(!model(Some(ReasoningEffort::High)).supports_prefill());
+    }
+
+    #[test]
+    fn maps_text() {
+        let message =
+            LlmMessage::User(vec![UserContent::Input(LlmContent::Text("hi".to_string()))]);
+        insta::assert_json_snapshot!(map_llm_message_to_anthropic(&message));
+    }
+
+    #[test]
+    fn maps_tool_calls() {
+        let message = LlmMessage::Assistant(vec![
+            AssistantContent::Output(LlmContent::Text("Let me look.".to_string())),
+            AssistantContent::FunctionCall {
+                id: "toolu_1".to_string(),
+                name: "bash".to_string(),
+                input: json!({ "command": "ls" }),
+            },
+        ]);
+        insta::assert_json_snapshot!(map_llm_message_to_anthropic(&message));
+    }
+
+    #[test]
+    fn maps_tool_results() {
+        let ok = UserContent::FunctionResult {
+            id: "toolu_1".to_string(),
+            result: Ok(vec![LlmContent::Text("Cargo.toml".to_string())]),
+        };
+        insta::assert_json_snapshot!(
+            "maps_tool_results_ok",
+            map_llm_user_content_to_anthropic(&ok)
+        );
+        let err = UserContent::FunctionResult {
+            id: "toolu_2".to_string(),
+            result: Err(LlmContent::Text("No such file".to_string())),
+        };
+        insta::assert_json_snapshot!(
+            "maps_tool_results_err",
+            map_llm_user_content_to_anthropic(&err)
+        );
+    }
+
+    #[test]
+    fn maps_responses() {
+        let text = Content::Text {
+            text: "Done.".to_string(),
+            citations: None,
+        };
+        insta::assert_json_snapshot!("maps_responses_text", map_anthropic_content_to_llm(text));
+        let call = Content::ToolUse {
+            id: "toolu_1".to_string(),
+            name: "bash".to_string(),
+            input: json!({ "command": "ls" }),
+        };
+        insta::assert_json_snapshot!("maps_responses_call", map_anthropic_content_to_llm(call));
+    }
+
+    #[tokio::test]
+    async fn retries_cut_off_tool_calls_with_more_room() {
+        let cut_off = json!([{ "type": "tool_use", "id": "toolu_1", "name": "bash", "input": {} }]);
+        let done = json!([{ "type": "text", "text": "Done." }]);
+        let transport = Canned::new(vec![
+            message("max_tokens", cut_off, 4096),
+            message("end_turn", done, 100),
+        ]);
+        let client = Anthropic::new("key".to_string()).transport(transport.clone());
+        let completion = model(client, Claude::ThreeDotFiveSonnet)
+            .call(Vec::new(), Vec::new())
+            .await
+            .unwrap();
+
+        let bodies = transport.bodies();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0]["max_tokens"], 4096);
+        // Capped at what the model can output
+        assert_eq!(bodies[1]["max_tokens"], 8192);
+        assert_eq!(completion.usage.input_tokens, 20);
+        assert_eq!(completion.usage.output_tokens, 4196);
+        assert!(matches!(
+            completion.content[..],
+            [AssistantContent::Output(LlmContent::Text(_))]
+        ));
+    }
+
+    #[tokio::test]
+    async fn errors_keep_their_kind() {
+        let transport = Canned::new(vec![json!({
+            "type": "error",
+            "error": { "type": "overloaded_error", "message": "Overloaded" }
+        })]);
+        let client = Anthropic::new("key".to_string()).transport(transport);
+        let error = model(client, Claude::ThreeDotSevenSonnet)
+            .call(Vec::new(), Vec::new())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::Provider { kind: ProviderErrorKind::Overloaded, message } if message == "Overloaded"
+        ));
+        assert!(error.is_retryable());
+
+        let error: ErrorInfo =
+            serde_json::from_value(json!({ "type": "authentication_error", "message": "no" }))
+                .unwrap();
+        let error = Error::from(error);
+        assert!(matches!(
+            error,
+            Error::Provider {
+                kind: ProviderErrorKind::Authentication,
+                ..
+            }
+        ));
+        assert!(!error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn rate_limit_envelopes_map_to_rate_limits() {
+        // A 429 body as Anthropic sends it
+        let envelope = r#"{"type":"error","error":{"type":"rate_limit_error","message":"Number of request tokens has exceeded your per-minute rate limit (https://docs.anthropic.com/en/api/rate-limits); see the response headers for current usage. Please reduce the prompt length or the maximum tokens requested, or try again later. You may also contact sales at https://www.anthropic.com/contact-sales to discuss your options for a rate limit increase."}}"#;
+        let Completion::Error { error } = serde_json::from_str(envelope).unwrap() else {
+            panic!("expected an error envelope");
+        };
+        assert!(error.message.starts_with("Number of request tokens"));
+
+        let transport = Canned::new(vec![serde_json::from_str(envelope).unwrap()]);
+        let client = Anthropic::new("key".to_string()).transport(transport);
+        let error = model(client, Claude::ThreeDotSevenSonnet)
+            .call(Vec::new(), Vec::new())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Provider {
+                kind: ProviderErrorKind::RateLimit,
+                ..
+            }
+        ));
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn overloaded_models_fall_back_to_the_next() {
+        // What Anthropic answers with a 529
+        let transport = Canned::new(vec![
+            json!({
+                "type": "error",
+                "error": { "type": "overloaded_error", "message": "Overloaded" }
+            }),
+            message("end_turn", json!([{ "type": "text", "text": "hi" }]), 5),
+        ]);
+        let client = Anthropic::new("key".to_string()).transport(transport.clone());
+        let chain = Fallback::new("sonnet", model(client.clone(), Claude::ThreeDotSevenSonnet))
+            .then("older", model(client, Claude::ThreeDotFiveSonnet));
+        let completion = chain.call(Vec::new(), Vec::new()).await.unwrap();
+        assert_eq!(completion.usage.output_tokens, 5);
+        let models: Vec<Value> = transport
+            .bodies()
+            .iter()
+            .map(|b| b["model"].clone())
+            .collect();
+        assert_eq!(
+            models,
+            [
+                json!("claude-3-7-sonnet-20250219"),
+                json!("claude-3-5-sonnet-20241022")
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn agents_sharing_a_client_share_its_rate_limit() {
+        let answer = || message("end_turn", json!([{ "type": "text", "text": "hi" }]), 5);
+        let transport = Canned::new((0..6).map(|_| answer()).collect());
+        let client = Anthropic::new("key".to_string())
+            .transport(transport.clone())
+            .rate_limit(SharedLimiter::per_minute(2));
+        let agent = || {
+            let model = model(client.clone(), Claude::ThreeDotSevenSonnet);
+            Agent::builder(model, Toolbox::new())
+                .verbosity(Verbosity::Quiet)
+                .buffered(true)
+                .build()
+        };
+        let run = |mut agent: Agent<'static, ClaudeModel>| async move {
+            for prompt in ["one", "two"] {
+                agent.go(prompt.to_string()).await.unwrap();
+            }
+        };
+        let start = Instant::now();
+        tokio::join!(run(agent()), run(agent()), run(agent()));
+
+        // Six requests at two a minute, so no more than two are ever sent within a minute
+        let sent_at = transport.sent_at.lock().unwrap().clone();
+        assert_eq!(sent_at.len(), 6);
+        for window in sent_at.windows(3) {
+            assert!(window[2] - window[0] >= Duration::from_secs(60));
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn merges_consecutive_messages() {
+        let text = |text: &str| UserContent::Input(LlmContent::Text(text.to_string()));
+        let messages = [
+            LlmMessage::User(vec![text("first")]),
+            LlmMessage::Assistant(vec![AssistantContent::Output(LlmContent::Text(
+                "a".to_string(),
+            ))]),
+            LlmMessage::Assistant(vec![AssistantContent::Output(LlmContent::Text(
+                "b".to_string(),
+            ))]),
+            LlmMessage::User(vec![text("second")]),
+            LlmMessage::User(vec![UserContent::FunctionResult {
+                id: "toolu_1".to_string(),
+                result: Ok(Vec::new()),
+            }]),
+        ];
+        let merged = merge_consecutive(messages.iter().map(map_llm_message_to_anthropic));
+        assert_eq!(
+            serde_json::to_value(merged).unwrap(),
+            json!([
+                { "role": "user", "content": [{ "type": "text", "text": "first" }] },
+                {
+                    "role": "assistant",
+                    "content": [{ "type": "text", "text": "a" }, { "type": "text", "text": "b" }]
+                },
+                {
+                    "role": "user",
+                    "content": [
+                        {
+                            "type": "tool_result",
+                            "tool_use_id": "toolu_1",
+                            "is_error": false,
+                            "content": []
+                        },
+                        { "type": "text", "text": "second" }
+                    ]
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn maps_tool_result_blocks() {
+        let result = UserContent::FunctionResult {
+            id: "toolu_1".to_string(),
+            result: Ok(vec![
+                LlmContent::Text("Here's the chart.".to_string()),
+                LlmContent::Image {
+                    media_type: "image/png".to_string(),
+                    data: "iVBORw0K".to_string(),
+                },
+                LlmContent::Json(json!({ "rows": 2 })),
+            ]),
+        };
+        assert_eq!(
+            serde_json::to_value(map_llm_user_content_to_anthropic(&result)).unwrap(),
+            json!({
+                "type": "tool_result",
+                "tool_use_id": "toolu_1",
+                "is_error": false,
+                "content": [
+                    { "type": "text", "text": "Here's the chart." },
+                    {
+                        "type": "image",
+                        "source": { "type": "base64", "media_type": "image/png", "data": "iVBORw0K" }
+                    },
+                    { "type": "text", "text": "{\"rows\":2}" }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn sends_the_user_id_as_metadata() {
+        let client = Anthropic::new("key".to_string());
+        let body = serde_json::to_value(
+            model(client.clone(), Claude::ThreeDotSevenSonnet).new_messages(&[], &[]),
+        )
+        .unwrap();
+        assert!(body.get("metadata").is_none());
+
+        let client = client.user_id("5f2b8c1e");
+        let body =
+            serde_json::to_value(model(client, Claude::ThreeDotSevenSonnet).new_messages(&[], &[]))
+                .unwrap();
+        assert_eq!(body["metadata"], json!({ "user_id": "5f2b8c1e" }));
+    }
+
+    #[tokio::test]
+    async fn no_system_prompt_sends_no_system() {
+        let done = json!([{ "type": "text", "text": "Hi." }]);
+        let transport = Canned::new(vec![
+            message("end_turn", done.clone(), 1),
+            message("end_turn", done, 1),
+        ]);
+        let client = Anthropic::new("key".to_string()).transport(transport.clone());
+        let hyperparams = Hyperparams::deterministic(1024);
+        for system_prompt in [None, Some("Be brief.".to_string())] {
+            client
+                .obtain(Claude::ThreeDotSevenSonnet, system_prompt, hyperparams)
+                .await
+                .call(Vec::new(), Vec::new())
+                .await
+                .unwrap();
+        }
+
+        let bodies = transport.bodies();
+        assert!(bodies[0].get("system").is_none());
+        assert_eq!(
+            bodies[1]["system"],
+            json!([{ "type": "text", "text": "Be brief." }])
+        );
+    }
+
+    fn header<'r>(request: &'r HttpRequest, name: &str) -> Vec<&'r str> {
+        request
+            .headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn extra_headers_replace_the_defaults() {
+        let done = json!([{ "type": "text", "text": "Hi." }]);
+        let transport = Canned::new(vec![message("end_turn", done, 1)]);
+        let client = Anthropic::new("sk-ant-key".to_string())
+            .transport(transport.clone())
+            .headers(HashMap::from([
+                ("X-Api-Key".to_string(), "gateway-key".to_string()),
+                ("x-gateway-route".to_string(), "eu".to_string()),
+                ("Anthropic-Version".to_string(), "1999-01-01".to_string()),
+            ]));
+        model(client, Claude::ThreeDotSevenSonnet)
+            .call(Vec::new(), Vec::new())
+            .await
+            .unwrap();
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(header(&requests[0], "x-api-key"), ["gateway-key"]);
+        assert_eq!(header(&requests[0], "x-gateway-route"), ["eu"]);
+        // The API relies on this one, so it can't be replaced
+        assert_eq!(header(&requests[0], "anthropic-version"), ["2023-06-01"]);
+    }
+
+    fn beta_header(client: &Anthropic, model: Option<&Claude>) -> Option<String> {
+        headers(client, model)
+            .into_iter()
+            .find(|(name, _)| name == "anthropic-beta")
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn token_efficient_tools_only_for_supporting_models() {
+        let client = Anthropic::new("key".to_string())
+            .betas(["output-128k-2025-02-19"])
+            .token_efficient_tools(true);
+        assert_eq!(
+            beta_header(&client, Some(&Claude::ThreeDotSevenSonnet)).as_deref(),
+            Some("output-128k-2025-02-19,token-efficient-tools-2025-02-19")
+        );
+        assert_eq!(
+            beta_header(&client, Some(&Claude::ThreeDotFiveSonnet)).as_deref(),
+            Some("output-128k-2025-02-19")
+        );
+        // Requests that aren't for a model, like listing them, don't get it either
+        assert_eq!(
+            beta_header(&client, None).as_deref(),
+            Some("output-128k-2025-02-19")
+        );
+
+        // Already asked for by hand, so it isn't sent twice
+        let client = client.betas([TOKEN_EFFICIENT_TOOLS]);
+        assert_eq!(
+            beta_header(&client, Some(&Claude::ThreeDotSevenSonnet)).as_deref(),
+            Some(TOKEN_EFFICIENT_TOOLS)
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+use super::api::{self, ClaudeModel, Completion, ErrorInfo, NewMessages};
+use super::{Anthropic, Claude};
+use crate::core::{
+    Error,
+    llm::{self, Function, Hyperparams, Message},
+    transport::{HttpRequest, Method},
+};
+
+/// A single request in a batch, which is answered independently of the others.
+pub struct BatchRequest {
+    /// An ID for the request, unique within the batch, that its result is returned with.
+    pub custom_id: String,
+    /// The model to send the request to.
+    pub model: Claude,
+    /// The sections of the system prompt, as with `Provider::obtain`.
+    pub system_prompt: Vec<String>,
+    /// The hyperparameters to use for the request.
+    pub hyperparams: Hyperparams,
+    /// The conversation to complete.
+    pub messages: Vec<Message>,
+    /// The functions the model may call. Since nothing runs the calls, they end the request.
+    pub functions: Vec<Function>,
+}
+
+/// A batch of requests submitted to the Message Batches API, which are processed asynchronously
+/// (usually within an hour) at a lower cost than sending them one by one.
+pub struct BatchHandle {
+    client: Anthropic,
+    id: String,
+}
+
+/// The progress of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    /// Some requests are still being processed.
+    InProgress,
+    /// The batch was canceled, and the requests still in progress are being stopped.
+    Canceling,
+    /// Every request has finished, so the results can be fetched.
+    Ended,
+}
+
+impl Anthropic {
+    /// Submit the requests as a batch, returning a handle to poll it with once it's accepted.
+    pub async fn batch(&self, requests: Vec<BatchRequest>) -> Result<BatchHandle, Error> {
+        let requests = requests
+            .into_iter()
+            .map(|r| {
+                // The same mapping as an interactive call, so that batched requests behave alike
+                let model = ClaudeModel::new(self.clone(), r.model, r.system_prompt, r.hyperparams);
+                BatchEntry {
+                    custom_id: r.custom_id,
+                    params: model.new_messages(&r.messages, &r.functions),
+                }
+            })
+            .collect();
+        let body = serde_json::to_string(&NewBatch { requests })?;
+        let batch = self
+            .batch_request(Method::Post, "/v1/messages/batches".to_string(), body)
+            .await?;
+        Ok(self.batch_handle(batch.id))
+    }
+
+    /// A handle to a batch that was already submitted, i.e by a previous run of the program.
+    pub fn batch_handle(&self, id: impl Into<String>) -> BatchHandle {
+        BatchHandle {
+            client: self.clone(),
+            id: id.into(),
+        }
+    }
+
+    async fn batch_request(
+        &self,
+        method: Method,
+        path: String,
+        body: String,
+    ) -> Result<Batch, Error> {
+        let req = HttpRequest {
+            method,
+            url: format!("{}{}", self.base_url, path),
+            headers: api::headers(self, None),
+            body,
+        };
+        let resp = self.transport.send(req).await?;
+        match serde_json::from_str(&resp.body)? {
+            BatchResponse::Batch(batch) => Ok(batch),
+            BatchResponse::Error { error } => Err(error.into()),
+        }
+    }
+}
+
+impl BatchHandle {
+    /// The ID of the batch, which can be used to get a handle to it again later.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Check how far along the batch is.
+    pub async fn status(&self) -> Result<BatchStatus, Error> {
+        Ok(self.fetch().await?.processing_status)
+    }
+
+    /// Stop processing the batch. Requests that already finished keep their results.
+    pub async fn cancel(&self) -> Result<(), Error> {
+        let path = format!("/v1/messages/batches/{}/cancel", self.id);
+        self.client
+            .batch_request(Method::Post, path, String::new())
+            .await?;
+        Ok(())
+    }
+
+    /// Fetch the result of every request in the batch alongside its custom ID, in no particular
+    /// order. Fails if the batch hasn't ended yet.
+    pub async fn results(&self) -> Result<Vec<(String, Result<llm::Completion, Error>)>, Error> {
+        let batch = self.fetch().await?;
+        let Some(url) = batch.results_url else {
+            return Err(Error::provider(format!(
+                "Batch {} has not ended yet, so it has no results",
+                self.id
+            )));
+        };
+        let req = HttpRequest {
+            method: Method::Get,
+            url,
+            headers: api::headers(&self.client, None),
+            body: String::new(),
+        };
+        let resp = self.client.transport.send(req).await?;
+        resp.body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let BatchResult { custom_id, result } = serde_json::from_str(line)?;
+                let result = match result {
+                    ResultKind::Succeeded { message } | ResultKind::Errored { error: message } => {
+                        api::map_anthropic_completion_to_llm(message)
+                    }
+                    ResultKind::Canceled => {
+                        Err(Error::provider("The request was canceled".to_string()))
+                    }
+                    ResultKind::Expired => Err(Error::provider(
+                        "The request expired before it could be processed".to_string(),
+                    )),
+                };
+                Ok((custom_id, result))
+            })
+            .collect()
+    }
+
+    async fn fetch(&self) -> Result<Batch, Error> {
+        let path = format!("/v1/messages/batches/{}", self.id);
+        self.client
+            .batch_request(Method::Get, path, String::new())
+            .await
+    }
+}
+
+#[derive(Serialize)]
+struct NewBatch {
+    requests: Vec<BatchEntry>,
+}
+
+#[derive(Serialize)]
+struct BatchEntry {
+    custom_id: String,
+    params: NewMessages,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BatchResponse {
+    Batch(Batch),
+    Error { error: ErrorInfo },
+}
+
+#[derive(Deserialize)]
+struct Batch {
+    id: String,
+    processing_status: BatchStatus,
+    results_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BatchResult {
+    custom_id: String,
+    result: ResultKind,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum ResultKind {
+    Succeeded { message: Completion },
+    Errored { error: Completion },
+    Canceled,
+    Expired,
+}
@@ -0,0 +1,222 @@
+use crate::{
+    anthropic::Claude,
+    core::{
+        llm::Content,
+        tool::{ProviderTool, Sink},
+    },
+    tools::truncate_tail,
+};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a command may run before it is killed.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Anthropic's bash tool, which runs commands in a local shell.
+///
+/// Unlike the shell session the model might expect, every command runs in a fresh `bash` process
+/// in the current directory, so state like environment variables doesn't carry over between calls.
+pub struct Bash {
+    model: Claude,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BashInput {
+    /// The command to run. Required unless restarting.
+    command: Option<String>,
+    /// Whether to restart the shell session.
+    #[serde(default)]
+    restart: bool,
+}
+
+impl Bash {
+    pub fn new(model: Claude) -> Self {
+        Self { model }
+    }
+
+    /// Run a command with `bash -c`, killing it if it runs for too long and keeping the end of its
+    /// output if there's too much.
+    pub(crate) fn run(command: &str, sink: Sink) -> Result<Vec<Content>, Content> {
+        run_for(command, sink, TIMEOUT)
+    }
+}
+
+// Run a command, killing it and everything it started once the timeout passes.
+fn run_for(command: &str, sink: Sink, timeout: Duration) -> Result<Vec<Content>, Content> {
+    let mut bash = Command::new("bash");
+    bash.arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Its own process group lets anything the command leaves running in the background (which
+    // keeps the pipes open) be killed along with it.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut bash, 0);
+    let mut child = bash
+        .spawn()
+        .map_err(|e| Content::Text(format!("Could not start bash: {}", e)))?;
+    // Both pipes have to be drained while waiting, or a chatty command will block forever once
+    // they fill up. The threads aren't joined, since a process that escaped the group could keep
+    // a pipe open forever, so their output comes back over a channel until the deadline.
+    let (sender, receiver) = mpsc::channel();
+    for (index, stream) in [
+        Box::new(child.stdout.take().unwrap()) as Box<dyn Read + Send>,
+        Box::new(child.stderr.take().unwrap()),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let sender = sender.clone();
+        thread::spawn(move || drain(stream, index, sender));
+    }
+    drop(sender);
+
+    let deadline = Instant::now() + timeout;
+    let mut streams = [Vec::new(), Vec::new()];
+    let mut status = None;
+    let finished = loop {
+        match receiver.recv_timeout(Duration::from_millis(50)) {
+            Ok((index, line)) => {
+                sink(&Content::Text(String::from_utf8_lossy(&line).into_owned()));
+                streams[index].extend(line);
+            }
+            // Both pipes were closed, so everything has been read
+            Err(RecvTimeoutError::Disconnected) if status.is_some() => break true,
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(Duration::from_millis(50)),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if status.is_none() {
+            match child.try_wait() {
+                Ok(exited) => status = exited,
+                Err(e) => {
+                    kill(&mut child);
+                    return Err(Content::Text(format!("Could not run command: {}", e)));
+                }
+            }
+        }
+        if Instant::now() > deadline {
+            kill(&mut child);
+            break false;
+        }
+    };
+
+    let [stdout, stderr] = streams;
+    let output = truncate_tail(format!(
+        "{}{}",
+        String::from_utf8_lossy(&stdout),
+        String::from_utf8_lossy(&stderr)
+    ));
+    match (status, finished) {
+        (Some(status), true) if status.success() => Ok(vec![Content::Text(if output.is_empty() {
+            "(no output)".to_string()
+        } else {
+            output
+        })]),
+        (Some(status), true) => Err(Content::Text(format!(
+            "{}\n[Command failed with {}]",
+            output, status
+        ))),
+        (Some(_), false) => Err(Content::Text(format!(
+            "{}\n[Command finished, but what it started in the background was still running after \
+            {} seconds and was killed]",
+            output,
+            timeout.as_secs()
+        ))),
+        (None, _) => Err(Content::Text(format!(
+            "{}\n[Command timed out after {} seconds and was killed]",
+            output,
+            timeout.as_secs()
+        ))),
+    }
+}
+
+// Kill the command along with everything it started.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(group) = i32::try_from(child.id()) {
+        // SAFETY: killpg only sends a signal, and the group is the one the child leads
+        unsafe {
+            libc::killpg(group, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+// Read a stream to the end, sending each line (tagged with the stream's index) as soon as it's
+// read. Reading carries on if nobody is listening anymore, so writers never see a broken pipe.
+fn drain(stream: impl Read, index: usize, sender: Sender<(usize, Vec<u8>)>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line).is_ok_and(|n| n > 0) {
+        let _ = sender.send((index, std::mem::take(&mut line)));
+    }
+}
+
+impl ProviderTool for Bash {
+    type Input = BashInput;
+
+    fn id(&self) -> String {
+        match self.model {
+            Claude::ThreeDotFiveSonnet => "bash_20241022".to_string(),
+            Claude::ThreeDotSevenSonnet | Claude::Custom(_) => "bash_20250124".to_string(),
+        }
+    }
+
+    fn name(&self) -> String {
+        "bash".to_string()
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        self.call_streaming(input, &|_| {})
+    }
+
+    fn call_streaming(&self, input: Self::Input, sink: Sink) -> Result<Vec<Content>, Content> {
+        if input.restart {
+            // Every command already gets a fresh shell, so there's nothing to restart.
+            return Ok(vec![Content::Text(
+                "The shell has been restarted.".to_string(),
+            )]);
+        }
+        match input.command {
+            Some(command) => Bash::run(&command, sink),
+            None => Err(Content::Text(
+                "A command is required unless restarting the shell.".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_processes_dont_outlive_the_timeout() {
+        let start = Instant::now();
+        let Err(Content::Text(output)) = run_for(
+            "(sleep 5; echo late) & echo early",
+            &|_| {},
+            Duration::from_secs(1),
+        ) else {
+            panic!("expected the background process to be killed");
+        };
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert!(output.starts_with("early\n"), "{}", output);
+        assert!(output.contains("was killed"), "{}", output);
+        assert!(!output.contains("late"));
+    }
+
+    #[test]
+    fn keeps_stdout_before_stderr() {
+        let Ok(output) = run_for("echo out; echo err >&2", &|_| {}, TIMEOUT) else {
+            panic!("expected the command to succeed");
+        };
+        assert!(matches!(&output[..], [Content::Text(text)] if text == "out\nerr\n"));
+    }
+}
@@ -1,17 +1,115 @@
+use super::encoding;
 use crate::{
     anthropic::Claude,
-    core::{llm::Content, tool::ProviderTool},
+    core::{
+        llm::Content,
+        tool::{LocalTool, ProviderTool},
+    },
+    tools::diff,
 };
 use schemars::JsonSchema;
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::{fs, io};
 
+/// Anthropic's text editor tool, which Claude models are trained to use.
 pub struct Editor {
     model: Claude,
+    local: LocalEditor,
 }
 
-#[derive(Deserialize, JsonSchema, Debug)]
+/// The same editor as a local tool, for models that don't have Anthropic's built in. It behaves
+/// exactly like `Editor`, but is described to the model with its own schema.
+pub struct LocalEditor {
+    config: EditorConfig,
+    reviewer: Option<Reviewer>,
+    history: EditHistory,
+}
+
+/// The user's verdict on an edit the agent wants to make.
+pub enum Review {
+    /// Write the edit.
+    Accept,
+    /// Don't write the edit, telling the model why so that it can revise it.
+    Reject(String),
+}
+
+/// A callback that is shown the unified diff of every edit before it is written, and decides
+/// whether it goes ahead.
+pub type Reviewer = Arc<dyn Fn(&Path, &str) -> Review + Send + Sync>;
+
+/// The contents of each edited file before its edits were made (or undone), so that they can be
+/// undone (or redone). A missing entry means the file didn't exist yet.
+#[derive(Default)]
+struct Edits {
+    undo: HashMap<PathBuf, Vec<Option<String>>>,
+    redo: HashMap<PathBuf, Vec<Option<String>>>,
+}
+
+/// The edits the editor can undo and redo. Clones share the same history, so that changes made to
+/// files outside of the editor (i.e by a formatter) can be undone with it too.
+#[derive(Clone, Default)]
+pub struct EditHistory(Arc<Mutex<Edits>>);
+
+impl EditHistory {
+    /// Record that the file at the given path was changed from the given contents, or created if
+    /// it didn't exist.
+    pub fn record(&self, path: &Path, before: Option<String>) {
+        let path = key(path);
+        let mut edits = self.0.lock().unwrap();
+        edits.undo.entry(path.clone()).or_default().push(before);
+        // A new edit invalidates anything that was undone before it
+        edits.redo.remove(&path);
+    }
+}
+
+/// The path edits to a file are kept under, relative to the working directory, so that every way
+/// of writing the path (i.e `./src/main.rs`, `src/main.rs` or the absolute path) finds the same
+/// edits. `..` is resolved without following symlinks.
+fn key(path: &Path) -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let mut key = PathBuf::new();
+    for component in path.strip_prefix(&cwd).unwrap_or(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(key.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                key.pop();
+            }
+            component => key.push(component),
+        }
+    }
+    key
+}
+
+/// Configuration for the editor tool.
+#[derive(Debug, Clone, Copy)]
+pub struct EditorConfig {
+    /// Whether to preview edits instead of applying them. When enabled, every command that would
+    /// modify a file returns a diff of the changes it would make without touching the disk.
+    pub dry_run: bool,
+    /// The maximum number of lines returned by a single view, even if a larger range was asked
+    /// for. Larger files are truncated with a hint to view the rest with a range.
+    pub max_view_lines: usize,
+    /// The largest file, in bytes, that will be read. Anything bigger is refused rather than
+    /// loaded into memory, i.e if the model points the editor at a huge log or binary.
+    pub max_file_bytes: u64,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            max_view_lines: 1000,
+            max_file_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
 #[serde(tag = "command", rename_all = "snake_case")]
 pub enum EditorInput {
     /// View the contents of the file at the given path.
@@ -32,7 +130,8 @@ pub enum EditorInput {
     /// Insert a new line of text at the given 1-based line number.
     Insert {
         path: PathBuf,
-        /// 1-based line number to insert at
+        /// 1-based line number to insert at. Use the number of lines in the file plus one to append
+        /// to the end of the file.
         insert_line: u64,
         new_str: String,
     },
@@ -40,14 +139,164 @@ pub enum EditorInput {
     UndoEdit { path: PathBuf },
 }
 
+/// A local tool for the parts of the edit history that Anthropic's editor has no commands for, so
+/// that they can be used alongside either editor.
+pub struct EditHistoryTool {
+    history: EditHistory,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum EditHistoryInput {
+    /// Revert every edit made to every file this session.
+    UndoAll,
+    /// Re-apply the last edit to the file that was undone.
+    RedoEdit { path: PathBuf },
+}
+
 // Helper to map std::io::Error to Content
-fn io_error_to_content(err: io::Error, path: &PathBuf) -> Content {
+fn io_error_to_content(err: io::Error, path: &Path) -> Content {
     Content::Text(format!("I/O error for file {:?}: {}", path, err))
 }
 
 impl Editor {
-    pub fn new(model: Claude) -> Self {
-        Self { model }
+    pub fn new(
+        model: Claude,
+        config: EditorConfig,
+        reviewer: Option<Reviewer>,
+        history: EditHistory,
+    ) -> Self {
+        Self {
+            model,
+            local: LocalEditor::new(config, reviewer, history),
+        }
+    }
+}
+
+impl EditHistoryTool {
+    /// Create a tool that undoes and redoes the edits in the given history, which should be shared
+    /// with the editor.
+    pub fn new(history: EditHistory) -> Self {
+        Self { history }
+    }
+}
+
+impl LocalEditor {
+    pub fn new(config: EditorConfig, reviewer: Option<Reviewer>, history: EditHistory) -> Self {
+        Self {
+            config,
+            reviewer,
+            history,
+        }
+    }
+
+    /// Make sure the file at the given path is small enough to read.
+    fn check_size(&self, path: &Path) -> Result<(), Content> {
+        check_size(path, self.config.max_file_bytes)
+    }
+
+    /// Read the file at the given path, if it isn't too large.
+    fn read(&self, path: &Path) -> Result<String, Content> {
+        read_file(path, self.config.max_file_bytes)
+    }
+
+    /// Write the new contents of a file, or just describe the change if this is a dry run. If there
+    /// is a reviewer, the change is only written once it accepts the diff.
+    fn write(
+        &self,
+        path: &Path,
+        old: &str,
+        new: String,
+        done: String,
+    ) -> Result<Vec<Content>, Content> {
+        if self.config.dry_run {
+            return Ok(vec![Content::Text(format!(
+                "{}{}",
+                DRY_RUN,
+                diff::unified(path, old, &new)
+            ))]);
+        }
+        if let Some(reviewer) = self.reviewer.as_ref().filter(|_| old != new)
+            && let Review::Reject(reason) = reviewer(path, &diff::unified(path, old, &new))
+        {
+            return Err(Content::Text(format!(
+                "The user rejected this edit, so nothing was written. Their feedback: {}",
+                reason
+            )));
+        }
+        let before = path.exists().then(|| old.to_string());
+        restore(path, Some(new))?;
+        self.history.record(path, before);
+        Ok(vec![Content::Text(done)])
+    }
+
+    /// Move the file at the given path one step through its history, from the `from` stack to the
+    /// `to` stack. Returns false if there was nothing to move.
+    fn step(
+        path: &Path,
+        from: &mut HashMap<PathBuf, Vec<Option<String>>>,
+        to: &mut HashMap<PathBuf, Vec<Option<String>>>,
+    ) -> Result<bool, Content> {
+        let path = &key(path);
+        let Some(contents) = from.get_mut(path).and_then(Vec::pop) else {
+            return Ok(false);
+        };
+        let current = read_text(path);
+        if let Err(e) = restore(path, contents.clone()) {
+            // Nothing changed, so the step can still be taken once the problem is fixed
+            from.entry(path.to_path_buf()).or_default().push(contents);
+            return Err(e);
+        }
+        to.entry(path.to_path_buf()).or_default().push(current);
+        Ok(true)
+    }
+}
+
+const DRY_RUN: &str = "Dry run, no changes were written. The edit would produce:\n";
+
+/// Read a text file for the model, decoding it from whatever encoding it is in, unless it's larger
+/// than `max_bytes`. Other tools that view files use this to read them the same way the editor does.
+pub(crate) fn read_file(path: &Path, max_bytes: u64) -> Result<String, Content> {
+    check_size(path, max_bytes)?;
+    encoding::read(path)
+        .map(|(text, _)| text)
+        .map_err(|e| io_error_to_content(e, path))
+}
+
+/// Make sure the file at the given path is no larger than `max_bytes`. Files that can't be
+/// inspected are let through, so that reading them reports the actual problem.
+fn check_size(path: &Path, max_bytes: u64) -> Result<(), Content> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() > max_bytes => Err(Content::Text(format!(
+            "File {:?} is too large: {} bytes, limit {}.",
+            path,
+            metadata.len(),
+            max_bytes
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Files are decoded for the model and written back in their original encoding, so that editing a
+// Latin-1 file or one with a BOM doesn't mangle it.
+fn read_text(path: &Path) -> Option<String> {
+    encoding::read(path).ok().map(|(text, _)| text)
+}
+
+/// Set the contents of a file, or delete it if there shouldn't be one.
+fn restore(path: &Path, contents: Option<String>) -> Result<(), Content> {
+    match contents {
+        Some(contents) => {
+            // Ensure parent directory exists
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| io_error_to_content(e, path))?;
+            }
+            encoding::write(path, &contents).map_err(|e| io_error_to_content(e, path))
+        }
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(io_error_to_content(e, path)),
+            _ => Ok(()),
+        },
     }
 }
 
@@ -57,7 +306,7 @@ impl ProviderTool for Editor {
     fn id(&self) -> String {
         match self.model {
             Claude::ThreeDotFiveSonnet => "text_editor_20241022".to_string(),
-            Claude::ThreeDotSevenSonnet => "text_editor_20250124".to_string(),
+            Claude::ThreeDotSevenSonnet | Claude::Custom(_) => "text_editor_20250124".to_string(),
         }
     }
 
@@ -65,6 +314,66 @@ impl ProviderTool for Editor {
         "str_replace_editor".to_string()
     }
 
+    fn is_read_only(&self, input: &Self::Input) -> bool {
+        self.local.is_read_only(input)
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        self.local.call(input)
+    }
+
+    fn preview(&self, input: &Self::Input) -> Option<String> {
+        self.local.preview(input)
+    }
+}
+
+impl LocalTool for LocalEditor {
+    type Input = EditorInput;
+
+    fn name(&self) -> &'static str {
+        "str_replace_editor"
+    }
+
+    fn description(&self) -> &'static str {
+        "View, create and edit files. `view` shows a file with line numbers (or lists a directory), \
+        `str_replace` replaces a string that occurs exactly once in a file, `create` writes a new \
+        file, and `insert` inserts text at a line number. The last edit to a file can be reverted with \
+        `undo_edit`. Always view a file before editing it, and \
+        include enough surrounding context in `old_str` for it to be unique."
+    }
+
+    fn is_read_only(&self, input: &Self::Input) -> bool {
+        matches!(input, EditorInput::View { .. })
+    }
+
+    // The diff of an edit, from a dry run of it. Undoing and redoing don't go through `write`, so
+    // they can't be dry run and have no preview.
+    fn preview(&self, input: &Self::Input) -> Option<String> {
+        if !matches!(
+            input,
+            EditorInput::StrReplace { .. }
+                | EditorInput::Create { .. }
+                | EditorInput::Insert { .. }
+        ) {
+            return None;
+        }
+        let dry_run = LocalEditor {
+            config: EditorConfig {
+                dry_run: true,
+                ..self.config
+            },
+            reviewer: None,
+            history: self.history.clone(),
+        };
+        Some(match dry_run.call(input.clone()) {
+            Ok(content) => content
+                .iter()
+                .map(|c| c.to_string().trim_start_matches(DRY_RUN).to_string())
+                .collect(),
+            Err(e) => format!("The edit can't be made: {}", e),
+        })
+    }
+
     fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
         match input {
             EditorInput::View { path, view_range } => {
@@ -96,13 +405,12 @@ impl ProviderTool for Editor {
                             }
                         } else {
                             // It's a file, proceed with reading content
-                            let content = fs::read_to_string(&path)
-                                .map_err(|e| io_error_to_content(e, &path))?;
+                            let content = self.read(&path)?;
 
-                            match view_range {
+                            let lines: Vec<&str> = content.lines().collect();
+                            let (start_line, end_line) = match view_range {
                                 Some(range) => {
                                     // Handle specific range view
-                                    let lines: Vec<&str> = content.lines().collect();
                                     let start_line = (range[0].saturating_sub(1)) as usize; // Convert 1-based to 0-based
                                     let end_line = (range[1]).min(lines.len() as u64) as usize; // Convert 1-based end to 0-based exclusive index, capped
 
@@ -114,15 +422,30 @@ impl ProviderTool for Editor {
                                             lines.len()
                                         )));
                                     }
-
-                                    let selected_lines = lines[start_line..end_line].join("\n");
-                                    Ok(vec![Content::Text(selected_lines)])
+                                    (start_line, end_line)
                                 }
                                 None => {
-                                    // No range specified, return entire file content
-                                    Ok(vec![Content::Text(content)])
+                                    // No range specified, return entire file content if it fits
+                                    if lines.len() <= self.config.max_view_lines {
+                                        return Ok(vec![Content::Text(content)]);
+                                    }
+                                    (0, lines.len())
                                 }
+                            };
+
+                            // Cap how much we return so that huge files don't flood the context,
+                            // and nudge the model towards ranged reads instead
+                            let capped_end = end_line.min(start_line + self.config.max_view_lines);
+                            let mut selected_lines = lines[start_line..capped_end].join("\n");
+                            if capped_end < end_line {
+                                selected_lines.push_str(&format!(
+                                    "\n\n[Showing lines {}-{} of a file with {} lines. Use view_range to see more.]",
+                                    start_line + 1,
+                                    capped_end,
+                                    lines.len()
+                                ));
                             }
+                            Ok(vec![Content::Text(selected_lines)])
                         }
                     }
                     Err(e) => Err(io_error_to_content(e, &path)), // Error getting metadata
@@ -133,8 +456,15 @@ impl ProviderTool for Editor {
                 old_str,
                 new_str,
             } => {
-                let content =
-                    fs::read_to_string(&path).map_err(|e| io_error_to_content(e, &path))?;
+                if old_str == new_str {
+                    return Err(Content::Text(
+                        "old_str and new_str are identical, so the file would not change. Check \
+                        what the edit was meant to do and try again with the text it should \
+                        become."
+                            .to_string(),
+                    ));
+                }
+                let content = self.read(&path)?;
 
                 let matches: Vec<_> = content.match_indices(&old_str).collect();
                 if matches.len() != 1 {
@@ -147,23 +477,29 @@ impl ProviderTool for Editor {
                 }
 
                 let new_content = content.replacen(&old_str, &new_str, 1);
-                fs::write(&path, new_content).map_err(|e| io_error_to_content(e, &path))?;
-
-                Ok(vec![Content::Text(format!(
-                    "Successfully replaced string in {:?}",
-                    path
-                ))])
+                let mut message = format!("Successfully replaced string in {:?}", path);
+                // Not an error, since repeated text is often intended, but the next replacement
+                // of it will need more context to match only once.
+                let repeats = new_content.matches(&new_str).count();
+                if !new_str.is_empty() && repeats > 1 {
+                    message.push_str(&format!(
+                        ". Note that new_str now occurs {} times in the file, so include more \
+                        surrounding lines to edit it again.",
+                        repeats
+                    ));
+                }
+                self.write(&path, &content, new_content, message)
             }
             EditorInput::Create { path, file_text } => {
-                // Ensure parent directory exists
-                if let Some(parent) = path.parent() {
-                    fs::create_dir_all(parent).map_err(|e| io_error_to_content(e, &path))?;
-                }
-                fs::write(&path, file_text).map_err(|e| io_error_to_content(e, &path))?;
-                Ok(vec![Content::Text(format!(
-                    "Successfully created/updated file {:?}",
-                    path
-                ))])
+                // Only needed to show what a dry run would overwrite, and to undo the overwrite
+                self.check_size(&path)?;
+                let old_content = read_text(&path).unwrap_or_default();
+                self.write(
+                    &path,
+                    &old_content,
+                    file_text,
+                    format!("Successfully created/updated file {:?}", path),
+                )
             }
             EditorInput::Insert {
                 path,
@@ -177,37 +513,292 @@ impl ProviderTool for Editor {
                     ));
                 }
 
-                let content =
-                    fs::read_to_string(&path).map_err(|e| io_error_to_content(e, &path))?;
+                let content = self.read(&path)?;
                 let mut lines: Vec<String> = content.lines().map(String::from).collect();
 
                 let insert_index = (insert_line.saturating_sub(1)) as usize; // Convert 1-based to 0-based index
 
+                // Inserting at one past the last line appends to the end of the file
                 if insert_index > lines.len() {
                     return Err(Content::Text(format!(
-                        "Insert line {} is out of bounds for file with {} lines.",
+                        "Insert line {} is out of bounds for file with {} lines. Use line {} to append to the end of the file.",
                         insert_line,
-                        lines.len()
+                        lines.len(),
+                        lines.len() + 1
                     )));
                 }
 
                 lines.insert(insert_index, new_str);
 
-                let new_content = lines.join("\n");
-                fs::write(&path, new_content).map_err(|e| io_error_to_content(e, &path))?;
+                // lines() drops the trailing newline, so restore it if the file had one (or was
+                // empty, in which case we're creating its first line)
+                let mut new_content = lines.join("\n");
+                if content.is_empty() || content.ends_with('\n') {
+                    new_content.push('\n');
+                }
+                self.write(
+                    &path,
+                    &content,
+                    new_content,
+                    format!(
+                        "Successfully inserted line at {} in {:?}",
+                        insert_line, path
+                    ),
+                )
+            }
+            EditorInput::UndoEdit { path } => {
+                let history = &mut *self.history.0.lock().unwrap();
+                if !LocalEditor::step(&path, &mut history.undo, &mut history.redo)? {
+                    return Err(Content::Text(format!(
+                        "There are no edits to undo in {:?}",
+                        path
+                    )));
+                }
+                Ok(vec![Content::Text(format!(
+                    "Successfully undid the last edit to {:?}",
+                    path
+                ))])
+            }
+        }
+    }
+}
+
+impl LocalTool for EditHistoryTool {
+    type Input = EditHistoryInput;
+
+    fn name(&self) -> &'static str {
+        "edit_history"
+    }
+
+    fn description(&self) -> &'static str {
+        "Undo or redo edits made to files this session. `undo_all` reverts every file that was \
+        edited to how it was before its first edit, and `redo_edit` reapplies the last edit to a \
+        file that was undone, or everything `undo_all` reverted in it."
+    }
 
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        match input {
+            EditHistoryInput::UndoAll => {
+                let history = &mut *self.history.0.lock().unwrap();
+                let paths: Vec<PathBuf> = history
+                    .undo
+                    .iter()
+                    .filter(|(_, edits)| !edits.is_empty())
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                if paths.is_empty() {
+                    return Err(Content::Text("There are no edits to undo.".to_string()));
+                }
+                for path in &paths {
+                    // Going straight back to the original contents makes undoing each file a
+                    // single step, so one redo_edit of a file brings back all of its edits
+                    let edits = history.undo.remove(path).unwrap_or_default();
+                    let current = read_text(path);
+                    if let Err(e) = restore(path, edits.first().cloned().flatten()) {
+                        history.undo.insert(path.clone(), edits);
+                        return Err(e);
+                    }
+                    history.redo.entry(path.clone()).or_default().push(current);
+                }
                 Ok(vec![Content::Text(format!(
-                    "Successfully inserted line at {} in {:?}",
-                    insert_line, path
+                    "Successfully undid all edits to {} files: {:?}",
+                    paths.len(),
+                    paths
                 ))])
             }
-            EditorInput::UndoEdit { path } => {
-                // Proper undo requires history tracking, which is complex.
-                Err(Content::Text(format!(
-                    "Undo functionality is not implemented for file {:?}",
+            EditHistoryInput::RedoEdit { path } => {
+                let history = &mut *self.history.0.lock().unwrap();
+                if !LocalEditor::step(&path, &mut history.redo, &mut history.undo)? {
+                    return Err(Content::Text(format!(
+                        "There are no edits to redo in {:?}",
+                        path
+                    )));
+                }
+                Ok(vec![Content::Text(format!(
+                    "Successfully redid the last undone edit to {:?}",
                     path
-                )))
+                ))])
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("asimov-editor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn replace(path: &Path, old: &str, new: &str) -> EditorInput {
+        EditorInput::StrReplace {
+            path: path.to_path_buf(),
+            old_str: old.to_string(),
+            new_str: new.to_string(),
+        }
+    }
+
+    #[test]
+    fn edit_undo_redo() {
+        let dir = temp_dir("undo");
+        let path = dir.join("a.txt");
+        fs::write(&path, "one\n").unwrap();
+        let history = EditHistory::default();
+        let editor = LocalEditor::new(EditorConfig::default(), None, history.clone());
+        let edits = EditHistoryTool::new(history);
+
+        editor.call(replace(&path, "one", "two")).unwrap();
+        editor.call(replace(&path, "two", "three")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three\n");
+
+        editor
+            .call(EditorInput::UndoEdit { path: path.clone() })
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two\n");
+        edits
+            .call(EditHistoryInput::RedoEdit { path: path.clone() })
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three\n");
+
+        // Undoing everything goes back to before the first edit, and one redo brings it all back
+        edits.call(EditHistoryInput::UndoAll).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+        edits
+            .call(EditHistoryInput::RedoEdit { path: path.clone() })
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn paths_share_one_key() {
+        let cwd = std::env::current_dir().unwrap();
+        for path in ["src/main.rs", "./src/main.rs", "src/../src/./main.rs"] {
+            assert_eq!(key(Path::new(path)), Path::new("src/main.rs"));
+        }
+        assert_eq!(key(&cwd.join("src/main.rs")), Path::new("src/main.rs"));
+        assert_eq!(key(Path::new("../other.rs")), Path::new("../other.rs"));
+    }
+
+    #[test]
+    fn failed_undo_can_be_retried() {
+        let dir = temp_dir("retry");
+        let path = dir.join("a.txt");
+        fs::write(&path, "one\n").unwrap();
+        let editor = LocalEditor::new(EditorConfig::default(), None, EditHistory::default());
+        editor.call(replace(&path, "one", "two")).unwrap();
+
+        // A directory in the file's place can't be written over
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        let undo = || editor.call(EditorInput::UndoEdit { path: path.clone() });
+        assert!(undo().is_err());
+
+        fs::remove_dir(&path).unwrap();
+        undo().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn insert(path: &Path, line: u64, text: &str) -> EditorInput {
+        EditorInput::Insert {
+            path: path.to_path_buf(),
+            insert_line: line,
+            new_str: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn insert_appends_and_keeps_trailing_newlines() {
+        let dir = temp_dir("insert");
+        let editor = LocalEditor::new(EditorConfig::default(), None, EditHistory::default());
+        let path = dir.join("a.txt");
+        fs::write(&path, "one\ntwo\n").unwrap();
+        editor.call(insert(&path, 3, "three")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\nthree\n");
+        editor.call(insert(&path, 1, "zero")).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "zero\none\ntwo\nthree\n"
+        );
+        let Err(Content::Text(error)) = editor.call(insert(&path, 9, "nine")) else {
+            panic!("expected inserting past the end to fail");
+        };
+        assert!(error.contains("Use line 5 to append"), "{}", error);
+
+        // A file without a trailing newline doesn't gain one
+        let bare = dir.join("b.txt");
+        fs::write(&bare, "one").unwrap();
+        editor.call(insert(&bare, 2, "two")).unwrap();
+        assert_eq!(fs::read_to_string(&bare).unwrap(), "one\ntwo");
+
+        // An empty file only has a first line to insert at
+        let empty = dir.join("c.txt");
+        fs::write(&empty, "").unwrap();
+        editor.call(insert(&empty, 1, "one")).unwrap();
+        assert_eq!(fs::read_to_string(&empty).unwrap(), "one\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dry_runs_leave_files_untouched() {
+        let dir = temp_dir("dry-run");
+        let config = EditorConfig {
+            dry_run: true,
+            ..EditorConfig::default()
+        };
+        let editor = LocalEditor::new(config, None, EditHistory::default());
+        let path = dir.join("a.txt");
+        fs::write(&path, "one\n").unwrap();
+        let created = dir.join("b.txt");
+        for input in [
+            EditorInput::Create {
+                path: created.clone(),
+                file_text: "new\n".to_string(),
+            },
+            EditorInput::Create {
+                path: path.clone(),
+                file_text: "new\n".to_string(),
+            },
+            replace(&path, "one", "two"),
+        ] {
+            let output = editor.call(input).unwrap();
+            let [Content::Text(text)] = output.as_slice() else {
+                panic!("expected a single diff");
+            };
+            assert!(text.starts_with(DRY_RUN), "{}", text);
+        }
+        assert!(!created.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_op_replacements_fail_and_repeats_are_noted() {
+        let dir = temp_dir("replace");
+        let editor = LocalEditor::new(EditorConfig::default(), None, EditHistory::default());
+        let path = dir.join("a.txt");
+        fs::write(&path, "let a = 1;\nlet b = 2;\n").unwrap();
+        let Err(Content::Text(error)) = editor.call(replace(&path, "let a", "let a")) else {
+            panic!("expected a no-op replacement to fail");
+        };
+        assert!(error.contains("identical"), "{}", error);
+
+        let output = editor
+            .call(replace(&path, "let a = 1;", "let b = 2;"))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "let b = 2;\nlet b = 2;\n"
+        );
+        assert!(output.iter().any(
+            |content| matches!(content, Content::Text(text) if text.contains("now occurs 2 times"))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How a text file is stored on disk, so that edits can be written back the same way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextEncoding {
+    encoding: &'static Encoding,
+    bom: bool,
+}
+
+impl Default for TextEncoding {
+    /// UTF-8 without a BOM, which is what new files are written as.
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+        }
+    }
+}
+
+/// Read a text file, decoding it from whatever encoding it is in and stripping any BOM.
+pub fn read(path: &Path) -> io::Result<(String, TextEncoding)> {
+    decode(&fs::read(path)?)
+}
+
+/// Write a text file, encoding it the same way as the file that is already there (if any).
+pub fn write(path: &Path, text: &str) -> io::Result<()> {
+    let encoding = match fs::read(path) {
+        // Overwriting a binary file replaces it with text, which is written as new files are
+        Ok(bytes) => decode(&bytes).map(|(_, e)| e).unwrap_or_default(),
+        Err(_) => TextEncoding::default(),
+    };
+    fs::write(path, encode(text, encoding)?)
+}
+
+/// Decode text, detecting its encoding from its BOM. Text without one is UTF-8 if it is valid
+/// UTF-8, and otherwise assumed to be Latin-1 (or rather Windows-1252, its common superset), which
+/// is the most likely legacy encoding for source code. Windows-1252 can decode any bytes, so text
+/// without a BOM that has NUL bytes in it is taken to be binary and refused instead.
+pub fn decode(bytes: &[u8]) -> io::Result<(String, TextEncoding)> {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        return Ok((
+            text.into_owned(),
+            TextEncoding {
+                encoding,
+                bom: true,
+            },
+        ));
+    }
+    if bytes.contains(&0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the file looks like a binary file rather than text",
+        ));
+    }
+    let encoding = match std::str::from_utf8(bytes) {
+        Ok(_) => UTF_8,
+        Err(_) => WINDOWS_1252,
+    };
+    let (text, _) = encoding.decode_without_bom_handling(bytes);
+    Ok((
+        text.into_owned(),
+        TextEncoding {
+            encoding,
+            bom: false,
+        },
+    ))
+}
+
+/// Encode text the given way, failing if it has characters the encoding can't represent.
+pub fn encode(text: &str, encoding: TextEncoding) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    // encoding_rs only decodes UTF-16, so it has to be encoded by hand
+    if encoding.encoding == UTF_16LE || encoding.encoding == UTF_16BE {
+        let little = encoding.encoding == UTF_16LE;
+        let units = encoding.bom.then_some(0xFEFF).into_iter();
+        for unit in units.chain(text.encode_utf16()) {
+            bytes.extend(if little {
+                unit.to_le_bytes()
+            } else {
+                unit.to_be_bytes()
+            });
+        }
+        return Ok(bytes);
+    }
+    if encoding.bom {
+        bytes.extend(b"\xEF\xBB\xBF");
+    }
+    let (encoded, _, unmappable) = encoding.encoding.encode(text);
+    if unmappable {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the file is encoded as {}, which can't represent some of the new characters",
+                encoding.encoding.name()
+            ),
+        ));
+    }
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_boms() {
+        let cases: [&[u8]; 4] = [
+            b"\xEF\xBB\xBFcaf\xC3\xA9\n",
+            b"\xFF\xFEc\0a\0f\0\xE9\0\n\0",
+            b"\xFE\xFF\0c\0a\0f\0\xE9\0\n",
+            b"caf\xE9\n",
+        ];
+        for bytes in cases {
+            let (text, encoding) = decode(bytes).unwrap();
+            assert_eq!(text, "caf\u{e9}\n");
+            assert_eq!(encode(&text, encoding).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn refuses_binary() {
+        assert!(decode(b"\x7FELF\x02\x01\x01\0\0\0").is_err());
+        assert!(decode(b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR").is_err());
+    }
+}
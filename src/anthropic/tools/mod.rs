@@ -1 +1,4 @@
+pub mod bash;
 pub mod editor;
+mod encoding;
+pub mod web_search;
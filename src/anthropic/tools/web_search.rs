@@ -0,0 +1,30 @@
+use crate::core::{llm::Content, tool::ProviderTool};
+use serde_json::Value;
+
+/// Anthropic's web search tool.
+///
+/// Searches are run by Anthropic's servers in the middle of generating a response, and their
+/// results come back as part of it, so the tool is never actually called locally.
+pub struct WebSearch;
+
+impl ProviderTool for WebSearch {
+    type Input = Value;
+
+    fn id(&self) -> String {
+        "web_search_20250305".to_string()
+    }
+
+    fn name(&self) -> String {
+        "web_search".to_string()
+    }
+
+    fn call(&self, _input: Self::Input) -> Result<Vec<Content>, Content> {
+        Err(Content::Text(
+            "web_search is run by Anthropic's servers and cannot be called locally.".to_string(),
+        ))
+    }
+
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        true
+    }
+}
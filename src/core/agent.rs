@@ -1,9 +1,24 @@
-use super::llm::{AssistantContent, Content, Message, Model, UserContent};
-use super::tool::Toolbox;
+use super::Error;
+use super::audit::{AuditLog, CallStatus};
+use super::history::{HistoryStore, InMemoryHistory};
+use super::llm::{
+    AssistantContent, Content, Function, Message, Model, StopReason, TimedMessage, Usage,
+    UserContent,
+};
+use super::redact::Redactor;
+use super::tool::{ToolStats, Toolbox, content_bytes};
 use colored::*;
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
 
 /// A simple "ampcode-style" agent.
-/// 
+///
 /// This agent will run the LLM with a set of tools, evaluate the resulting tool calls, and then
 /// return the results to the LLM. This will continue until the LLM does not return any more tool
 /// calls. Note that this Agent assumes that the LLM can chain tool calls indefinitely to complete
@@ -11,55 +26,1563 @@ use colored::*;
 pub struct Agent<'a, M: Model> {
     model: M,
     toolbox: Toolbox<'a>,
-    history: Vec<Message>,
+    history: Box<dyn HistoryStore + 'a>,
+    usage: Usage,
+    spent_usd: f64,
+    budget_usd: Option<f64>,
+    redactor: Redactor,
+    audit_log: Option<AuditLog>,
+    verbosity: Verbosity,
+    limiter: Arc<Semaphore>,
+    max_tool_failures: Option<usize>,
+    batch_reviewer: Option<BatchReviewer<'a>>,
+    markdown: bool,
+    mode: Mode,
+    output: Output,
 }
 
+/// The largest file, in bytes, that can be attached to a prompt with `go_with_files`.
+pub const MAX_ATTACHED_BYTES: u64 = 256 * 1024;
+
+/// How many model calls and tool calls may run at the same time by default.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// How many tool calls may fail in a row by default before the model is told to step back.
+pub const DEFAULT_MAX_TOOL_FAILURES: usize = 5;
+
+/// How much of the agent's work is printed while it runs.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verbosity {
+    /// Only print the model's text.
+    Quiet,
+    /// Also print the name, a summary of the input and the status of every tool call, alongside
+    /// any output the tool streams while it runs.
+    #[default]
+    Normal,
+    /// Also print the full input and result of every tool call.
+    Verbose,
+}
+
+/// A rough range for what the first request of a turn will use, from `Agent::estimate`. Every
+/// round of tool calls sends another request with the whole history, so a turn that uses tools
+/// will cost more than this.
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    /// The input tokens of the request.
+    pub input_tokens: u32,
+    /// The most output tokens the response could use, if the model's limit is known. At best, it
+    /// uses next to none.
+    pub max_output_tokens: Option<u32>,
+    /// The cost of the request in US dollars, from an empty response to one that uses every
+    /// output token. Only known for models with known pricing.
+    pub cost_usd: Option<(f64, f64)>,
+}
+
+/// Whether the agent may change anything, or only look around.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Mode {
+    /// Only read-only tool calls run, so the model can explore and come up with a plan, but has to
+    /// present it before doing anything. See `LocalTool::is_read_only`.
+    Plan,
+    /// Every tool call runs.
+    #[default]
+    Act,
+}
+
+/// A call the model wants to make that would change something, shown for review before it runs.
+pub struct PendingCall<'c> {
+    /// The name of the tool.
+    pub name: &'c str,
+    /// The input to the tool.
+    pub input: &'c Value,
+    /// What the call would change (i.e a diff), if the tool can tell. See `LocalTool::preview`.
+    pub preview: Option<String>,
+}
+
+/// A callback that is shown every call in a response that would change something, all at once,
+/// and decides whether they run. Returning an error rejects all of them, with the user's feedback.
+pub type BatchReviewer<'a> = Box<dyn Fn(&[PendingCall]) -> Result<(), String> + Send + Sync + 'a>;
+
+/// The error sent back for calls that would change something while planning.
+const PLAN_REJECTION: &str = "can change files or run commands, which isn't allowed while planning. \
+    Finish exploring with read-only tools, then present your plan to the user. They will let you \
+    carry it out once they approve it.";
+
 impl<'a, M: Model> Agent<'a, M> {
+    /// Create an agent with everything but its model and tools left at the defaults. See
+    /// `Agent::builder` to configure the rest up front.
     pub fn new(model: M, toolbox: Toolbox<'a>) -> Self {
         Self {
             model,
             toolbox,
-            history: Vec::new(),
+            history: Box::new(InMemoryHistory::default()),
+            usage: Usage::default(),
+            spent_usd: 0.0,
+            budget_usd: None,
+            redactor: Redactor::default(),
+            audit_log: None,
+            verbosity: Verbosity::default(),
+            limiter: Arc::new(Semaphore::new(DEFAULT_CONCURRENCY)),
+            max_tool_failures: Some(DEFAULT_MAX_TOOL_FAILURES),
+            batch_reviewer: None,
+            markdown: false,
+            mode: Mode::default(),
+            output: Output::default(),
+        }
+    }
+
+    /// Start building an agent with the given model and tools, i.e:
+    ///
+    /// ```ignore
+    /// let agent = Agent::builder(model, toolbox)
+    ///     .mode(Mode::Plan)
+    ///     .budget_usd(Some(1.0))
+    ///     .verbosity(Verbosity::Quiet)
+    ///     .build();
+    /// ```
+    ///
+    /// Everything that isn't set is left at the same defaults as `Agent::new`.
+    pub fn builder(model: M, toolbox: Toolbox<'a>) -> AgentBuilder<'a, M> {
+        AgentBuilder(Self::new(model, toolbox))
+    }
+
+    /// The conversation history so far, alongside when each message was sent.
+    pub fn history(&self) -> Vec<TimedMessage> {
+        self.history.iter().cloned().collect()
+    }
+
+    /// Keep the conversation history in the given store instead of in memory, i.e to persist it
+    /// across restarts. Anything already in the store is picked up as the current history.
+    pub fn set_history_store(&mut self, store: impl HistoryStore + 'a) -> Result<(), Error> {
+        self.history = Box::new(store);
+        self.repair_history()?;
+        Ok(())
+    }
+
+    /// Render the conversation history as Markdown, with any secrets redacted.
+    pub fn export_markdown(&self) -> String {
+        let mut out = super::export::markdown(&self.history());
+        out.push_str(&super::export::tool_stats(&self.toolbox.tool_stats()));
+        self.redactor.redact(&out)
+    }
+
+    /// Set how secrets are found and masked in logged tool inputs and exported histories.
+    pub fn set_redactor(&mut self, redactor: Redactor) {
+        self.redactor = redactor;
+    }
+
+    /// Record every tool call in the given audit log, with secrets redacted from its input.
+    pub fn set_audit_log(&mut self, audit_log: Option<AuditLog>) {
+        self.audit_log = audit_log;
+    }
+
+    /// Set how much of the agent's work is printed while it runs.
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+
+    /// Set whether the model's text is formatted as Markdown when printed. This only affects what
+    /// is printed, and is skipped when stdout isn't a terminal.
+    pub fn set_markdown(&mut self, markdown: bool) {
+        self.markdown = markdown;
+    }
+
+    /// Set whether everything the agent prints during a turn is held back and printed at once when
+    /// the turn ends, instead of as it happens. This keeps the output of non-interactive runs (i.e
+    /// piped into a file) in one piece.
+    pub fn set_buffered(&mut self, buffered: bool) {
+        self.output.buffered = buffered;
+    }
+
+    /// Set whether the agent may change anything, or may only plan. Switching takes effect from
+    /// the next tool call.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Whether the agent may currently change anything.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Set how many model calls and tool calls may run at the same time, i.e to avoid rate limits
+    /// or too many subprocesses at once. Defaults to `DEFAULT_CONCURRENCY`, and is at least one.
+    pub fn set_concurrency_limit(&mut self, limit: usize) {
+        self.limiter = Arc::new(Semaphore::new(limit.max(1)));
+    }
+
+    /// Set how many tool calls may fail in a row before the agent steps in, or `None` to never.
+    /// The first time, the model is told to step back and rethink its approach. If the calls
+    /// after that keep failing, the turn ends and control returns to the user, rather than
+    /// spending more on a model stuck retrying the same mistake. Any successful call starts the
+    /// count over. Defaults to `DEFAULT_MAX_TOOL_FAILURES`.
+    pub fn set_max_tool_failures(&mut self, max: Option<usize>) {
+        self.max_tool_failures = max;
+    }
+
+    /// Show every call in a response that would change something to the given reviewer before
+    /// any of them run, instead of reviewing edits one at a time. They're approved or rejected
+    /// together, with each rejected call given the reviewer's feedback. Approval isn't a
+    /// transaction: approved calls run as usual, so if one fails, the changes made by the others
+    /// stay made. Read-only calls always run.
+    pub fn set_batch_reviewer(&mut self, reviewer: Option<BatchReviewer<'a>>) {
+        self.batch_reviewer = reviewer;
+    }
+
+    /// Replace the conversation history, i.e to resume a previously saved session.
+    pub fn set_history(&mut self, history: Vec<TimedMessage>) -> Result<(), Error> {
+        self.history.load(history)?;
+        self.repair_history()?;
+        Ok(())
+    }
+
+    /// Make sure every function call in the history has a result, synthesizing an error result for
+    /// any call that was interrupted before it could finish (i.e if the agent crashed mid-turn
+    /// and the session was saved). Providers reject histories with unanswered calls, so this is
+    /// done automatically whenever the history is replaced and before every turn. Returns how many
+    /// results had to be synthesized.
+    pub fn repair_history(&mut self) -> Result<usize, Error> {
+        let mut history = self.history();
+        let repaired = repair(&mut history);
+        if repaired > 0 {
+            log::warn!(
+                "Repaired {} interrupted function calls in the history",
+                repaired
+            );
+            self.history.load(history)?;
         }
+        Ok(repaired)
+    }
+
+    /// Clear the conversation history and usage and tool statistics, starting a fresh session.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.history.clear()?;
+        self.usage = Usage::default();
+        self.spent_usd = 0.0;
+        self.toolbox.reset_stats();
+        Ok(())
+    }
+
+    /// Set the most the agent may spend this session, in US dollars. Once the cost of the session
+    /// goes over the budget, the agent stops and returns `Error::BudgetExceeded`, leaving the work
+    /// done so far in the history. Spending is only tracked for models with known pricing.
+    pub fn set_budget(&mut self, budget_usd: Option<f64>) {
+        self.budget_usd = budget_usd;
+    }
+
+    /// The estimated cost of this session so far, in US dollars.
+    pub fn spent_usd(&self) -> f64 {
+        self.spent_usd
+    }
+
+    /// The total tokens used by the model over this session.
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// How each tool has been used over this session.
+    pub fn tool_stats(&self) -> Vec<(String, ToolStats)> {
+        self.toolbox.tool_stats()
+    }
+
+    /// Replace the model used by the agent. The conversation history is kept, so the new model
+    /// will pick up where the previous one left off.
+    pub fn set_model(&mut self, model: M) {
+        self.model = model;
+    }
+
+    /// The tools available to the agent.
+    pub fn toolbox(&self) -> &Toolbox<'a> {
+        &self.toolbox
+    }
+
+    /// Mutable access to the agent's tools, allowing them to be enabled or disabled between turns.
+    pub fn toolbox_mut(&mut self) -> &mut Toolbox<'a> {
+        &mut self.toolbox
+    }
+
+    /// Replace the conversation history and pick it up where it left off. If the model's last
+    /// message has function calls that never got results (i.e because the process crashed or
+    /// handed the session off before running them), they are run and the agent carries on with
+    /// the turn, returning the tokens used doing so. Otherwise this is the same as `set_history`.
+    pub async fn resume_history(&mut self, mut history: Vec<TimedMessage>) -> Result<Usage, Error> {
+        let pending: Vec<_> = pending_calls(&history)
+            .into_iter()
+            .map(|(id, name, input)| (id.to_string(), name.to_string(), input.clone()))
+            .collect();
+        // Anything else that was interrupted can't be picked up again, so it's repaired as usual.
+        // The last message is left out of the repair, since its calls are about to get real results.
+        let last = if pending.is_empty() {
+            None
+        } else {
+            history.pop()
+        };
+        self.history.load(history)?;
+        self.repair_history()?;
+        let Some(last) = last else {
+            return Ok(Usage::default());
+        };
+        self.history.append(last)?;
+        log::info!("Resuming {} pending function calls", pending.len());
+        let (results, _) = self
+            .dispatch(
+                pending
+                    .iter()
+                    .map(|(id, name, input)| (id, name, input, self.check_call(id, name, input)))
+                    .collect(),
+            )
+            .await;
+        self.run(results, None).await
+    }
+
+    /// Run the agent on the given input until it stops calling tools, returning the tokens used.
+    pub async fn go(&mut self, and: String) -> Result<Usage, Error> {
+        self.repair_history()?;
+        self.run(vec![UserContent::Input(Content::Text(and))], None)
+            .await
+    }
+
+    /// Run the agent on the given input, with the model's response starting with the given text,
+    /// i.e `{` to make it answer with JSON. The model continues on from the prefill, and the
+    /// prefill is kept as the start of its response in the history. The prefill can't be empty or
+    /// end with whitespace, which providers reject, and the model has to support prefills (i.e
+    /// Claude doesn't while it's thinking).
+    pub async fn go_with_prefill(&mut self, and: String, prefill: String) -> Result<Usage, Error> {
+        if prefill.is_empty() || prefill.trim_end().len() != prefill.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The prefill can't be empty or end with whitespace",
+            )
+            .into());
+        }
+        if !self.model.supports_prefill() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The model can't be prefilled, i.e because it's set to think first",
+            )
+            .into());
+        }
+        self.repair_history()?;
+        self.run(vec![UserContent::Input(Content::Text(and))], Some(prefill))
+            .await
+    }
+
+    /// Estimate what sending the given input would use, without sending it. Returns `None` if the
+    /// model can't count tokens.
+    pub async fn estimate(&self, input: &str) -> Result<Option<Estimate>, Error> {
+        let mut messages: Vec<Message> = self.history.iter().map(|m| m.message.clone()).collect();
+        messages.push(Message::User(vec![UserContent::Input(Content::Text(
+            input.to_string(),
+        ))]));
+        let Some(input_tokens) = self
+            .model
+            .count_tokens(&messages, &self.functions()?)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let max_output_tokens = self.model.max_tokens();
+        let cost_usd = self
+            .model
+            .pricing()
+            .zip(max_output_tokens)
+            .map(|(pricing, max)| {
+                let usage = |output_tokens| Usage {
+                    input_tokens,
+                    output_tokens,
+                };
+                (pricing.cost(&usage(0)), pricing.cost(&usage(max)))
+            });
+        Ok(Some(Estimate {
+            input_tokens,
+            max_output_tokens,
+            cost_usd,
+        }))
     }
 
-    pub async fn go(&mut self, and: String) -> Result<(), super::Error> {
+    /// Run the agent on the given input with the contents of the given files attached, saving the
+    /// model from having to view them itself. Each file is sent as its own block labeled with its
+    /// path. Files have to be inside the current directory and no larger than
+    /// `MAX_ATTACHED_BYTES`, otherwise nothing is sent.
+    pub async fn go_with_files(
+        &mut self,
+        and: String,
+        paths: &[impl AsRef<Path>],
+    ) -> Result<Usage, Error> {
+        let root = std::env::current_dir()?.canonicalize()?;
         let mut send = vec![UserContent::Input(Content::Text(and))];
-        while !send.is_empty() {
-            self.history.push(Message::User(send.drain(..).collect()));
-            let completion = self
-                .model
-                .call(&self.history, &self.toolbox.functions()?)
-                .await?;
-            for content in &completion.content {
+        for path in paths {
+            let path = path.as_ref();
+            let full = root.join(path).canonicalize()?;
+            if !full.starts_with(&root) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is outside of the workspace", path.display()),
+                )
+                .into());
+            }
+            let size = full.metadata()?.len();
+            if size > MAX_ATTACHED_BYTES {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} is too large to attach: {} bytes, limit {}",
+                        path.display(),
+                        size,
+                        MAX_ATTACHED_BYTES
+                    ),
+                )
+                .into());
+            }
+            let text = std::fs::read_to_string(&full)?;
+            send.push(UserContent::Input(Content::Text(format!(
+                "=== {} ===\n{}",
+                path.display(),
+                text
+            ))));
+        }
+        self.repair_history()?;
+        self.run(send, None).await
+    }
+
+    // Send the content to the model, and keep running its function calls and sending back the
+    // results until it stops making them. The first response is prefilled with the given text.
+    async fn run(
+        &mut self,
+        send: Vec<UserContent>,
+        prefill: Option<String>,
+    ) -> Result<Usage, Error> {
+        let result = self.run_turn(send, prefill).await;
+        self.output.finish();
+        result
+    }
+
+    async fn run_turn(
+        &mut self,
+        send: Vec<UserContent>,
+        mut prefill: Option<String>,
+    ) -> Result<Usage, Error> {
+        // The tools can't change during a turn, so they're only gathered once, and before anything
+        // is added to the history in case one of them is broken
+        let functions = self.functions()?;
+        let mut usage = Usage::default();
+        let mut nudged = false;
+        let mut failures = 0;
+        let mut stepped_back = false;
+        self.push_user(send)?;
+        loop {
+            // Don't spend anything more once the budget is gone, i.e if an earlier turn used it up
+            if let Some(limit) = self.budget_usd.filter(|limit| self.spent_usd > *limit) {
+                return Err(Error::BudgetExceeded {
+                    spent: self.spent_usd,
+                    limit,
+                });
+            }
+            let mut messages: Vec<Message> =
+                self.history.iter().map(|m| m.message.clone()).collect();
+            let prefilled = prefill.take();
+            if let Some(text) = &prefilled {
+                messages.push(Message::Assistant(vec![AssistantContent::Output(
+                    Content::Text(text.clone()),
+                )]));
+            }
+            let spinner = Spinner::start();
+            let permit = self.limiter.acquire().await.unwrap();
+            let completion = self.model.call(&messages, &functions).await;
+            drop(permit);
+            drop(spinner);
+            let mut completion = completion?;
+            // The response only has what came after the prefill, so put it back in front
+            if let Some(text) = prefilled {
+                match completion.content.first_mut() {
+                    Some(AssistantContent::Output(Content::Text(rest))) => {
+                        rest.insert_str(0, &text)
+                    }
+                    _ => completion
+                        .content
+                        .insert(0, AssistantContent::Output(Content::Text(text))),
+                }
+            }
+            for usage in [&mut usage, &mut self.usage] {
+                usage.input_tokens += completion.usage.input_tokens;
+                usage.output_tokens += completion.usage.output_tokens;
+            }
+            if let Some(pricing) = self.model.pricing() {
+                self.spent_usd += pricing.cost(&completion.usage);
+            }
+            if let Some(limit) = self.budget_usd.filter(|limit| self.spent_usd > *limit) {
+                self.stop_over_budget(completion.content)?;
+                return Err(Error::BudgetExceeded {
+                    spent: self.spent_usd,
+                    limit,
+                });
+            }
+            // Providers reject blank text in the history, and a response with nothing else in it
+            // would otherwise end the turn without a word.
+            completion.content.retain(
+                |c| !matches!(c, AssistantContent::Output(Content::Text(s)) if s.trim().is_empty()),
+            );
+            if completion.content.is_empty() {
+                if !nudged {
+                    log::warn!(
+                        "The model returned no content (stop reason: {:?}), nudging it once",
+                        completion.stop_reason
+                    );
+                    nudged = true;
+                    // Stand in for the empty response so the nudge doesn't merge into the prompt
+                    self.history
+                        .append(TimedMessage::now(Message::Assistant(vec![
+                            AssistantContent::Output(Content::Text(EMPTY_RESPONSE.to_string())),
+                        ])))?;
+                    self.push_user(vec![UserContent::Input(Content::Text(
+                        EMPTY_NUDGE.to_string(),
+                    ))])?;
+                    continue;
+                }
+                self.output.line(format!(
+                    "{}: the model returned no content (stop reason: {:?})",
+                    "err".red(),
+                    completion.stop_reason
+                ));
+                break;
+            }
+            let last = completion.content.len().saturating_sub(1);
+            let mut calls = Vec::new();
+            for (i, content) in completion.content.iter().enumerate() {
                 match content {
+                    AssistantContent::Output(Content::Text(text))
+                        if self.markdown && std::io::stdout().is_terminal() =>
+                    {
+                        self.output.line(format!(
+                            "{}: {}",
+                            "agent".green(),
+                            super::markdown::render(text)
+                        ));
+                    }
                     AssistantContent::Output(content) => {
-                        let Content::Text(s) = content;
-                        println!("{}: {}", "agent".green(), s);
+                        self.output
+                            .line(format!("{}: {}", "agent".green(), content));
+                    }
+                    AssistantContent::Reasoning { text, .. } => {
+                        if self.verbosity == Verbosity::Verbose {
+                            self.output
+                                .line(format!("{}: {}", "thinking".dimmed(), text.dimmed()));
+                        }
+                    }
+                    AssistantContent::Cited { text, citations } => {
+                        let mut out = format!("{}: {}", "agent".green(), text);
+                        for citation in citations {
+                            out.push_str(&format!("\n  {} {}", "source:".dimmed(), citation));
+                        }
+                        self.output.line(out);
                     }
 
                     AssistantContent::FunctionCall { id, name, input } => {
-                        print!("{}: {}", "tool".red(), name);
-                        let function_result = self.toolbox.call(name, input.clone());
-                        match &function_result {
-                            Ok(_) => {
-                                println!(" -> {}", "ok".green());
-                            }
-                            Err(e) => {
-                                let Content::Text(s) = e;
-                                println!(" -> {}: {}", "err".red(), s);
-                            }
-                        }
-                        let result = UserContent::FunctionResult {
-                            id: id.clone(),
-                            result: function_result,
+                        // Only the last piece of content can be cut off, but if it is, the
+                        // input is almost certainly incomplete and shouldn't be acted on.
+                        let rejection = if i == last
+                            && completion.stop_reason == StopReason::MaxTokens
+                        {
+                            Some(Content::Text(format!(
+                                "The call to '{}' was cut off because the response reached the maximum number of tokens, so its input is incomplete. Try again with a smaller input, i.e by splitting a large edit into several smaller ones.",
+                                name
+                            )))
+                        } else {
+                            self.check_call(id, name, input)
                         };
-                        send.push(result);
+                        calls.push((id, name, input, rejection));
+                    }
+                }
+            }
+            // The response is recorded before its calls run, and their results as soon as they're
+            // done, so that a failure later in the turn (i.e a rate limit on the next request)
+            // never loses work that was already done. If the agent is stopped while the calls
+            // run, `repair_history` fills in their results before the next turn.
+            self.history.append(TimedMessage::now(Message::Assistant(
+                completion.content.clone(),
+            )))?;
+            if calls.is_empty() {
+                break;
+            }
+            // Rejected calls never ran, so they say nothing about whether the model is stuck
+            let (results, failed) = self.dispatch(calls).await;
+            for failed in failed {
+                failures = if failed { failures + 1 } else { 0 };
+            }
+            self.push_user(results)?;
+            if let Some(max) = self.max_tool_failures
+                && failures >= max
+            {
+                if !stepped_back {
+                    log::warn!(
+                        "{} tool calls failed in a row, asking the model to step back",
+                        failures
+                    );
+                    stepped_back = true;
+                    failures = 0;
+                    self.push_user(vec![UserContent::Input(Content::Text(
+                        STEP_BACK.to_string(),
+                    ))])?;
+                } else {
+                    // The results are already in the history, so the model sees them when the
+                    // user answers
+                    self.output.line(format!(
+                        "{}: stopped after {} tool calls failed in a row",
+                        "err".red(),
+                        failures
+                    ));
+                    break;
+                }
+            }
+        }
+        Ok(usage)
+    }
+
+    // The tools to offer the current model.
+    fn functions(&self) -> Result<Vec<Function>, Error> {
+        self.toolbox
+            .functions(|id| self.model.supports_provider_tool(id))
+    }
+
+    // Why the call shouldn't be run, if there's anything wrong with it.
+    fn check_call(&self, id: &str, name: &str, input: &Value) -> Option<Content> {
+        if !input.is_object() {
+            return Some(Content::Text(format!(
+                "The input to '{}' must be a JSON object, but got: {}",
+                name, input
+            )));
+        }
+        if self.mode == Mode::Plan && !self.toolbox.is_read_only(name, input) {
+            return Some(Content::Text(format!("'{}' {}", name, PLAN_REJECTION)));
+        }
+        log::debug!(
+            "Calling {} ({}) with {}",
+            name,
+            id,
+            self.redactor.redact(&input.to_string())
+        );
+        None
+    }
+
+    // Run the calls that weren't rejected, returning the results of every call in order, and
+    // whether each call that ran failed.
+    async fn dispatch(
+        &self,
+        mut calls: Vec<(&String, &String, &Value, Option<Content>)>,
+    ) -> (Vec<UserContent>, Vec<bool>) {
+        self.review_batch(&mut calls);
+        // Turns with several calls are usually independent reads (i.e viewing a few files), which
+        // run at once.
+        let verbosity = self.verbosity;
+        let output = &self.output;
+        let mut results = self
+            .toolbox
+            .call_all(
+                calls
+                    .iter()
+                    .filter(|(.., rejection)| rejection.is_none())
+                    .map(|(_, name, input, _)| (name.as_str(), (*input).clone()))
+                    .collect(),
+                &self.limiter,
+                &|name, content| {
+                    if verbosity == Verbosity::Quiet {
+                        return;
                     }
+                    for line in content.to_string().lines() {
+                        output.line(format!("{} {}", format!("{} │", name).dimmed(), line));
+                    }
+                },
+            )
+            .await
+            .into_iter();
+        let mut failed = Vec::new();
+        let contents = calls
+            .into_iter()
+            .map(|(id, name, input, rejection)| {
+                let rejected = rejection.is_some();
+                let function_result = match rejection {
+                    Some(rejection) => Err(rejection),
+                    None => results.next().unwrap(),
+                };
+                if !rejected {
+                    failed.push(function_result.is_err());
+                }
+                if self.verbosity >= Verbosity::Normal {
+                    self.print_call(name, input, &function_result);
                 }
+                self.audit_call(name, input, rejected, &function_result);
+                UserContent::FunctionResult {
+                    id: id.clone(),
+                    result: function_result,
+                }
+            })
+            .collect();
+        (contents, failed)
+    }
+
+    // Show the reviewer every call that would change something at once, and reject all of them if
+    // it doesn't approve.
+    fn review_batch(&self, calls: &mut [(&String, &String, &Value, Option<Content>)]) {
+        let Some(reviewer) = &self.batch_reviewer else {
+            return;
+        };
+        let pending: Vec<usize> = (0..calls.len())
+            .filter(|&i| {
+                let (_, name, input, rejection) = &calls[i];
+                rejection.is_none() && !self.toolbox.is_read_only(name, input)
+            })
+            .collect();
+        if pending.is_empty() {
+            return;
+        }
+        let review: Vec<PendingCall> = pending
+            .iter()
+            .map(|&i| PendingCall {
+                name: calls[i].1,
+                input: calls[i].2,
+                preview: self.toolbox.preview(calls[i].1, calls[i].2),
+            })
+            .collect();
+        if let Err(feedback) = reviewer(&review) {
+            for i in pending {
+                calls[i].3 = Some(Content::Text(format!(
+                    "The user rejected this batch of {} changes, so none of them were made. Their \
+                    feedback: {}",
+                    review.len(),
+                    feedback
+                )));
             }
-            self.history.push(Message::Assistant(completion.content));
+        }
+    }
+
+    fn audit_call(
+        &self,
+        name: &str,
+        input: &Value,
+        rejected: bool,
+        result: &Result<Vec<Content>, Content>,
+    ) {
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+        let (status, bytes) = match result {
+            _ if rejected => (CallStatus::Rejected, 0),
+            Ok(content) => (CallStatus::Ok, content.iter().map(content_bytes).sum()),
+            Err(content) => (CallStatus::Error, content_bytes(content)),
+        };
+        let input = self.redactor.redact(&input.to_string());
+        if let Err(e) = audit_log.record(name, &input, status, bytes) {
+            self.output.line(format!(
+                "{}: could not write to the audit log: {}",
+                "err".red(),
+                e
+            ));
+        }
+    }
+
+    // Secrets are redacted from anything printed about the input or result, since it ends up in
+    // the terminal's scrollback.
+    fn print_call(&self, name: &str, input: &Value, result: &Result<Vec<Content>, Content>) {
+        let verbose = self.verbosity == Verbosity::Verbose;
+        let mut out = format!("{}: {}", "tool".red(), name);
+        if verbose {
+            let pretty = serde_json::to_string_pretty(input).unwrap_or_else(|_| input.to_string());
+            out.push_str(&format!("\n{}\n", self.redactor.redact(&pretty).dimmed()));
+        } else {
+            out.push_str(&format!(
+                " {}",
+                self.redactor.redact(&summarize(input)).dimmed()
+            ));
+        }
+        match result {
+            Ok(content) => {
+                out.push_str(&format!(" -> {}", "ok".green()));
+                for content in content.iter().filter(|_| verbose) {
+                    out.push_str(&format!(
+                        "\n{}",
+                        self.redactor.redact(&content.to_string()).dimmed()
+                    ));
+                }
+            }
+            Err(content) => out.push_str(&format!(
+                " -> {}: {}",
+                "err".red(),
+                self.redactor.redact(&content.to_string())
+            )),
+        }
+        self.output.line(out);
+    }
+
+    // Most providers reject consecutive user messages, so content sent right after another user
+    // message (i.e results synthesized by repair_history) is merged into it.
+    fn push_user(&mut self, mut content: Vec<UserContent>) -> Result<(), Error> {
+        let after_user = matches!(
+            self.history.iter().last(),
+            Some(TimedMessage {
+                message: Message::User(_),
+                ..
+            })
+        );
+        if !after_user {
+            return self
+                .history
+                .append(TimedMessage::now(Message::User(content)));
+        }
+        let mut history = self.history();
+        if let Some(TimedMessage {
+            message: Message::User(last),
+            ..
+        }) = history.last_mut()
+        {
+            last.append(&mut content);
+        }
+        self.history.load(history)
+    }
+
+    // Keep the model's last response, but don't act on any of its function calls. They still need
+    // results for the history to be valid, so tell the model why they were never run.
+    fn stop_over_budget(&mut self, content: Vec<AssistantContent>) -> Result<(), Error> {
+        let results: Vec<UserContent> = content
+            .iter()
+            .filter_map(|c| match c {
+                AssistantContent::FunctionCall { id, .. } => Some(UserContent::FunctionResult {
+                    id: id.clone(),
+                    result: Err(Content::Text(
+                        "This call was not run because the agent exceeded its budget.".to_string(),
+                    )),
+                }),
+                _ => None,
+            })
+            .collect();
+        self.history
+            .append(TimedMessage::now(Message::Assistant(content)))?;
+        if !results.is_empty() {
+            self.history
+                .append(TimedMessage::now(Message::User(results)))?;
         }
         Ok(())
     }
 }
+
+/// Configures an `Agent` before it's used, from `Agent::builder`. Each option is the same as the
+/// agent's setter of the same name, which can still be used to change it afterwards.
+pub struct AgentBuilder<'a, M: Model>(Agent<'a, M>);
+
+impl<'a, M: Model> AgentBuilder<'a, M> {
+    /// See `Agent::set_budget`.
+    pub fn budget_usd(mut self, budget_usd: Option<f64>) -> Self {
+        self.0.set_budget(budget_usd);
+        self
+    }
+
+    /// See `Agent::set_mode`.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.0.set_mode(mode);
+        self
+    }
+
+    /// See `Agent::set_verbosity`.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.0.set_verbosity(verbosity);
+        self
+    }
+
+    /// See `Agent::set_markdown`.
+    pub fn markdown(mut self, markdown: bool) -> Self {
+        self.0.set_markdown(markdown);
+        self
+    }
+
+    /// See `Agent::set_buffered`.
+    pub fn buffered(mut self, buffered: bool) -> Self {
+        self.0.set_buffered(buffered);
+        self
+    }
+
+    /// See `Agent::set_concurrency_limit`.
+    pub fn concurrency_limit(mut self, limit: usize) -> Self {
+        self.0.set_concurrency_limit(limit);
+        self
+    }
+
+    /// See `Agent::set_max_tool_failures`.
+    pub fn max_tool_failures(mut self, max: Option<usize>) -> Self {
+        self.0.set_max_tool_failures(max);
+        self
+    }
+
+    /// See `Agent::set_batch_reviewer`.
+    pub fn batch_reviewer(mut self, reviewer: Option<BatchReviewer<'a>>) -> Self {
+        self.0.set_batch_reviewer(reviewer);
+        self
+    }
+
+    /// See `Agent::set_redactor`.
+    pub fn redactor(mut self, redactor: Redactor) -> Self {
+        self.0.set_redactor(redactor);
+        self
+    }
+
+    /// See `Agent::set_audit_log`.
+    pub fn audit_log(mut self, audit_log: Option<AuditLog>) -> Self {
+        self.0.set_audit_log(audit_log);
+        self
+    }
+
+    /// Finish configuring the agent.
+    pub fn build(self) -> Agent<'a, M> {
+        self.0
+    }
+
+    /// Finish configuring the agent and pick up a saved conversation where it left off, as with
+    /// `Agent::resume_history`, returning the tokens used running any calls it was in the middle
+    /// of.
+    pub async fn resume(self, history: Vec<TimedMessage>) -> Result<(Agent<'a, M>, Usage), Error> {
+        let mut agent = self.0;
+        let usage = agent.resume_history(history).await?;
+        Ok((agent, usage))
+    }
+}
+
+/// The most characters of a single input field shown in a summary.
+const SUMMARY_FIELD_CHARS: usize = 40;
+
+/// A one-line summary of a tool input, i.e `command=str_replace path=src/main.rs old_str="fn…"`.
+/// The command and path come first, since they say the most about what a call does.
+fn summarize(input: &Value) -> String {
+    let Some(fields) = input.as_object() else {
+        return elide(&input.to_string());
+    };
+    let mut keys: Vec<&String> = fields.keys().collect();
+    keys.sort_by_key(|k| !matches!(k.as_str(), "command" | "path"));
+    keys.into_iter()
+        .map(|k| {
+            let value = match &fields[k] {
+                Value::String(s) if k == "command" || k == "path" => elide(s),
+                Value::String(s) => format!("{:?}", elide(s)),
+                value => elide(&value.to_string()),
+            };
+            format!("{}={}", k, value)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The first line of the text, cut short if it's too long.
+fn elide(text: &str) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    let mut short: String = line.chars().take(SUMMARY_FIELD_CHARS).collect();
+    if short.len() < text.len() {
+        short.push('…');
+    }
+    short
+}
+
+/// The function calls in the model's last message that never got results, as their ID, name and
+/// input, i.e because the session was saved before they were run. See `Agent::resume_history`.
+pub fn pending_calls(history: &[TimedMessage]) -> Vec<(&str, &str, &Value)> {
+    match history.last() {
+        Some(TimedMessage {
+            message: Message::Assistant(content),
+            ..
+        }) => content
+            .iter()
+            .filter_map(|c| match c {
+                AssistantContent::FunctionCall { id, name, input } => {
+                    Some((id.as_str(), name.as_str(), input))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn repair(history: &mut Vec<TimedMessage>) -> usize {
+    let mut repaired = 0;
+    let mut i = 0;
+    while i < history.len() {
+        let calls: Vec<String> = match &history[i].message {
+            Message::Assistant(content) => content
+                .iter()
+                .filter_map(|c| match c {
+                    AssistantContent::FunctionCall { id, .. } => Some(id.clone()),
+                    _ => None,
+                })
+                .collect(),
+            Message::User(_) => Vec::new(),
+        };
+        if calls.is_empty() {
+            i += 1;
+            continue;
+        }
+        // The results have to be in the message immediately after the calls
+        if !matches!(
+            history.get(i + 1),
+            Some(TimedMessage {
+                message: Message::User(_),
+                ..
+            })
+        ) {
+            history.insert(i + 1, TimedMessage::now(Message::User(Vec::new())));
+        }
+        if let Message::User(results) = &mut history[i + 1].message {
+            let missing: Vec<UserContent> = calls
+                .into_iter()
+                .filter(|id| {
+                    !results.iter().any(|r| {
+                        matches!(r, UserContent::FunctionResult { id: result_id, .. } if result_id == id)
+                    })
+                })
+                .map(|id| UserContent::FunctionResult {
+                    id,
+                    result: Err(Content::Text(
+                        "This call was interrupted before it could finish.".to_string(),
+                    )),
+                })
+                .collect();
+            repaired += missing.len();
+            // Results also have to come before anything else in the message
+            results.splice(0..0, missing);
+        }
+        i += 2;
+    }
+    repaired
+}
+
+/// Kept in the history in place of an empty response, which providers would reject.
+const EMPTY_RESPONSE: &str = "(empty response)";
+
+/// Sent when the model returns an empty response, to get it to either continue or explain itself.
+const EMPTY_NUDGE: &str =
+    "Your last response was empty. Please continue with the task, or explain why you can't.";
+
+const STEP_BACK: &str = "Your last several tool calls all failed. Don't retry them the same way. \
+    Step back and work out why they are failing, i.e by viewing the file again before editing it, \
+    then try a different approach or ask the user for help.";
+
+/// Where the agent prints to. Each line is flushed as soon as it's printed so that output written
+/// from several threads doesn't interleave, unless it is buffered until the end of the turn.
+#[derive(Default)]
+struct Output {
+    buffered: bool,
+    buffer: Mutex<String>,
+}
+
+impl Output {
+    fn line(&self, text: impl std::fmt::Display) {
+        if self.buffered {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.push_str(&text.to_string());
+            buffer.push('\n');
+        } else {
+            let mut stdout = io::stdout().lock();
+            let _ = writeln!(stdout, "{}", text);
+            let _ = stdout.flush();
+        }
+    }
+
+    // Print everything buffered so far, in one go.
+    fn finish(&self) {
+        let buffer = std::mem::take(&mut *self.buffer.lock().unwrap());
+        if !buffer.is_empty() {
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(buffer.as_bytes());
+            let _ = stdout.flush();
+        }
+    }
+}
+
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// A spinner shown while waiting on the model, alongside the time elapsed so far. The spinner
+/// is cleared when dropped, and does nothing if stdout isn't a terminal.
+struct Spinner(Option<JoinHandle<()>>);
+
+impl Spinner {
+    fn start() -> Self {
+        if !std::io::stdout().is_terminal() {
+            return Self(None);
+        }
+        Self(Some(tokio::spawn(async {
+            let start = Instant::now();
+            for frame in SPINNER_FRAMES.iter().cycle() {
+                print!("\r{} {:.1}s", frame.cyan(), start.elapsed().as_secs_f64());
+                let _ = std::io::stdout().flush();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })))
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.take() {
+            handle.abort();
+            print!("\r\x1b[2K");
+            let _ = std::io::stdout().flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::llm::Completion;
+    use crate::core::redact::{self, MASK};
+    use crate::core::tool::{LocalTool, ProviderTool};
+    use schemars::JsonSchema;
+    use serde_json::json;
+    use std::collections::VecDeque;
+
+    // Answers with the given responses in order, failing once they run out, and keeps every
+    // request it was sent.
+    #[derive(Default)]
+    struct Scripted {
+        responses: Mutex<VecDeque<Vec<AssistantContent>>>,
+        requests: Mutex<Vec<Vec<Message>>>,
+        provider_tools: Vec<&'static str>,
+        thinking: bool,
+    }
+
+    impl Scripted {
+        fn new(responses: Vec<Vec<AssistantContent>>) -> Self {
+            Self {
+                responses: Mutex::new(responses.into()),
+                ..Self::default()
+            }
+        }
+
+        // Report that the model can use the provider tool with the given ID.
+        fn supporting(mut self, id: &'static str) -> Self {
+            self.provider_tools.push(id);
+            self
+        }
+
+        // Act like a model that thinks first, which can't be prefilled.
+        fn thinking(mut self) -> Self {
+            self.thinking = true;
+            self
+        }
+
+        fn requests(&self) -> Vec<Vec<Message>> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl Model for Scripted {
+        async fn call(
+            &self,
+            messages: impl AsRef<[Message]>,
+            _functions: impl AsRef<[Function]>,
+        ) -> Result<Completion, Error> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push(messages.as_ref().to_vec());
+            let Some(content) = self.responses.lock().unwrap().pop_front() else {
+                return Err(Error::provider("Overloaded"));
+            };
+            Ok(Completion {
+                usage: Usage::default(),
+                content,
+                stop_reason: StopReason::EndTurn,
+            })
+        }
+
+        fn supports_provider_tool(&self, id: &str) -> bool {
+            self.provider_tools.contains(&id)
+        }
+
+        fn supports_prefill(&self) -> bool {
+            !self.thinking
+        }
+    }
+
+    struct Echo;
+
+    #[derive(Deserialize, JsonSchema)]
+    struct EchoInput {
+        text: String,
+    }
+
+    impl LocalTool for Echo {
+        type Input = EchoInput;
+
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn description(&self) -> &'static str {
+            "Echo the text back."
+        }
+
+        fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+            Ok(vec![Content::Text(input.text)])
+        }
+    }
+
+    // Echo as a provider would build it in, telling the model where the echo came from.
+    struct ProvidedEcho;
+
+    impl ProviderTool for ProvidedEcho {
+        type Input = EchoInput;
+
+        fn id(&self) -> String {
+            "echo_20250101".to_string()
+        }
+
+        fn name(&self) -> String {
+            "echo".to_string()
+        }
+
+        fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+            Ok(vec![Content::Text(format!("provided {}", input.text))])
+        }
+    }
+
+    fn call(id: &str, text: &str) -> AssistantContent {
+        AssistantContent::FunctionCall {
+            id: id.to_string(),
+            name: "echo".to_string(),
+            input: json!({ "text": text }),
+        }
+    }
+
+    fn text(text: &str) -> AssistantContent {
+        AssistantContent::Output(Content::Text(text.to_string()))
+    }
+
+    fn result_ids(message: &Message) -> Vec<String> {
+        match message {
+            Message::User(content) => content
+                .iter()
+                .filter_map(|c| match c {
+                    UserContent::FunctionResult { id, .. } => Some(id.clone()),
+                    _ => None,
+                })
+                .collect(),
+            Message::Assistant(_) => Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn no_request_is_sent_over_budget() {
+        let mut agent = Agent::new(Scripted::new(vec![vec![text("hi")]]), Toolbox::new());
+        agent.set_budget(Some(1.0));
+        agent.spent_usd = 1.5;
+        let error = agent.go("hello".to_string()).await.unwrap_err();
+        assert!(matches!(error, Error::BudgetExceeded { .. }));
+        assert_eq!(
+            error.to_string(),
+            "Stopped after spending $1.50, over the budget of $1.00"
+        );
+        assert!(agent.model.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn empty_responses_are_nudged_in_a_new_message() {
+        let model = Scripted::new(vec![vec![text(" ")], vec![text("done")]]);
+        let mut agent = Agent::new(model, Toolbox::new());
+        agent.go("hello".to_string()).await.unwrap();
+
+        let requests = agent.model.requests();
+        assert_eq!(requests.len(), 2);
+        let json = serde_json::to_value(&requests[1]).unwrap();
+        assert_eq!(
+            json,
+            json!([
+                { "User": [{ "Input": { "Text": "hello" } }] },
+                { "Assistant": [{ "Output": { "Text": EMPTY_RESPONSE } }] },
+                { "User": [{ "Input": { "Text": EMPTY_NUDGE } }] },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn prefills_are_sent_last_and_kept_in_the_reply() {
+        let model = Scripted::new(vec![vec![text("\"done\": true}")]]);
+        let mut agent = Agent::new(model, Toolbox::new());
+        agent
+            .go_with_prefill("hello".to_string(), "{".to_string())
+            .await
+            .unwrap();
+
+        let json = serde_json::to_value(&agent.model.requests()[0]).unwrap();
+        assert_eq!(
+            json,
+            json!([
+                { "User": [{ "Input": { "Text": "hello" } }] },
+                { "Assistant": [{ "Output": { "Text": "{" } }] },
+            ])
+        );
+        let history = agent.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            serde_json::to_value(&history[1].message).unwrap(),
+            json!({ "Assistant": [{ "Output": { "Text": "{\"done\": true}" } }] })
+        );
+    }
+
+    #[tokio::test]
+    async fn prefills_are_rejected_before_sending() {
+        let mut agent = Agent::new(Scripted::new(vec![vec![text("}")]]), Toolbox::new());
+        for prefill in ["", "{ "] {
+            let result = agent
+                .go_with_prefill("hello".to_string(), prefill.to_string())
+                .await;
+            assert!(result.is_err());
+        }
+        agent.set_model(Scripted::new(vec![vec![text("}")]]).thinking());
+        let result = agent
+            .go_with_prefill("hello".to_string(), "{".to_string())
+            .await;
+        assert!(result.is_err());
+        assert!(agent.model.requests().is_empty());
+        assert!(agent.history().is_empty());
+    }
+
+    #[test]
+    fn failed_calls_are_printed_redacted() {
+        let mut agent = Agent::new(Scripted::default(), Toolbox::new());
+        agent.output.buffered = true;
+        let error = Content::Text("curl: (22) 401 for key sk-ant-api03-abcdefghijkl".to_string());
+        agent.print_call("bash", &json!({ "command": "curl" }), &Err(error));
+        let printed = agent.output.buffer.lock().unwrap().clone();
+        assert!(printed.contains(MASK), "{}", printed);
+        assert!(!printed.contains("abcdefghijkl"), "{}", printed);
+    }
+
+    #[test]
+    fn exports_are_redacted() {
+        let mut agent = Agent::builder(Scripted::default(), Toolbox::new())
+            .redactor(Redactor::default().pattern(redact::literal("hunter2")))
+            .build();
+        agent
+            .set_history(vec![user(
+                "my key is sk-ant-api03-abcdefghijkl and my password is hunter2",
+            )])
+            .unwrap();
+        let exported = agent.export_markdown();
+        assert!(exported.contains(MASK), "{}", exported);
+        assert!(!exported.contains("abcdefghijkl"), "{}", exported);
+        assert!(!exported.contains("hunter2"), "{}", exported);
+    }
+
+    #[tokio::test]
+    async fn audited_inputs_are_redacted() {
+        let path = std::env::temp_dir().join(format!("asimov-audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let responses = vec![
+            vec![call("1", "sk-ant-api03-abcdefghijkl")],
+            vec![text("done")],
+        ];
+        let mut agent = Agent::builder(Scripted::new(responses), Toolbox::new().local(Echo))
+            .audit_log(Some(AuditLog::open(&path).unwrap()))
+            .verbosity(Verbosity::Quiet)
+            .build();
+        agent.go("hello".to_string()).await.unwrap();
+        let logged = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            logged.contains("echo") && logged.contains(MASK),
+            "{}",
+            logged
+        );
+        assert!(!logged.contains("abcdefghijkl"), "{}", logged);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn sent_step_back(requests: &[Vec<Message>]) -> bool {
+        serde_json::to_string(requests).unwrap().contains(STEP_BACK)
+    }
+
+    #[tokio::test]
+    async fn only_calls_that_ran_count_as_failures() {
+        // Rejected while planning, so the calls never ran
+        let responses = vec![
+            vec![call("1", "a")],
+            vec![call("2", "b")],
+            vec![text("done")],
+        ];
+        let mut agent = Agent::new(Scripted::new(responses), Toolbox::new().local(Echo));
+        agent.set_max_tool_failures(Some(2));
+        agent.set_mode(Mode::Plan);
+        agent.go("hello".to_string()).await.unwrap();
+        assert!(!sent_step_back(&agent.model.requests()));
+
+        let bad = || AssistantContent::FunctionCall {
+            id: "1".to_string(),
+            name: "echo".to_string(),
+            input: json!({ "wrong": true }),
+        };
+        let responses = vec![vec![bad()], vec![bad()], vec![text("done")]];
+        let mut agent = Agent::new(Scripted::new(responses), Toolbox::new().local(Echo));
+        agent.set_max_tool_failures(Some(2));
+        agent.go("hello".to_string()).await.unwrap();
+        assert!(sent_step_back(&agent.model.requests()));
+    }
+
+    #[tokio::test]
+    async fn failing_after_stepping_back_ends_the_turn() {
+        let bad = |id: &str| AssistantContent::FunctionCall {
+            id: id.to_string(),
+            name: "echo".to_string(),
+            input: json!({ "wrong": true }),
+        };
+        let responses = vec![
+            vec![bad("1")],
+            vec![bad("2")],
+            vec![bad("3")],
+            vec![bad("4")],
+            vec![text("never sent")],
+        ];
+        let mut agent = Agent::new(Scripted::new(responses), Toolbox::new().local(Echo));
+        agent.set_max_tool_failures(Some(2));
+        agent.go("hello".to_string()).await.unwrap();
+
+        // Two failures get the model to step back, and two more stop the turn before it's called
+        // again
+        let requests = agent.model.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(sent_step_back(&requests[2..3]));
+        assert!(!sent_step_back(&requests[..2]));
+        let history = agent.history();
+        assert_eq!(result_ids(&history.last().unwrap().message), ["4"]);
+    }
+
+    fn user(text: &str) -> TimedMessage {
+        TimedMessage::now(Message::User(vec![UserContent::Input(Content::Text(
+            text.to_string(),
+        ))]))
+    }
+
+    #[test]
+    fn repair_answers_interrupted_calls_first() {
+        let mut agent = Agent::new(Scripted::default(), Toolbox::new());
+        let answered = UserContent::FunctionResult {
+            id: "2".to_string(),
+            result: Ok(vec![Content::Text("b".to_string())]),
+        };
+        agent
+            .set_history(vec![
+                user("hi"),
+                TimedMessage::now(Message::Assistant(vec![call("1", "a"), call("2", "b")])),
+                TimedMessage::now(Message::User(vec![answered])),
+                TimedMessage::now(Message::Assistant(vec![call("3", "c")])),
+            ])
+            .unwrap();
+
+        let history: Vec<Message> = agent.history().into_iter().map(|m| m.message).collect();
+        assert_eq!(history.len(), 5);
+        assert_eq!(result_ids(&history[2]), ["1", "2"]);
+        assert_eq!(result_ids(&history[4]), ["3"]);
+        let Message::User(results) = &history[2] else {
+            panic!("expected the results in a user message");
+        };
+        assert!(matches!(
+            results[0],
+            UserContent::FunctionResult { result: Err(_), .. }
+        ));
+        assert_eq!(agent.repair_history().unwrap(), 0);
+    }
+
+    // The text of the first result in the last request the model was sent.
+    fn last_result(model: &Scripted) -> String {
+        let requests = model.requests();
+        let Some(Message::User(content)) = requests.last().and_then(|r| r.last()) else {
+            panic!("expected the results in a user message");
+        };
+        match &content[0] {
+            UserContent::FunctionResult {
+                result: Ok(content),
+                ..
+            } => content[0].to_string(),
+            other => panic!("expected a successful result, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn models_get_the_provider_tool_only_if_they_support_it() {
+        let responses = || vec![vec![call("1", "hi")], vec![text("done")]];
+        let toolbox = || Toolbox::new().provided_or_local(ProvidedEcho, Echo);
+
+        let model = Scripted::new(responses()).supporting("echo_20250101");
+        let mut agent = Agent::new(model, toolbox());
+        agent.go("hello".to_string()).await.unwrap();
+        assert_eq!(last_result(&agent.model), "provided hi");
+
+        let mut agent = Agent::new(Scripted::new(responses()), toolbox());
+        agent.go("hello".to_string()).await.unwrap();
+        assert_eq!(last_result(&agent.model), "hi");
+    }
+
+    #[tokio::test]
+    async fn results_are_kept_when_the_next_request_fails() {
+        let mut agent = Agent::new(
+            Scripted::new(vec![vec![call("1", "a")]]),
+            Toolbox::new().local(Echo),
+        );
+        assert!(agent.go("hello".to_string()).await.is_err());
+
+        let history: Vec<Message> = agent.history().into_iter().map(|m| m.message).collect();
+        assert_eq!(history.len(), 3);
+        assert!(matches!(&history[1], Message::Assistant(content) if content.len() == 1));
+        assert_eq!(result_ids(&history[2]), ["1"]);
+    }
+
+    #[tokio::test]
+    async fn resuming_sends_one_result_per_pending_call() {
+        let model = Scripted::new(vec![vec![text("done")]]);
+        let mut agent = Agent::new(model, Toolbox::new().local(Echo));
+        let history = vec![
+            TimedMessage::now(Message::User(vec![UserContent::Input(Content::Text(
+                "hi".to_string(),
+            ))])),
+            TimedMessage::now(Message::Assistant(vec![call("1", "a"), call("2", "b")])),
+        ];
+        agent.resume_history(history).await.unwrap();
+
+        let requests = agent.model.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].len(), 3);
+        assert_eq!(result_ids(&requests[0][2]), ["1", "2"]);
+        let Message::User(results) = &requests[0][2] else {
+            panic!("expected the results in a user message");
+        };
+        assert!(
+            results
+                .iter()
+                .all(|r| matches!(r, UserContent::FunctionResult { result: Ok(_), .. }))
+        );
+    }
+
+    #[tokio::test]
+    async fn built_agents_resume_with_their_configuration() {
+        let history = vec![
+            user("hi"),
+            TimedMessage::now(Message::Assistant(vec![call("1", "a")])),
+        ];
+        let (agent, _) = Agent::builder(
+            Scripted::new(vec![vec![text("done")]]),
+            Toolbox::new().local(Echo),
+        )
+        .mode(Mode::Plan)
+        .verbosity(Verbosity::Quiet)
+        .resume(history)
+        .await
+        .unwrap();
+        assert_eq!(agent.mode(), Mode::Plan);
+
+        // The pending call is run as planning allows, so it's refused rather than echoed
+        let requests = agent.model.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(result_ids(&requests[0][2]), ["1"]);
+        assert!(matches!(
+            &requests[0][2],
+            Message::User(results)
+                if matches!(&results[0], UserContent::FunctionResult { result: Err(_), .. })
+        ));
+        assert_eq!(agent.history().len(), 4);
+    }
+}
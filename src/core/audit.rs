@@ -0,0 +1,85 @@
+use super::Error;
+use super::time::format_utc;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A durable record of every tool call the agent makes, with one JSON object per line.
+///
+/// Unlike the debug log, this is meant to be kept and reviewed after the fact, so it is only ever
+/// appended to, and every line has the same shape. Records from several runs can share a file,
+/// since each is tagged with the session it came from.
+pub struct AuditLog {
+    file: Mutex<File>,
+    session: String,
+}
+
+/// What came of a tool call.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CallStatus {
+    /// The tool ran and succeeded.
+    Ok,
+    /// The tool ran and failed.
+    Error,
+    /// The call was never run, i.e because its input was malformed or the agent was planning.
+    Rejected,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    session: &'a str,
+    tool: &'a str,
+    input: Value,
+    status: CallStatus,
+    output_bytes: u64,
+}
+
+impl AuditLog {
+    /// Open the log at the given path, creating it if it doesn't exist yet. The session is
+    /// identified by when it started and the ID of the process.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(Self {
+            file: Mutex::new(file),
+            session: format!("{}-{}", started, std::process::id()),
+        })
+    }
+
+    /// Record a call to the tool with the given name. The input should already have any secrets
+    /// redacted from it.
+    pub fn record(
+        &self,
+        tool: &str,
+        input: &str,
+        status: CallStatus,
+        output_bytes: u64,
+    ) -> Result<(), Error> {
+        let record = Record {
+            time: format_utc(SystemTime::now()),
+            session: &self.session,
+            tool,
+            // Redaction keeps the input valid JSON unless a secret spanned its syntax
+            input: serde_json::from_str(input).unwrap_or_else(|_| Value::String(input.to_string())),
+            status,
+            output_bytes,
+        };
+        let line = serde_json::to_string(&record)?;
+        // Each record is written in one go, so that lines from parallel calls never interleave
+        writeln!(self.file.lock().unwrap(), "{}", line)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use super::llm::{AssistantContent, Message, TimedMessage, UserContent};
+use super::time::format_utc;
+use super::tool::ToolStats;
+
+/// Render a conversation as human-readable Markdown, i.e for sharing or record-keeping.
+///
+/// Each message gets its own heading (with a timestamp if known), with tool calls and their
+/// results rendered in code blocks. This is intended for people to read, and can't be loaded back
+/// into an agent.
+pub fn markdown(history: &[TimedMessage]) -> String {
+    let mut out = String::from("# Conversation\n");
+    for TimedMessage { at, message } in history {
+        let at = at
+            .map(|at| format!(" ({})", format_utc(at)))
+            .unwrap_or_default();
+        match message {
+            Message::User(content) => {
+                out.push_str(&format!("\n## User{}\n", at));
+                for content in content {
+                    match content {
+                        UserContent::Input(content) => {
+                            out.push_str(&format!("\n{}\n", content.to_string().trim_end()));
+                        }
+                        UserContent::FunctionResult { id, result } => {
+                            let (status, content) = match result {
+                                Ok(content) => ("ok", content.as_slice()),
+                                Err(content) => ("error", std::slice::from_ref(content)),
+                            };
+                            out.push_str(&format!("\n**Tool result** `{}` ({})\n", id, status));
+                            for content in content {
+                                out.push_str(&code_block("", &content.to_string()));
+                            }
+                        }
+                    }
+                }
+            }
+            Message::Assistant(content) => {
+                out.push_str(&format!("\n## Assistant{}\n", at));
+                for content in content {
+                    match content {
+                        AssistantContent::Output(content) => {
+                            out.push_str(&format!("\n{}\n", content.to_string().trim_end()));
+                        }
+                        AssistantContent::FunctionCall { id, name, input } => {
+                            let input = serde_json::to_string_pretty(input)
+                                .unwrap_or_else(|_| input.to_string());
+                            out.push_str(&format!("\n**Tool call** `{}` `{}`\n", name, id));
+                            out.push_str(&code_block("json", &input));
+                        }
+                        AssistantContent::Reasoning { text, redacted, .. } => {
+                            let text = if *redacted {
+                                "(redacted)"
+                            } else {
+                                text.trim_end()
+                            };
+                            out.push_str(&format!("\n**Reasoning**\n{}\n", quote(text)));
+                        }
+                        AssistantContent::Cited { text, citations } => {
+                            out.push_str(&format!("\n{}\n", text.trim_end()));
+                            out.push_str("\n**Sources**\n");
+                            for citation in citations {
+                                let cited = quote(citation.cited_text.trim()).replace('\n', "\n  ");
+                                out.push_str(&format!("- {}\n  {}\n", citation, cited));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Render how tools were used as a Markdown table, to go at the end of an exported conversation.
+/// Tools that were never called are left out, and with none called this is empty.
+pub fn tool_stats(stats: &[(String, ToolStats)]) -> String {
+    let used: Vec<_> = stats.iter().filter(|(_, s)| s.calls() > 0).collect();
+    if used.is_empty() {
+        return String::new();
+    }
+    let mut out =
+        String::from("\n## Tool usage\n\n| Tool | Calls | Errors | Bytes |\n|---|---|---|---|\n");
+    for (name, stats) in used {
+        out.push_str(&format!(
+            "| `{}` | {} | {} | {} |\n",
+            name,
+            stats.calls(),
+            stats.errors,
+            stats.bytes
+        ));
+    }
+    out
+}
+
+// Tool inputs and outputs may well contain code blocks of their own, so the fence has to be
+// longer than any run of backticks in the text.
+fn code_block(lang: &str, text: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("\n{}{}\n{}\n{}\n", fence, lang, text.trim_end(), fence)
+}
+
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("> {}", line).trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,191 @@
+use super::Error;
+use super::llm::{BoxedModel, Completion, Function, Message, Model, Pricing};
+use super::tool::BoxedProviderTool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A model that tries an ordered list of models in turn, moving on to the next one when a model
+/// fails with a retryable error, such as being overloaded or rate-limited. The same messages and
+/// functions are sent to every model in the chain, and the error of the last model is returned if
+/// they all fail.
+pub struct Fallback<'a> {
+    models: Vec<(String, BoxedModel<'a>)>,
+    last: AtomicUsize,
+}
+
+impl<'a> Fallback<'a> {
+    /// Create a fallback chain that tries the given model first, with a name used when logging
+    /// that the chain fell back from it.
+    pub fn new(name: impl Into<String>, model: impl Model + 'a) -> Self {
+        Self {
+            models: vec![(name.into(), BoxedModel::new(model))],
+            last: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a model to try after the ones already in the chain.
+    pub fn then(mut self, name: impl Into<String>, model: impl Model + 'a) -> Self {
+        self.models.push((name.into(), BoxedModel::new(model)));
+        self
+    }
+}
+
+impl Model for Fallback<'_> {
+    async fn call(
+        &self,
+        messages: impl AsRef<[Message]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<Completion, Error> {
+        let messages = messages.as_ref();
+        let functions = functions.as_ref();
+        let mut index = 0;
+        loop {
+            let (name, model) = &self.models[index];
+            self.last.store(index, Ordering::Relaxed);
+            match model.call(messages, functions).await {
+                Err(e) if e.is_retryable() && index + 1 < self.models.len() => {
+                    log::warn!(
+                        "{} failed, falling back to {}: {}",
+                        name,
+                        self.models[index + 1].0,
+                        e
+                    );
+                    index += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn count_tokens(
+        &self,
+        messages: impl AsRef<[Message]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<Option<u32>, Error> {
+        self.models[0].1.count_tokens(messages, functions).await
+    }
+
+    // This is asked for right after a call to work out its cost, so use whichever model answered.
+    fn pricing(&self) -> Option<Pricing> {
+        self.models[self.last.load(Ordering::Relaxed)].1.pricing()
+    }
+
+    // Any model could end up answering, so the largest limit is the one that can be relied on.
+    fn max_tokens(&self) -> Option<u32> {
+        self.models
+            .iter()
+            .filter_map(|(_, model)| model.max_tokens())
+            .max()
+    }
+
+    // Provider tools are versioned per model, so there's no way to offer ones that every model in
+    // the chain supports. The first model's are used, and only offered if every model supports
+    // them (see `supports_provider_tool`).
+    fn provider_tools(&self) -> Vec<BoxedProviderTool<'static>> {
+        self.models[0].1.provider_tools()
+    }
+
+    // Any model could end up answering, so they all have to be able to use the tool.
+    fn supports_provider_tool(&self, id: &str) -> bool {
+        self.models
+            .iter()
+            .all(|(_, model)| model.supports_provider_tool(id))
+    }
+
+    fn supports_prefill(&self) -> bool {
+        self.models
+            .iter()
+            .all(|(_, model)| model.supports_prefill())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ProviderErrorKind;
+    use crate::core::llm::{StopReason, Usage};
+    use std::sync::Arc;
+
+    // Fails with the given kind of error, or answers if there is none, counting its calls.
+    struct Canned {
+        error: Option<ProviderErrorKind>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Canned {
+        fn new(error: Option<ProviderErrorKind>) -> (Self, Arc<AtomicUsize>) {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let model = Self {
+                error,
+                calls: calls.clone(),
+            };
+            (model, calls)
+        }
+    }
+
+    impl Model for Canned {
+        async fn call(
+            &self,
+            _messages: impl AsRef<[Message]>,
+            _functions: impl AsRef<[Function]>,
+        ) -> Result<Completion, Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            match self.error {
+                Some(kind) => Err(Error::Provider {
+                    kind,
+                    message: format!("{:?}", kind),
+                }),
+                None => Ok(Completion {
+                    usage: Usage::default(),
+                    content: Vec::new(),
+                    stop_reason: StopReason::EndTurn,
+                }),
+            }
+        }
+
+        fn pricing(&self) -> Option<Pricing> {
+            self.error.is_none().then_some(Pricing {
+                input: 1.0,
+                output: 2.0,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn moves_on_after_retryable_errors() {
+        let (overloaded, first) = Canned::new(Some(ProviderErrorKind::Overloaded));
+        let (limited, second) = Canned::new(Some(ProviderErrorKind::RateLimit));
+        let (answering, third) = Canned::new(None);
+        let chain = Fallback::new("overloaded", overloaded)
+            .then("limited", limited)
+            .then("answering", answering);
+        assert!(chain.call(Vec::new(), Vec::new()).await.is_ok());
+        for calls in [first, second, third] {
+            assert_eq!(calls.load(Ordering::Relaxed), 1);
+        }
+        // The cost is worked out with the pricing of the model that answered
+        assert_eq!(chain.pricing().map(|p| p.output), Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_returned_right_away() {
+        let (invalid, _) = Canned::new(Some(ProviderErrorKind::InvalidRequest));
+        let (answering, calls) = Canned::new(None);
+        let chain = Fallback::new("invalid", invalid).then("answering", answering);
+        let error = chain.call(Vec::new(), Vec::new()).await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Provider {
+                kind: ProviderErrorKind::InvalidRequest,
+                ..
+            }
+        ));
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+
+        let (overloaded, _) = Canned::new(Some(ProviderErrorKind::Overloaded));
+        let error = Fallback::new("overloaded", overloaded)
+            .call(Vec::new(), Vec::new())
+            .await
+            .unwrap_err();
+        assert!(error.is_retryable());
+    }
+}
@@ -0,0 +1,118 @@
+use super::Error;
+use super::llm::TimedMessage;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Where an agent keeps its conversation history.
+///
+/// The agent only ever adds messages to the end of the history, except when it has to rewrite it
+/// (i.e to repair interrupted function calls), in which case the whole history is replaced. This
+/// keeps stores simple to implement on top of databases or files, which can persist sessions
+/// across restarts or share them between processes.
+pub trait HistoryStore {
+    /// Add a message to the end of the history.
+    fn append(&mut self, message: TimedMessage) -> Result<(), Error>;
+    /// Replace the whole history with the given messages.
+    fn load(&mut self, history: Vec<TimedMessage>) -> Result<(), Error>;
+    /// Remove every message from the history.
+    fn clear(&mut self) -> Result<(), Error>;
+    /// The messages in the history, oldest first.
+    fn iter(&self) -> Box<dyn Iterator<Item = &TimedMessage> + '_>;
+}
+
+/// A history kept in memory, which is lost when the agent is dropped. This is the default.
+#[derive(Default)]
+pub struct InMemoryHistory(Vec<TimedMessage>);
+
+impl HistoryStore for InMemoryHistory {
+    fn append(&mut self, message: TimedMessage) -> Result<(), Error> {
+        self.0.push(message);
+        Ok(())
+    }
+
+    fn load(&mut self, history: Vec<TimedMessage>) -> Result<(), Error> {
+        self.0 = history;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.0.clear();
+        Ok(())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &TimedMessage> + '_> {
+        Box::new(self.0.iter())
+    }
+}
+
+/// A history backed by a file with one JSON message per line, so that a session survives
+/// restarts. Messages are also kept in memory, and new ones are appended to the file as they come.
+pub struct FileHistory {
+    path: PathBuf,
+    messages: Vec<TimedMessage>,
+}
+
+impl FileHistory {
+    /// Open the history stored at the given path, starting an empty one if it doesn't exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let messages = match fs::read_to_string(&path) {
+            Ok(lines) => lines
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, messages })
+    }
+
+    // Like the memory tool, write to a temporary file first and then rename it over the real one,
+    // so that an interrupted rewrite never loses the whole session.
+    fn rewrite(&self) -> Result<(), Error> {
+        let mut out = String::new();
+        for message in &self.messages {
+            out.push_str(&serde_json::to_string(message)?);
+            out.push('\n');
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl HistoryStore for FileHistory {
+    fn append(&mut self, message: TimedMessage) -> Result<(), Error> {
+        let line = serde_json::to_string(&message)?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        self.messages.push(message);
+        Ok(())
+    }
+
+    fn load(&mut self, history: Vec<TimedMessage>) -> Result<(), Error> {
+        self.messages = history;
+        self.rewrite()
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.messages.clear();
+        self.rewrite()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &TimedMessage> + '_> {
+        Box::new(self.messages.iter())
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// How far back requests count against the limit.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// A requests-per-minute budget shared by everything holding a clone of it, i.e every model of
+/// several agents that run at once against the same API key.
+///
+/// The limit is kept over a sliding minute, so a burst can use the whole budget at once, but the
+/// next request then waits until the first of the burst is a minute old. Waiters are let through
+/// in the order they arrived.
+#[derive(Clone)]
+pub struct SharedLimiter {
+    per_minute: usize,
+    sent: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl SharedLimiter {
+    /// Allow the given number of requests a minute, which is at least one.
+    pub fn per_minute(requests: usize) -> Self {
+        Self {
+            per_minute: requests.max(1),
+            sent: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Wait until another request fits in the budget, and count it.
+    pub async fn acquire(&self) {
+        // The lock is held while waiting, which is what keeps waiters in order
+        let mut sent = self.sent.lock().await;
+        loop {
+            let now = Instant::now();
+            while sent
+                .front()
+                .is_some_and(|t| now.duration_since(*t) >= WINDOW)
+            {
+                sent.pop_front();
+            }
+            if sent.len() < self.per_minute {
+                sent.push_back(now);
+                return;
+            }
+            let oldest = *sent.front().unwrap();
+            log::debug!(
+                "At the limit of {} requests a minute, waiting {:?}",
+                self.per_minute,
+                (oldest + WINDOW).duration_since(now)
+            );
+            tokio::time::sleep_until(oldest + WINDOW).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_out_the_window() {
+        let limiter = SharedLimiter::per_minute(2);
+        let start = Instant::now();
+        limiter.acquire().await;
+        tokio::time::advance(Duration::from_secs(10)).await;
+        limiter.clone().acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+
+        // Each request waits for the one that's a minute older to leave the window
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), WINDOW);
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), WINDOW + Duration::from_secs(10));
+    }
+}
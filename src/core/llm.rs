@@ -1,14 +1,37 @@
+use super::Error;
+use super::tool::BoxedProviderTool;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::pin::Pin;
+use std::time::SystemTime;
 
 /// A provider of LLM models.
 pub trait Provider<T> {
     /// Obtain a new model from the provider with the provided system prompt and hyperparams.
+    ///
+    /// The system prompt is an ordered collection of sections (i.e a shared base prompt followed by
+    /// project-specific instructions), which providers may send separately or join together. A
+    /// single prompt can be given with `Some(prompt)`, and no prompt at all with `None`.
     async fn obtain(
         &self,
         model: T,
-        system_prompt: Option<impl AsRef<str>>,
+        system_prompt: impl IntoIterator<Item = impl AsRef<str>>,
         hyperparams: Hyperparams,
     ) -> impl Model;
+
+    /// List the models the provider currently offers. Providers with an endpoint for this ask it,
+    /// so the list can include models newer than this crate, while the rest return the models
+    /// they are known to support.
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, Error>;
+}
+
+/// A model offered by a provider.
+#[derive(Debug, Clone)]
+pub struct ModelInfo {
+    /// The ID of the model, as used in API requests.
+    pub id: String,
+    /// A human-readable name for the model, if the provider gives one.
+    pub display_name: Option<String>,
 }
 
 /// Hyperparameters for an LLM.
@@ -18,6 +41,58 @@ pub struct Hyperparams {
     pub max_tokens: u32,
     /// The temperature to use for the model.
     pub temperature: f64,
+    /// The seed to sample with, for providers that support one.
+    pub seed: Option<u64>,
+    /// How hard the model should think before answering, for models that can reason. Each
+    /// provider translates this to its own mechanism, see `ReasoningEffort`. `None` disables
+    /// reasoning.
+    pub reasoning_effort: Option<ReasoningEffort>,
+}
+
+/// A provider-agnostic amount of reasoning for a model to do before answering.
+///
+/// For Anthropic, this enables extended thinking with a `budget_tokens` of 1024, 4096 or 16384
+/// tokens, which are added on top of `max_tokens`. Thinking requires a temperature of 1, so the
+/// temperature is ignored while it is enabled, and models without thinking ignore this entirely.
+/// Gemini's supported models can't reason, so it ignores this too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl std::str::FromStr for ReasoningEffort {
+    type Err = String;
+
+    /// Parse a reasoning effort from `low`, `medium` or `high`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(ReasoningEffort::Low),
+            "medium" => Ok(ReasoningEffort::Medium),
+            "high" => Ok(ReasoningEffort::High),
+            _ => Err(format!(
+                "Unknown reasoning effort '{}'. Expected low, medium or high",
+                s
+            )),
+        }
+    }
+}
+
+impl Hyperparams {
+    /// Hyperparameters that make the model's output as reproducible as possible, i.e for snapshot
+    /// tests against a replayed session. The temperature is zero and a fixed seed is passed to
+    /// providers that accept one. Note that this doesn't guarantee identical output: Anthropic in
+    /// particular has no seed, and only promises much less variance at temperature zero.
+    pub fn deterministic(max_tokens: u32) -> Self {
+        Self {
+            max_tokens,
+            temperature: 0.0,
+            seed: Some(0),
+            reasoning_effort: None,
+        }
+    }
 }
 
 /// A LLM model.
@@ -28,10 +103,189 @@ pub trait Model {
         messages: impl AsRef<[Message]>,
         functions: impl AsRef<[Function]>,
     ) -> Result<Completion, super::Error>;
+
+    /// Count the input tokens that calling the model with the provided messages and functions
+    /// would use, without generating anything. Returns `None` if the provider can't count tokens.
+    async fn count_tokens(
+        &self,
+        _messages: impl AsRef<[Message]>,
+        _functions: impl AsRef<[Function]>,
+    ) -> Result<Option<u32>, super::Error> {
+        Ok(None)
+    }
+
+    /// The price of using the model, if known.
+    fn pricing(&self) -> Option<Pricing> {
+        None
+    }
+
+    /// The most tokens a single response may use, including any reasoning, if known.
+    fn max_tokens(&self) -> Option<u32> {
+        None
+    }
+
+    /// The tools built into the model's provider that this model supports, i.e Anthropic's
+    /// editor. These can all be added to a toolbox with `Toolbox::provided_all`.
+    fn provider_tools(&self) -> Vec<BoxedProviderTool<'static>> {
+        Vec::new()
+    }
+
+    /// Whether the model can use the provider tool with the given ID (see `ProviderTool::id`). Tools
+    /// registered with a local fallback use the fallback when this is false.
+    fn supports_provider_tool(&self, _id: &str) -> bool {
+        false
+    }
+
+    /// Whether the model's response can be prefilled (see `Agent::go_with_prefill`). Anthropic
+    /// rejects prefills while extended thinking is on.
+    fn supports_prefill(&self) -> bool {
+        true
+    }
+}
+
+/// The price of using a model, in US dollars per million tokens.
+#[derive(Debug, Clone, Copy)]
+pub struct Pricing {
+    /// The price of a million input tokens.
+    pub input: f64,
+    /// The price of a million output tokens.
+    pub output: f64,
+}
+
+impl Pricing {
+    /// The cost of the given usage in US dollars.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input + usage.output_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// A type-erased model that can be chosen at runtime.
+///
+/// `Model` uses `async fn` and `impl Trait` arguments, so it can't be used as a trait object.
+/// This wrapper allows an `Agent` to be created with a dynamically chosen model (or have its model
+/// swapped out later) at the cost of boxing every call. Statically known models should continue
+/// to be used directly.
+pub struct BoxedModel<'a>(Box<dyn DynModel + 'a>);
+
+impl<'a> BoxedModel<'a> {
+    /// Erase the type of the given model.
+    pub fn new(model: impl Model + 'a) -> Self {
+        Self(Box::new(model))
+    }
+}
+
+impl Model for BoxedModel<'_> {
+    async fn call(
+        &self,
+        messages: impl AsRef<[Message]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<Completion, super::Error> {
+        self.0
+            .call_boxed(messages.as_ref(), functions.as_ref())
+            .await
+    }
+
+    async fn count_tokens(
+        &self,
+        messages: impl AsRef<[Message]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<Option<u32>, super::Error> {
+        self.0
+            .count_tokens_boxed(messages.as_ref(), functions.as_ref())
+            .await
+    }
+
+    fn pricing(&self) -> Option<Pricing> {
+        self.0.pricing_boxed()
+    }
+
+    fn max_tokens(&self) -> Option<u32> {
+        self.0.max_tokens_boxed()
+    }
+
+    fn provider_tools(&self) -> Vec<BoxedProviderTool<'static>> {
+        self.0.provider_tools_boxed()
+    }
+
+    fn supports_provider_tool(&self, id: &str) -> bool {
+        self.0.supports_provider_tool_boxed(id)
+    }
+
+    fn supports_prefill(&self) -> bool {
+        self.0.supports_prefill_boxed()
+    }
+}
+
+// Much like the tool wrappers, this is a dyn-compatible version of the model trait that takes
+// plain slices and returns a boxed future.
+
+type BoxedFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, super::Error>> + 'a>>;
+
+trait DynModel {
+    fn call_boxed<'a>(
+        &'a self,
+        messages: &'a [Message],
+        functions: &'a [Function],
+    ) -> BoxedFuture<'a, Completion>;
+
+    fn count_tokens_boxed<'a>(
+        &'a self,
+        messages: &'a [Message],
+        functions: &'a [Function],
+    ) -> BoxedFuture<'a, Option<u32>>;
+
+    fn pricing_boxed(&self) -> Option<Pricing>;
+
+    fn max_tokens_boxed(&self) -> Option<u32>;
+
+    fn provider_tools_boxed(&self) -> Vec<BoxedProviderTool<'static>>;
+
+    fn supports_provider_tool_boxed(&self, id: &str) -> bool;
+
+    fn supports_prefill_boxed(&self) -> bool;
+}
+
+impl<M: Model> DynModel for M {
+    fn call_boxed<'a>(
+        &'a self,
+        messages: &'a [Message],
+        functions: &'a [Function],
+    ) -> BoxedFuture<'a, Completion> {
+        Box::pin(self.call(messages, functions))
+    }
+
+    fn count_tokens_boxed<'a>(
+        &'a self,
+        messages: &'a [Message],
+        functions: &'a [Function],
+    ) -> BoxedFuture<'a, Option<u32>> {
+        Box::pin(self.count_tokens(messages, functions))
+    }
+
+    fn pricing_boxed(&self) -> Option<Pricing> {
+        self.pricing()
+    }
+
+    fn max_tokens_boxed(&self) -> Option<u32> {
+        self.max_tokens()
+    }
+
+    fn provider_tools_boxed(&self) -> Vec<BoxedProviderTool<'static>> {
+        self.provider_tools()
+    }
+
+    fn supports_provider_tool_boxed(&self, id: &str) -> bool {
+        self.supports_provider_tool(id)
+    }
+
+    fn supports_prefill_boxed(&self) -> bool {
+        self.supports_prefill()
+    }
 }
 
 /// A message to the LLM.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     /// A user message.
     User(Vec<UserContent>),
@@ -39,6 +293,28 @@ pub enum Message {
     Assistant(Vec<AssistantContent>),
 }
 
+/// A message in the conversation history, alongside when it was sent.
+///
+/// Timestamps are only kept locally and are never sent to the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedMessage {
+    /// When the message was added to the history, if known.
+    #[serde(default)]
+    pub at: Option<SystemTime>,
+    /// The message itself.
+    pub message: Message,
+}
+
+impl TimedMessage {
+    /// Wrap a message that was sent just now.
+    pub fn now(message: Message) -> Self {
+        Self {
+            at: Some(SystemTime::now()),
+            message,
+        }
+    }
+}
+
 /// A function to be called by the LLM.
 #[derive(Debug, Clone)]
 pub enum Function {
@@ -61,16 +337,37 @@ pub enum Function {
 }
 
 /// The content of a message.
-/// 
+///
 /// Note that some LLMs may not support all possible modalities in this enum.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Content {
     /// Text content.
     Text(String),
+    /// An image, i.e a screenshot or a rendered chart.
+    Image {
+        /// The MIME type of the image, i.e `image/png`.
+        media_type: String,
+        /// The image itself, encoded in base64.
+        data: String,
+    },
+    /// Structured data, i.e a list of search results. Tools can return this to have the toolbox
+    /// format it for the model (see `ToolFormat`), and anything else sends it as compact JSON.
+    Json(Value),
+}
+
+impl std::fmt::Display for Content {
+    /// Renders text as-is, and images as a short placeholder, i.e for printing to the terminal.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Content::Text(text) => f.write_str(text),
+            Content::Image { media_type, .. } => write!(f, "[{} image]", media_type),
+            Content::Json(value) => write!(f, "{}", value),
+        }
+    }
 }
 
 /// The content of a user message.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum UserContent {
     /// Content that the user has input.
     Input(Content),
@@ -84,7 +381,7 @@ pub enum UserContent {
 }
 
 /// The content of an assistant message sent by the LLM.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AssistantContent {
     /// The output of the LLM.
     Output(Content),
@@ -98,6 +395,48 @@ pub enum AssistantContent {
         /// The input to the function.
         input: Value,
     },
+    /// The reasoning the LLM did before answering, when a reasoning effort was set. Providers may
+    /// require this to be sent back as-is alongside the rest of the message.
+    Reasoning {
+        /// The reasoning itself, or an empty string if the provider withheld it.
+        text: String,
+        /// An opaque, provider-specific token that verifies the reasoning (or holds it encrypted
+        /// if it was withheld).
+        signature: String,
+        /// Whether the provider withheld the reasoning, i.e because it was flagged by a safety
+        /// system.
+        redacted: bool,
+    },
+    /// Text the LLM wrote from the documents or search results it was given, alongside the passages
+    /// it drew on. Providers that don't support citations only ever send plain `Output`.
+    Cited {
+        /// The text itself.
+        text: String,
+        /// The sources backing the text.
+        citations: Vec<Citation>,
+    },
+}
+
+/// A passage of a source that the LLM cited. See `AssistantContent::Cited`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    /// The text quoted from the source.
+    pub cited_text: String,
+    /// The title of the source, if it has one.
+    pub title: Option<String>,
+    /// Where to find the source, if it is on the web.
+    pub url: Option<String>,
+}
+
+impl std::fmt::Display for Citation {
+    /// Renders the title and URL of the source, falling back to the cited text.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.title, &self.url) {
+            (Some(title), Some(url)) => write!(f, "{} ({})", title, url),
+            (Some(source), None) | (None, Some(source)) => f.write_str(source),
+            (None, None) => write!(f, "\"{}\"", self.cited_text.trim()),
+        }
+    }
 }
 
 /// The completion of a message.
@@ -107,10 +446,25 @@ pub struct Completion {
     pub usage: Usage,
     /// The content of the message.
     pub content: Vec<AssistantContent>,
+    /// Why the model stopped generating.
+    pub stop_reason: StopReason,
+}
+
+/// The reason a model stopped generating a completion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// The model finished its turn.
+    EndTurn,
+    /// The model stopped to wait on the results of its function calls.
+    FunctionCall,
+    /// The model hit the maximum number of tokens, so the last piece of content may be incomplete.
+    MaxTokens,
+    /// Some other provider-specific reason.
+    Other(String),
 }
 
 /// Model usage statistics.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Usage {
     /// The number of input tokens used.
     pub input_tokens: u32,
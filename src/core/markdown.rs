@@ -0,0 +1,71 @@
+use colored::*;
+
+/// Format Markdown for the terminal: headers are bold and underlined, code is colored, bullets
+/// are drawn as `•` and `**bold**` text is bold. This only covers what models tend to write, and
+/// anything else is left as-is.
+pub fn render(text: &str) -> String {
+    let mut out = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+            out.push(line.dimmed().to_string());
+        } else if in_code {
+            out.push(line.cyan().to_string());
+        } else if let Some(header) = header(trimmed) {
+            out.push(inline(header).bold().underline().to_string());
+        } else if let Some(item) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| trimmed.strip_prefix(bullet))
+        {
+            out.push(format!("{}• {}", indent, inline(item)));
+        } else {
+            out.push(inline(line));
+        }
+    }
+    out.join("\n")
+}
+
+fn header(line: &str) -> Option<&str> {
+    let text = line.trim_start_matches('#');
+    let level = line.len() - text.len();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    text.strip_prefix(' ')
+}
+
+// Code spans are split out first, since their contents shouldn't be formatted any further.
+fn inline(line: &str) -> String {
+    spans(
+        line,
+        "`",
+        |code| code.cyan().to_string(),
+        |text| spans(text, "**", |bold| bold.bold().to_string(), str::to_string),
+    )
+}
+
+/// Format the parts of the text between pairs of the delimiter with `inside`, and the rest with
+/// `outside`. Text with an unpaired delimiter is only formatted with `outside`.
+fn spans(
+    text: &str,
+    delimiter: &str,
+    inside: impl Fn(&str) -> String,
+    outside: impl Fn(&str) -> String,
+) -> String {
+    if text.matches(delimiter).count() % 2 == 1 {
+        return outside(text);
+    }
+    text.split(delimiter)
+        .enumerate()
+        .map(|(i, part)| {
+            if i % 2 == 1 {
+                inside(part)
+            } else {
+                outside(part)
+            }
+        })
+        .collect()
+}
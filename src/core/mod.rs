@@ -1,6 +1,17 @@
 pub mod agent;
+pub mod audit;
+pub mod export;
+pub mod fallback;
+pub mod history;
+pub mod limiter;
 pub mod llm;
+pub mod markdown;
+pub mod prompt;
+pub mod redact;
+pub mod routing;
+pub mod time;
 pub mod tool;
+pub mod transport;
 
 /// Possible errors that can occur when interacting with the agent.
 #[derive(Debug)]
@@ -11,8 +22,87 @@ pub enum Error {
     Reqwest(reqwest::Error),
     /// An error occurred when parsing JSON.
     Serde(serde_json::Error),
-    /// An internal error occurred in the LLM provider.
-    Provider(String),
+    /// The input type of the tool with the given name couldn't be turned into a JSON schema, so it
+    /// can't be offered to the model. The tool has to be fixed, or disabled.
+    Schema {
+        tool: String,
+        source: serde_json::Error,
+    },
+    /// The LLM provider reported an error.
+    Provider {
+        kind: ProviderErrorKind,
+        message: String,
+    },
+    /// The agent spent more than its budget, in US dollars.
+    BudgetExceeded { spent: f64, limit: f64 },
+}
+
+/// The kind of error reported by an LLM provider, normalized across providers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderErrorKind {
+    /// The request was malformed or otherwise invalid.
+    InvalidRequest,
+    /// The API key is missing or invalid.
+    Authentication,
+    /// The API key isn't allowed to do what was asked.
+    Permission,
+    /// The model or resource asked for doesn't exist.
+    NotFound,
+    /// The request was too large.
+    RequestTooLarge,
+    /// Too many requests or tokens were sent in too short a time.
+    RateLimit,
+    /// The provider is temporarily unable to serve requests.
+    Overloaded,
+    /// The provider hit an internal error.
+    Internal,
+    /// Anything else, including errors reported by this crate on the provider's behalf.
+    Other,
+}
+
+impl Error {
+    /// An error with no more specific kind than `ProviderErrorKind::Other`.
+    pub fn provider(message: impl Into<String>) -> Error {
+        Error::Provider {
+            kind: ProviderErrorKind::Other,
+            message: message.into(),
+        }
+    }
+
+    /// Whether the error is transient, such that the same request may succeed if it is retried
+    /// later or sent to another model.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Reqwest(e) => e.is_timeout() || e.is_connect(),
+            Error::Provider { kind, .. } => matches!(
+                kind,
+                ProviderErrorKind::RateLimit | ProviderErrorKind::Overloaded
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::IO(e) => write!(f, "{}", e),
+            Error::Reqwest(e) => write!(f, "Request failed: {}", e),
+            Error::Serde(e) => write!(f, "Invalid JSON: {}", e),
+            Error::Schema { tool, source } => write!(
+                f,
+                "The input schema of the tool '{}' couldn't be built, so the tool has to be \
+                fixed or disabled: {}",
+                tool, source
+            ),
+            Error::Provider { message, .. } => f.write_str(message),
+            Error::BudgetExceeded { spent, limit } => write!(
+                f,
+                "Stopped after spending ${:.2}, over the budget of ${:.2}",
+                spent, limit
+            ),
+        }
+    }
 }
 
 impl From<std::io::Error> for Error {
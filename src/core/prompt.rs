@@ -0,0 +1,205 @@
+use super::time::format_utc;
+use std::time::SystemTime;
+
+/// The default persona, taken from cline's system prompt.
+const DEFAULT_PERSONA: &str = "You are a highly skilled software engineer with extensive knowledge in many programming languages, frameworks, design patterns, and best practices.";
+
+/// The default objective, also taken from cline's system prompt minus the custom tool calling
+/// (we assume the LLM can call tools without any coaxing).
+const DEFAULT_OBJECTIVE: &str = "You accomplish a given task iteratively, breaking it down into clear steps and working through them methodically.
+
+1. Analyze the user's task and set clear, achievable goals to accomplish it. Prioritize these goals in a logical order.
+2. Work through these goals sequentially, utilizing available tools one at a time as necessary. Each goal should correspond to a distinct step in your problem-solving process. You will be informed on the work completed and what's remaining as you go.
+3. The user may provide feedback, which you can use to make improvements and try again. But DO NOT continue in pointless back and forth conversations, i.e. don't end your responses with questions or offers for further assistance.";
+
+/// A builder for system prompts.
+///
+/// A system prompt is made up of a persona describing who the agent is, information about the
+/// environment the agent is running in, the current time, and any number of titled sections. The
+/// default prompt is a general software engineering persona with the detected environment, the
+/// time it was built at and an objective section.
+#[derive(Debug, Clone)]
+pub struct SystemPrompt {
+    persona: String,
+    environment: Option<Environment>,
+    time: Option<PromptTime>,
+    sections: Vec<(String, String)>,
+}
+
+/// The time a system prompt tells the model it is, since it otherwise only knows its training
+/// cutoff.
+#[derive(Debug, Clone, Copy)]
+pub enum PromptTime {
+    /// The time the prompt is built at.
+    Now,
+    /// A fixed time, i.e so that the prompt stays the same in tests.
+    Fixed(SystemTime),
+}
+
+impl SystemPrompt {
+    /// Create a new system prompt with the given persona and nothing else.
+    pub fn new(persona: impl Into<String>) -> Self {
+        Self {
+            persona: persona.into(),
+            environment: None,
+            time: None,
+            sections: Vec::new(),
+        }
+    }
+
+    /// Replace the persona of the prompt.
+    pub fn persona(mut self, persona: impl Into<String>) -> Self {
+        self.persona = persona.into();
+        self
+    }
+
+    /// Set the environment information included in the prompt, or `None` to leave it out.
+    pub fn environment(mut self, environment: Option<Environment>) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Set the time the prompt tells the model it is, or `None` to leave it out so that the prompt
+    /// is the same every time it's built.
+    pub fn time(mut self, time: Option<PromptTime>) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// Append a section with the given title and body to the prompt.
+    pub fn section(mut self, title: impl Into<String>, body: impl Into<String>) -> Self {
+        self.sections.push((title.into(), body.into()));
+        self
+    }
+
+    /// Produce the final system prompt.
+    pub fn build(&self) -> String {
+        let mut prompt = self.persona.clone();
+        if let Some(environment) = &self.environment {
+            push_section(&mut prompt, "SYSTEM INFORMATION:", &environment.to_string());
+        }
+        if let Some(time) = self.time {
+            let time = match time {
+                PromptTime::Now => SystemTime::now(),
+                PromptTime::Fixed(time) => time,
+            };
+            push_section(&mut prompt, "CURRENT TIME:", &format_utc(time));
+        }
+        for (title, body) in &self.sections {
+            push_section(&mut prompt, title, body);
+        }
+        prompt
+    }
+}
+
+impl Default for SystemPrompt {
+    fn default() -> Self {
+        Self::new(DEFAULT_PERSONA)
+            .environment(Some(Environment::detect()))
+            .time(Some(PromptTime::Now))
+            .section("OBJECTIVE", DEFAULT_OBJECTIVE)
+    }
+}
+
+fn push_section(prompt: &mut String, title: &str, body: &str) {
+    prompt.push_str("\n\n");
+    prompt.push_str(title);
+    prompt.push_str("\n\n");
+    prompt.push_str(body);
+}
+
+/// Information about the environment the agent is running in.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// The name of the operating system, i.e `linux`.
+    pub os: String,
+    /// The user's default shell.
+    pub shell: String,
+    /// The user's home directory.
+    pub home_dir: String,
+    /// The directory the agent was started in.
+    pub current_dir: String,
+}
+
+impl Environment {
+    /// Detect the environment of the current process.
+    pub fn detect() -> Self {
+        Self {
+            os: std::env::consts::OS.to_string(),
+            shell: std::env::var("SHELL").unwrap_or_else(|_| String::from("unknown")),
+            home_dir: dirs::home_dir()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            current_dir: std::env::current_dir()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Operating System: {}\nDefault Shell: {}\nHome Directory: {}\nCurrent Working Directory: {}",
+            self.os, self.shell, self.home_dir, self.current_dir
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn builds_every_part_in_order() {
+        let environment = Environment {
+            os: "linux".to_string(),
+            shell: "/bin/zsh".to_string(),
+            home_dir: "/home/me".to_string(),
+            current_dir: "/home/me/asimov".to_string(),
+        };
+        let prompt = SystemPrompt::default()
+            .persona("You are a careful reviewer.")
+            .environment(Some(environment))
+            .time(Some(PromptTime::Fixed(UNIX_EPOCH)))
+            .section("NOTES", "Be brief.");
+        assert_eq!(
+            prompt.build(),
+            format!(
+                "You are a careful reviewer.\n\n\
+                SYSTEM INFORMATION:\n\n\
+                Operating System: linux\n\
+                Default Shell: /bin/zsh\n\
+                Home Directory: /home/me\n\
+                Current Working Directory: /home/me/asimov\n\n\
+                CURRENT TIME:\n\n1970-01-01 00:00:00 UTC\n\n\
+                OBJECTIVE\n\n{}\n\n\
+                NOTES\n\nBe brief.",
+                DEFAULT_OBJECTIVE
+            )
+        );
+    }
+
+    #[test]
+    fn fixed_times_are_given_in_utc() {
+        let at = |secs| {
+            SystemPrompt::new("Persona.")
+                .time(Some(PromptTime::Fixed(
+                    UNIX_EPOCH + Duration::from_secs(secs),
+                )))
+                .build()
+        };
+        assert_eq!(
+            at(1_709_210_096),
+            "Persona.\n\nCURRENT TIME:\n\n2024-02-29 12:34:56 UTC"
+        );
+        assert_eq!(
+            at(946_684_799),
+            "Persona.\n\nCURRENT TIME:\n\n1999-12-31 23:59:59 UTC"
+        );
+    }
+}
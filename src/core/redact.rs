@@ -0,0 +1,247 @@
+use std::ops::Range;
+
+/// What every secret that is found gets replaced with.
+pub const MASK: &str = "[REDACTED]";
+
+/// Parts of a variable's name that mark it as holding a secret wherever they appear in it, i.e the
+/// `TOKEN` in `NPMTOKEN` or `ACCESS_TOKENS`.
+const SECRET_PARTS: [&str; 10] = [
+    "APIKEY",
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "AUTHORIZATION",
+    "COOKIE",
+    "SESSION",
+    "PRIVATE",
+];
+
+/// Words that mark a name as holding a secret only when they are a whole word of it, since they
+/// are also part of harmless names (i.e `KEYBOARD`, `PATH` or `AUTHOR`).
+const SECRET_WORDS: [&str; 3] = ["KEY", "PAT", "AUTH"];
+
+/// Whether an environment variable (or similar setting) with the given name probably holds a
+/// secret, judging by its name.
+pub fn is_secret_name(name: &str) -> bool {
+    let name = name.to_uppercase();
+    SECRET_PARTS.iter().any(|part| name.contains(part))
+        || name
+            .split(['_', '-'])
+            .any(|word| SECRET_WORDS.contains(&word))
+}
+
+/// Finds the byte ranges of any secrets in a piece of text.
+pub type Pattern = Box<dyn Fn(&str) -> Vec<Range<usize>> + Send + Sync>;
+
+/// Masks secrets like API keys and tokens in text before it's logged or exported, so that
+/// transcripts and logs can be shared without leaking credentials.
+///
+/// The default redactor knows about common kinds of secrets. More can be added with `pattern`.
+pub struct Redactor {
+    patterns: Vec<Pattern>,
+}
+
+impl Redactor {
+    /// Create a redactor with no patterns, which leaves all text untouched.
+    pub fn new() -> Self {
+        Self {
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Also redact anything found by the given pattern.
+    pub fn pattern(
+        mut self,
+        pattern: impl Fn(&str) -> Vec<Range<usize>> + Send + Sync + 'static,
+    ) -> Self {
+        self.patterns.push(Box::new(pattern));
+        self
+    }
+
+    /// Replace every secret in the text with `[REDACTED]`.
+    pub fn redact(&self, text: &str) -> String {
+        let mut ranges: Vec<Range<usize>> = self.patterns.iter().flat_map(|p| p(text)).collect();
+        ranges.sort_by_key(|r| r.start);
+        let mut out = String::with_capacity(text.len());
+        let mut at = 0;
+        for range in ranges {
+            if range.start >= at {
+                out.push_str(&text[at..range.start]);
+                out.push_str(MASK);
+                at = range.end;
+            } else {
+                // Overlaps a secret that was already masked, so just extend it
+                at = at.max(range.end);
+            }
+        }
+        out.push_str(&text[at..]);
+        out
+    }
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new()
+            // Anthropic, then OpenAI
+            .pattern(prefixed("sk-ant-", 8))
+            .pattern(prefixed("sk-", 20))
+            // AWS access key IDs, long-lived and temporary
+            .pattern(prefixed("AKIA", 16))
+            .pattern(prefixed("ASIA", 16))
+            // Google (including Gemini)
+            .pattern(prefixed("AIza", 30))
+            // GitHub and Slack
+            .pattern(prefixed("ghp_", 20))
+            .pattern(prefixed("github_pat_", 20))
+            .pattern(prefixed("xoxb-", 10))
+            .pattern(prefixed("xoxp-", 10))
+            .pattern(labeled("bearer ", 8))
+            .pattern(assignments)
+    }
+}
+
+fn is_token_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"-_.~+/=".contains(&c)
+}
+
+/// Match secrets that start with a well-known prefix, i.e `sk-ant-` for Anthropic API keys,
+/// followed by at least `min_len` more characters. The prefix is masked too.
+pub fn prefixed(
+    prefix: &'static str,
+    min_len: usize,
+) -> impl Fn(&str) -> Vec<Range<usize>> + Send + Sync {
+    move |text| {
+        let bytes = text.as_bytes();
+        text.match_indices(prefix)
+            // Don't match the prefix in the middle of some other word
+            .filter(|(start, _)| *start == 0 || !is_token_char(bytes[start - 1]))
+            .filter_map(|(start, _)| {
+                let rest = start + prefix.len();
+                let len = bytes[rest..]
+                    .iter()
+                    .take_while(|c| is_token_char(**c))
+                    .count();
+                (len >= min_len).then_some(start..rest + len)
+            })
+            .collect()
+    }
+}
+
+/// Match every occurrence of the given text, i.e a secret the other patterns don't know the shape
+/// of.
+pub fn literal(secret: impl Into<String>) -> impl Fn(&str) -> Vec<Range<usize>> + Send + Sync {
+    let secret = secret.into();
+    move |text| {
+        if secret.is_empty() {
+            return Vec::new();
+        }
+        text.match_indices(secret.as_str())
+            .map(|(start, _)| start..start + secret.len())
+            .collect()
+    }
+}
+
+/// Match secrets that follow a label, ignoring case, i.e the token in `Bearer <token>`. Only the
+/// secret itself is masked, so that it's still clear what was there.
+pub fn labeled(
+    label: &'static str,
+    min_len: usize,
+) -> impl Fn(&str) -> Vec<Range<usize>> + Send + Sync {
+    move |text| {
+        // Lowercasing ASCII doesn't move anything around, so the indices still line up
+        let lower = text.to_ascii_lowercase();
+        let bytes = text.as_bytes();
+        lower
+            .match_indices(label)
+            .filter_map(|(start, _)| {
+                let rest = start + label.len();
+                let len = bytes[rest..]
+                    .iter()
+                    .take_while(|c| is_token_char(**c))
+                    .count();
+                (len >= min_len).then_some(rest..rest + len)
+            })
+            .collect()
+    }
+}
+
+/// Match the values of `NAME=value` assignments where the name looks like it holds a secret (see
+/// `is_secret_name`), i.e in `export GITHUB_TOKEN=...` or a URL's query string.
+pub fn assignments(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    text.match_indices('=')
+        .filter_map(|(eq, _)| {
+            let name_start = bytes[..eq]
+                .iter()
+                .rposition(|c| !(c.is_ascii_alphanumeric() || *c == b'_'))
+                .map_or(0, |i| i + 1);
+            if name_start == eq || !is_secret_name(&text[name_start..eq]) {
+                return None;
+            }
+            let mut start = eq + 1;
+            if matches!(bytes.get(start), Some(b'"' | b'\'')) {
+                start += 1;
+            }
+            let len = bytes[start..]
+                .iter()
+                .take_while(|c| !(c.is_ascii_whitespace() || b"\"'\\,;&".contains(c)))
+                .count();
+            (len > 0).then_some(start..start + len)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_names() {
+        let cases = [
+            ("ANTHROPIC_API_KEY", true),
+            ("AWS_SECRET_ACCESS_KEY", true),
+            ("x-api-key", true),
+            ("Authorization", true),
+            ("GH_PAT", true),
+            ("KEY", true),
+            ("NPMTOKEN", true),
+            ("GITHUBTOKEN", true),
+            ("DBPASSWORD", true),
+            ("MY_SECRETS", true),
+            ("ACCESS_TOKENS", true),
+            ("SESSION_ID", true),
+            ("PATH", false),
+            ("HOME", false),
+            ("KEYBOARD_LAYOUT", false),
+            ("MONKEY", false),
+            ("PATTERN", false),
+            ("GIT_AUTHOR_NAME", false),
+        ];
+        for (name, secret) in cases {
+            assert_eq!(is_secret_name(name), secret, "{}", name);
+        }
+    }
+
+    #[test]
+    fn redacts_assignments_and_keys() {
+        let redactor = Redactor::default();
+        let cases = [
+            (
+                "NPMTOKEN=abc123 npm publish",
+                "NPMTOKEN=[REDACTED] npm publish",
+            ),
+            (
+                "export MY_SECRETS='hunter2'",
+                "export MY_SECRETS='[REDACTED]'",
+            ),
+            ("HOME=/root", "HOME=/root"),
+            ("key is sk-ant-api03-abcdefghijkl", "key is [REDACTED]"),
+            ("Bearer abcdefghijkl", "Bearer [REDACTED]"),
+        ];
+        for (text, redacted) in cases {
+            assert_eq!(redactor.redact(text), redacted, "{}", text);
+        }
+    }
+}
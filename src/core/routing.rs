@@ -0,0 +1,176 @@
+use super::Error;
+use super::llm::{BoxedModel, Completion, Content, Function, Message, Model, Pricing, UserContent};
+use super::tool::BoxedProviderTool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Decides which of a `RoutingModel`'s models should handle a conversation.
+pub trait Router {
+    /// The index of the model that should be called with the given messages, in the order the
+    /// models were added. Out of range indices are clamped to the last model.
+    fn route(&self, messages: &[Message]) -> usize;
+}
+
+impl<F: Fn(&[Message]) -> usize> Router for F {
+    fn route(&self, messages: &[Message]) -> usize {
+        self(messages)
+    }
+}
+
+/// A router that sends short, simple prompts to the first (cheapest) model and everything else
+/// to the second one.
+///
+/// A prompt is complex if it's longer than `max_simple_chars` or mentions any of the keywords,
+/// which should be words that hint at a lot of work, like "refactor" or "debug".
+pub struct Heuristic {
+    pub max_simple_chars: usize,
+    pub complex_keywords: Vec<String>,
+}
+
+impl Default for Heuristic {
+    fn default() -> Self {
+        Self {
+            max_simple_chars: 200,
+            complex_keywords: [
+                "refactor",
+                "debug",
+                "implement",
+                "design",
+                "migrate",
+                "optimize",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl Router for Heuristic {
+    fn route(&self, messages: &[Message]) -> usize {
+        let Some(prompt) = last_prompt(messages) else {
+            return 0;
+        };
+        let lower = prompt.to_lowercase();
+        let complex = prompt.chars().count() > self.max_simple_chars
+            || self
+                .complex_keywords
+                .iter()
+                .any(|k| lower.contains(&k.to_lowercase()));
+        complex as usize
+    }
+}
+
+/// The text the user last typed. Calls made while the agent is working through tool calls are
+/// routed on the prompt that started the work, so one task doesn't bounce between models.
+pub fn last_prompt(messages: &[Message]) -> Option<String> {
+    messages.iter().rev().find_map(|m| match m {
+        Message::User(content) => {
+            let text: Vec<&str> = content
+                .iter()
+                .filter_map(|c| match c {
+                    UserContent::Input(Content::Text(text)) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            (!text.is_empty()).then(|| text.join("\n"))
+        }
+        Message::Assistant(_) => None,
+    })
+}
+
+/// A model that picks which of several models to call based on the conversation, i.e to send
+/// simple prompts to a cheap model and harder ones to a more capable one.
+///
+/// All models share the same message format, so the history stays consistent even when the
+/// router switches models between turns. The models should support the same provider tools,
+/// since only the first model's are offered.
+pub struct RoutingModel<'a, R: Router> {
+    router: R,
+    models: Vec<(String, BoxedModel<'a>)>,
+    last: AtomicUsize,
+}
+
+impl<'a, R: Router> RoutingModel<'a, R> {
+    /// Create a routing model with no models. At least one has to be added with `route` before
+    /// it's called.
+    pub fn new(router: R) -> Self {
+        Self {
+            router,
+            models: Vec::new(),
+            last: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a model to route to, with a name used when logging routing decisions.
+    pub fn route(mut self, name: impl Into<String>, model: impl Model + 'a) -> Self {
+        self.models.push((name.into(), BoxedModel::new(model)));
+        self
+    }
+
+    fn pick(&self, messages: &[Message]) -> Result<usize, Error> {
+        if self.models.is_empty() {
+            return Err(Error::provider("No models to route to".to_string()));
+        }
+        Ok(self.router.route(messages).min(self.models.len() - 1))
+    }
+}
+
+impl<R: Router> Model for RoutingModel<'_, R> {
+    async fn call(
+        &self,
+        messages: impl AsRef<[Message]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<Completion, Error> {
+        let messages = messages.as_ref();
+        let index = self.pick(messages)?;
+        let (name, model) = &self.models[index];
+        log::info!("Routing call to {}", name);
+        self.last.store(index, Ordering::Relaxed);
+        model.call(messages, functions).await
+    }
+
+    async fn count_tokens(
+        &self,
+        messages: impl AsRef<[Message]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<Option<u32>, Error> {
+        let messages = messages.as_ref();
+        let index = self.pick(messages)?;
+        self.models[index].1.count_tokens(messages, functions).await
+    }
+
+    // This is asked for right after a call to work out its cost, so use whichever model was
+    // called last.
+    fn pricing(&self) -> Option<Pricing> {
+        self.models
+            .get(self.last.load(Ordering::Relaxed))
+            .and_then(|(_, model)| model.pricing())
+    }
+
+    // Any model could be picked, so the largest limit is the one that can be relied on.
+    fn max_tokens(&self) -> Option<u32> {
+        self.models
+            .iter()
+            .filter_map(|(_, model)| model.max_tokens())
+            .max()
+    }
+
+    fn provider_tools(&self) -> Vec<BoxedProviderTool<'static>> {
+        self.models
+            .first()
+            .map(|(_, model)| model.provider_tools())
+            .unwrap_or_default()
+    }
+
+    // Any model could be picked, so they all have to be able to use the tool.
+    fn supports_provider_tool(&self, id: &str) -> bool {
+        self.models
+            .iter()
+            .all(|(_, model)| model.supports_provider_tool(id))
+    }
+
+    fn supports_prefill(&self) -> bool {
+        self.models
+            .iter()
+            .all(|(_, model)| model.supports_prefill())
+    }
+}
@@ -2,7 +2,13 @@ use super::llm::Function;
 use super::{Error, llm::Content};
 use schemars::{JsonSchema, schema_for};
 use serde::de::DeserializeOwned;
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::Semaphore;
 
 /// A local tool, defined in the codebase.
 pub trait LocalTool {
@@ -17,8 +23,42 @@ pub trait LocalTool {
     fn description(&self) -> &'static str;
     /// The function that the tool will call.
     fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content>;
+    /// Like `call`, but also passes output to `sink` as it is produced, so that tools which run
+    /// for a while (i.e a build) can show their progress. The full result must still be returned
+    /// once the tool is done. By default, this is the same as `call`.
+    fn call_streaming(&self, input: Self::Input, _sink: Sink) -> Result<Vec<Content>, Content> {
+        self.call(input)
+    }
+    /// Whether the call only reads, and can't change files, run commands or otherwise have side
+    /// effects. Only read-only calls are allowed while the agent is planning. By default, no call
+    /// is read-only.
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        false
+    }
+    /// What the call would change (i.e a diff), worked out without changing anything, so that it
+    /// can be reviewed before it runs. By default, there is no preview.
+    fn preview(&self, _input: &Self::Input) -> Option<String> {
+        None
+    }
+}
+
+/// A local tool whose name, description and input schema are only known at runtime, i.e one
+/// defined in a config file. There is no input type to check calls against, so the input is passed
+/// on as-is and the tool has to validate it itself.
+pub trait DynamicTool {
+    /// The name of the tool. Must be unique within the toolbox.
+    fn name(&self) -> &str;
+    /// The description of the tool, as with `LocalTool::description`.
+    fn description(&self) -> &str;
+    /// The JSON schema of the tool's input, which must describe an object.
+    fn input_schema(&self) -> &Value;
+    /// The function that the tool will call.
+    fn call(&self, input: Value) -> Result<Vec<Content>, Content>;
 }
 
+/// Receives the output of a tool as it is produced. See `LocalTool::call_streaming`.
+pub type Sink<'s> = &'s (dyn Fn(&Content) + Sync);
+
 /// A tool provided by a model provider.
 pub trait ProviderTool {
     type Input: DeserializeOwned;
@@ -30,44 +70,528 @@ pub trait ProviderTool {
     fn name(&self) -> String;
     /// The function that the tool will call.
     fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content>;
+    /// Like `call`, but also passes output to `sink` as it is produced. See
+    /// `LocalTool::call_streaming`.
+    fn call_streaming(&self, input: Self::Input, _sink: Sink) -> Result<Vec<Content>, Content> {
+        self.call(input)
+    }
+    /// Whether the call only reads. See `LocalTool::is_read_only`.
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        false
+    }
+    /// What the call would change. See `LocalTool::preview`.
+    fn preview(&self, _input: &Self::Input) -> Option<String> {
+        None
+    }
+}
+
+/// A type-erased provider tool, so that a model can hand out all of the provider tools it supports
+/// without callers needing to know what they are. Much like `BoxedModel`, inputs are checked when
+/// the tool is called rather than statically.
+pub struct BoxedProviderTool<'a>(Box<dyn ProviderTool<Input = Value> + Sync + 'a>);
+
+impl<'a> BoxedProviderTool<'a> {
+    /// Erase the type of the given tool.
+    pub fn new(tool: impl ProviderTool + Sync + 'a) -> Self {
+        Self(Box::new(ErasedProviderTool(tool)))
+    }
+}
+
+impl ProviderTool for BoxedProviderTool<'_> {
+    type Input = Value;
+
+    fn id(&self) -> String {
+        self.0.id()
+    }
+
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        self.0.call(input)
+    }
+
+    fn call_streaming(&self, input: Self::Input, sink: Sink) -> Result<Vec<Content>, Content> {
+        self.0.call_streaming(input, sink)
+    }
+
+    fn is_read_only(&self, input: &Self::Input) -> bool {
+        self.0.is_read_only(input)
+    }
+
+    fn preview(&self, input: &Self::Input) -> Option<String> {
+        self.0.preview(input)
+    }
+}
+
+struct ErasedProviderTool<T: ProviderTool>(T);
+
+impl<T: ProviderTool> ProviderTool for ErasedProviderTool<T> {
+    type Input = Value;
+
+    fn id(&self) -> String {
+        self.0.id()
+    }
+
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        self.call_streaming(input, &|_| {})
+    }
+
+    fn call_streaming(&self, input: Self::Input, sink: Sink) -> Result<Vec<Content>, Content> {
+        let value =
+            serde_json::from_value::<T::Input>(input).map_err(|e| Content::Text(e.to_string()))?;
+        self.0.call_streaming(value, sink)
+    }
+
+    fn is_read_only(&self, input: &Self::Input) -> bool {
+        serde_json::from_value::<T::Input>(input.clone()).map_or(true, |v| self.0.is_read_only(&v))
+    }
+
+    fn preview(&self, input: &Self::Input) -> Option<String> {
+        serde_json::from_value::<T::Input>(input.clone())
+            .ok()
+            .and_then(|v| self.0.preview(&v))
+    }
 }
 
 /// A collection of tools that can be used by the agent.
 pub struct Toolbox<'a> {
-    tools: Vec<Box<dyn DynTool + 'a>>,
+    tools: Vec<Entry<'a>>,
+    descriptions: HashMap<String, String>,
+    formats: HashMap<String, ToolFormat>,
+    lean_schemas: bool,
+}
+
+/// How structured results (`Content::Json`) from a tool are given to the model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolFormat {
+    /// As compact JSON, which is the most precise.
+    #[default]
+    Json,
+    /// As indented `key: value` lines, which some models follow more easily and which is usually
+    /// shorter.
+    Text,
+}
+
+/// How a tool has been used over the session.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ToolStats {
+    /// The calls that returned a result.
+    pub successes: u64,
+    /// The calls that returned an error.
+    pub errors: u64,
+    /// The total size of everything the tool returned, errors included.
+    pub bytes: u64,
+}
+
+impl ToolStats {
+    /// How many times the tool was called.
+    pub fn calls(&self) -> u64 {
+        self.successes + self.errors
+    }
+}
+
+/// A tool registered in the toolbox, alongside whether the agent is currently allowed to use it.
+struct Entry<'a> {
+    tool: Box<dyn DynTool + Sync + 'a>,
+    // What to use instead of a provider tool when the model doesn't support it, see
+    // `Toolbox::provided_or_local`
+    fallback: Option<Box<dyn DynTool + Sync + 'a>>,
+    use_fallback: AtomicBool,
+    enabled: bool,
+    // Calls run on several threads at once, see `Toolbox::call_all`
+    stats: Mutex<ToolStats>,
+}
+
+impl<'a> Entry<'a> {
+    fn new(tool: Box<dyn DynTool + Sync + 'a>) -> Self {
+        Self {
+            tool,
+            fallback: None,
+            use_fallback: AtomicBool::new(false),
+            enabled: true,
+            stats: Mutex::new(ToolStats::default()),
+        }
+    }
+
+    // The implementation that was last offered to the model, which is the one it will call.
+    fn active(&self) -> &(dyn DynTool + Sync + 'a) {
+        match &self.fallback {
+            Some(fallback) if self.use_fallback.load(Ordering::Relaxed) => fallback.as_ref(),
+            _ => self.tool.as_ref(),
+        }
+    }
 }
 
 impl<'a> Toolbox<'a> {
     pub fn new() -> Self {
-        Self { tools: Vec::new() }
+        Self {
+            tools: Vec::new(),
+            descriptions: HashMap::new(),
+            formats: HashMap::new(),
+            lean_schemas: true,
+        }
+    }
+
+    /// Add a local tool to the toolbox. The tool must live for the lifetime of the toolbox, and be
+    /// safe to call from several threads at once.
+    pub fn local<T: LocalTool + Sync + 'a>(mut self, tool: T) -> Self {
+        self.tools.push(Entry::new(Box::new(LocalDynTool(tool))));
+        self
+    }
+
+    /// Add a tool defined at runtime to the toolbox. The tool must live for the lifetime of the
+    /// toolbox, and be safe to call from several threads at once.
+    pub fn dynamic<T: DynamicTool + Sync + 'a>(mut self, tool: T) -> Self {
+        self.tools.push(Entry::new(Box::new(DynamicDynTool(tool))));
+        self
     }
 
-    /// Add a local tool to the toolbox. The tool must live for the lifetime of the toolbox.
-    pub fn local<T: LocalTool + 'a>(mut self, tool: T) -> Self {
-        self.tools.push(Box::new(LocalDynTool(tool)));
+    /// Describe the tools with the given names to the model with the given descriptions instead
+    /// of their built-in ones, i.e to tune or translate them. Provider tools are described by their
+    /// provider, so they can't be overridden.
+    pub fn descriptions(mut self, descriptions: HashMap<String, String>) -> Self {
+        self.descriptions = descriptions;
         self
     }
 
-    /// Add a provider tool to the toolbox. The tool must live for the lifetime of the toolbox.
-    pub fn provided<T: ProviderTool + 'a>(mut self, tool: T) -> Self {
-        self.tools.push(Box::new(ProviderDynTool(tool)));
+    /// Give the structured results of the tools with the given names to the model in the given
+    /// formats, instead of as JSON.
+    pub fn formats(mut self, formats: HashMap<String, ToolFormat>) -> Self {
+        self.formats = formats;
         self
     }
 
-    pub(crate) fn call(&self, name: &str, input: Value) -> Result<Vec<Content>, Content> {
-        let tool = self
+    /// Set whether the input schemas of local tools are trimmed of what the model doesn't need
+    /// before they're sent, which saves tokens on every request. See `lean_schema`. On by default.
+    pub fn lean_schemas(mut self, lean: bool) -> Self {
+        self.lean_schemas = lean;
+        self
+    }
+
+    /// Add a provider tool to the toolbox. The tool must live for the lifetime of the toolbox, and
+    /// be safe to call from several threads at once.
+    pub fn provided<T: ProviderTool + Sync + 'a>(mut self, tool: T) -> Self {
+        self.tools.push(Entry::new(Box::new(ProviderDynTool(tool))));
+        self
+    }
+
+    /// Add a provider tool that falls back to a local tool with the same name when the model doesn't
+    /// support it (see `Model::supports_provider_tool`), i.e Anthropic's editor with a local
+    /// editor for other models. Only one of them is ever offered to the model, decided again every
+    /// time the model is called, so it never sees two overlapping tools.
+    pub fn provided_or_local<P: ProviderTool + Sync + 'a, L: LocalTool + Sync + 'a>(
+        mut self,
+        provider: P,
+        local: L,
+    ) -> Self {
+        let mut entry = Entry::new(Box::new(ProviderDynTool(provider)));
+        entry.fallback = Some(Box::new(LocalDynTool(local)));
+        self.tools.push(entry);
+        self
+    }
+
+    /// Add several provider tools to the toolbox at once, i.e all of the tools a model supports.
+    pub fn provided_all(mut self, tools: impl IntoIterator<Item = BoxedProviderTool<'a>>) -> Self {
+        for tool in tools {
+            self = self.provided(tool);
+        }
+        self
+    }
+
+    /// Replace the provider tool with the same name as the given tool, keeping whether it is
+    /// enabled and any local fallback. If there is no such tool, it is added instead. This is
+    /// useful when the model changes and its provider tools must be swapped for versions the new
+    /// model supports.
+    pub fn replace_provided<T: ProviderTool + Sync + 'a>(&mut self, tool: T) {
+        let name = tool.name();
+        let tool: Box<dyn DynTool + Sync + 'a> = Box::new(ProviderDynTool(tool));
+        match self.tools.iter_mut().find(|e| e.tool.is(&name)) {
+            Some(entry) => entry.tool = tool,
+            None => self.tools.push(Entry::new(tool)),
+        }
+    }
+
+    /// Allow the agent to use the tool with the given name again. Returns false if no such tool
+    /// is in the toolbox.
+    pub fn enable(&mut self, name: &str) -> bool {
+        self.set_enabled(name, true)
+    }
+
+    /// Hide the tool with the given name from the agent until it is enabled again. Returns false
+    /// if no such tool is in the toolbox.
+    pub fn disable(&mut self, name: &str) -> bool {
+        self.set_enabled(name, false)
+    }
+
+    /// The names of all tools in the toolbox, alongside whether they are currently enabled.
+    pub fn tools(&self) -> impl Iterator<Item = (String, bool)> + '_ {
+        self.tools.iter().map(|e| (e.tool.name(), e.enabled))
+    }
+
+    /// The names of all tools in the toolbox, alongside how they have been used so far.
+    pub fn tool_stats(&self) -> Vec<(String, ToolStats)> {
+        self.tools
+            .iter()
+            .map(|e| (e.tool.name(), *e.stats.lock().unwrap()))
+            .collect()
+    }
+
+    /// Forget how every tool has been used, i.e when starting a new conversation.
+    pub fn reset_stats(&mut self) {
+        for entry in &mut self.tools {
+            *entry.stats.get_mut().unwrap() = ToolStats::default();
+        }
+    }
+
+    fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.tools.iter_mut().find(|e| e.tool.is(name)) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether calling the tool with the given name and input only reads. Calls that can't be
+    /// made at all, i.e to a missing tool or with an invalid input, count as read-only too, since
+    /// they will fail without doing anything.
+    pub(crate) fn is_read_only(&self, name: &str, input: &Value) -> bool {
+        match self.tools.iter().find(|e| e.tool.is(name)) {
+            Some(entry) => entry.active().is_read_only(input),
+            None => true,
+        }
+    }
+
+    /// What calling the tool with the given name and input would change, if the tool can tell.
+    pub(crate) fn preview(&self, name: &str, input: &Value) -> Option<String> {
+        self.tools
+            .iter()
+            .find(|e| e.tool.is(name))
+            .and_then(|entry| entry.active().preview(input))
+    }
+
+    pub(crate) fn call(
+        &self,
+        name: &str,
+        input: Value,
+        sink: Sink,
+    ) -> Result<Vec<Content>, Content> {
+        let entry = self
             .tools
             .iter()
-            .find(|t| t.is(name))
+            .find(|e| e.tool.is(name))
             .ok_or(Content::Text(format!(
                 "Cannot use '{}' because it was not found.",
                 name
             )))?;
-        tool.call(input)
+        if !entry.enabled {
+            return Err(Content::Text(format!(
+                "Cannot use '{}' because it has been disabled by the user.",
+                name
+            )));
+        }
+        let format = self.formats.get(name).copied().unwrap_or_default();
+        let result: Result<Vec<_>, _> = match entry.active().call(input, sink) {
+            Ok(contents) => Ok(contents
+                .into_iter()
+                .map(|content| format_content(content, format))
+                .collect()),
+            Err(content) => Err(format_content(content, format)),
+        };
+        let mut stats = entry.stats.lock().unwrap();
+        match &result {
+            Ok(contents) => {
+                stats.successes += 1;
+                stats.bytes += contents.iter().map(content_bytes).sum::<u64>();
+            }
+            Err(content) => {
+                stats.errors += 1;
+                stats.bytes += content_bytes(content);
+            }
+        }
+        result
     }
 
-    pub(super) fn functions(&self) -> Result<Vec<Function>, Error> {
-        self.tools.iter().map(|t| t.function()).collect()
+    /// Make several calls, returning their results in the same order as the calls.
+    ///
+    /// Calls that only read (see `LocalTool::is_read_only`) run in parallel with the read-only
+    /// calls next to them. Anything else runs on its own, after every call before it has finished
+    /// and before any call after it starts, so that calls always see the changes made by the ones
+    /// before them. Output is passed to `sink` alongside the name of the tool that produced it as
+    /// it comes. Every call holds a permit from `limiter` while it runs, which caps how many run at
+    /// the same time.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub(crate) async fn call_all(
+        &self,
+        calls: Vec<(&str, Value)>,
+        limiter: &Semaphore,
+        sink: &(dyn Fn(&str, &Content) + Sync),
+    ) -> Vec<Result<Vec<Content>, Content>> {
+        let mut results = Vec::with_capacity(calls.len());
+        let mut rest = &calls[..];
+        while !rest.is_empty() {
+            let parallel = rest
+                .iter()
+                .position(|(name, input)| !self.is_read_only(name, input))
+                .unwrap_or(rest.len())
+                .max(1);
+            // Waiting for every permit at once could deadlock with others doing the same, so only
+            // the first is waited on, and as many of the calls run as there are permits to spare
+            let mut permits = vec![limiter.acquire().await.unwrap()];
+            while permits.len() < parallel
+                && let Ok(permit) = limiter.try_acquire()
+            {
+                permits.push(permit);
+            }
+            let (batch, next) = rest.split_at(permits.len());
+            rest = next;
+            results.extend(blocking(|| {
+                thread::scope(|scope| {
+                    let handles: Vec<_> = batch
+                        .iter()
+                        .map(|(name, input)| {
+                            scope.spawn(move || {
+                                let sink = |content: &Content| sink(name, content);
+                                self.call(name, input.clone(), &sink)
+                            })
+                        })
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|h| h.join().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            }));
+        }
+        results
+    }
+
+    /// The functions to offer a model, which can use the provider tools with the IDs that
+    /// `supports_provider_tool` accepts. Provider tools with a local fallback are swapped for it
+    /// when the model can't use them.
+    pub(super) fn functions(
+        &self,
+        supports_provider_tool: impl Fn(&str) -> bool,
+    ) -> Result<Vec<Function>, Error> {
+        self.tools
+            .iter()
+            .filter(|e| e.enabled)
+            .map(|e| {
+                let function = e.tool.function()?;
+                if let Some(fallback) = &e.fallback
+                    && let Function::Provider { id, .. } = &function
+                {
+                    let use_fallback = !supports_provider_tool(id);
+                    e.use_fallback.store(use_fallback, Ordering::Relaxed);
+                    if use_fallback {
+                        return fallback.function();
+                    }
+                }
+                Ok(function)
+            })
+            .map(|function| match function? {
+                Function::Local {
+                    name,
+                    description,
+                    input_schema,
+                } => Ok(Function::Local {
+                    description: self.descriptions.get(&name).cloned().unwrap_or(description),
+                    name,
+                    input_schema: if self.lean_schemas {
+                        lean_schema(input_schema)
+                    } else {
+                        input_schema
+                    },
+                }),
+                function => Ok(function),
+            })
+            .collect()
+    }
+}
+
+// Models are only ever given text, so structured results are formatted here, the same way for
+// every tool.
+fn format_content(content: Content, format: ToolFormat) -> Content {
+    match (content, format) {
+        (Content::Json(value), ToolFormat::Json) => Content::Text(value.to_string()),
+        (Content::Json(value), ToolFormat::Text) => {
+            let mut text = String::new();
+            format_text(&value, 0, &mut text);
+            Content::Text(text.trim_end().to_string())
+        }
+        (content, _) => content,
+    }
+}
+
+// A YAML-like rendering: scalars as-is, objects as `key: value` lines and arrays as `-` items, with
+// anything nested indented under its key.
+fn format_text(value: &Value, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if is_scalar(value) {
+                    out.push_str(&format!("{}{}: {}\n", pad, key, scalar(value)));
+                } else {
+                    out.push_str(&format!("{}{}:\n", pad, key));
+                    format_text(value, indent + 1, out);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                if is_scalar(item) {
+                    out.push_str(&format!("{}- {}\n", pad, scalar(item)));
+                } else {
+                    out.push_str(&format!("{}-\n", pad));
+                    format_text(item, indent + 1, out);
+                }
+            }
+        }
+        value => out.push_str(&format!("{}{}\n", pad, scalar(value))),
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => map.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        _ => true,
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "none".to_string(),
+        value => value.to_string(),
+    }
+}
+
+pub(crate) fn content_bytes(content: &Content) -> u64 {
+    let bytes = match content {
+        Content::Text(text) => text.len(),
+        Content::Image { data, .. } => data.len(),
+        Content::Json(value) => value.to_string().len(),
+    };
+    bytes as u64
+}
+
+// Tools are synchronous and can take a while, so on a multi-threaded runtime the worker thread is
+// handed off while they run instead of stalling the tasks queued on it.
+fn blocking<R>(f: impl FnOnce() -> R) -> R {
+    match Handle::current().runtime_flavor() {
+        RuntimeFlavor::MultiThread => tokio::task::block_in_place(f),
+        _ => f(),
     }
 }
 
@@ -77,14 +601,21 @@ impl<'a> Toolbox<'a> {
 // based on the tool's type information.
 
 trait DynTool {
+    fn name(&self) -> String;
     fn is(&self, name: &str) -> bool;
     fn function(&self) -> Result<Function, Error>;
-    fn call(&self, input: Value) -> Result<Vec<Content>, Content>;
+    fn call(&self, input: Value, sink: Sink) -> Result<Vec<Content>, Content>;
+    fn is_read_only(&self, input: &Value) -> bool;
+    fn preview(&self, input: &Value) -> Option<String>;
 }
 
 struct LocalDynTool<T: LocalTool>(T);
 
 impl<T: LocalTool> DynTool for LocalDynTool<T> {
+    fn name(&self) -> String {
+        self.0.name().to_string()
+    }
+
     fn is(&self, name: &str) -> bool {
         self.0.name() == name
     }
@@ -93,20 +624,248 @@ impl<T: LocalTool> DynTool for LocalDynTool<T> {
         Ok(Function::Local {
             name: self.0.name().to_string(),
             description: self.0.description().to_string(),
-            input_schema: serde_json::to_value(schema_for!(T::Input))?,
+            input_schema: input_schema(self.0.name(), &schema_for!(T::Input))?,
         })
     }
 
-    fn call(&self, input: Value) -> Result<Vec<Content>, Content> {
-        let value =
-            serde_json::from_value::<T::Input>(input).map_err(|e| Content::Text(e.to_string()))?;
-        self.0.call(value)
+    fn call(&self, input: Value, sink: Sink) -> Result<Vec<Content>, Content> {
+        let value = serde_json::from_value::<T::Input>(input.clone())
+            .map_err(|e| invalid_input(self.0.name(), &schema_for!(T::Input), &input, e))?;
+        self.0.call_streaming(value, sink)
+    }
+
+    fn is_read_only(&self, input: &Value) -> bool {
+        serde_json::from_value::<T::Input>(input.clone()).map_or(true, |v| self.0.is_read_only(&v))
+    }
+
+    fn preview(&self, input: &Value) -> Option<String> {
+        serde_json::from_value::<T::Input>(input.clone())
+            .ok()
+            .and_then(|v| self.0.preview(&v))
+    }
+}
+
+/// Strip what a model doesn't need from a generated input schema: the `$schema` and `title`
+/// annotations, and the `definitions` that are only there to be referenced, which are inlined where
+/// they're used. Definitions that refer to others in a cycle (i.e recursive types) can't be inlined,
+/// so they're kept.
+fn lean_schema(mut schema: Value) -> Value {
+    let Some(root) = schema.as_object_mut() else {
+        return schema;
+    };
+    root.remove("$schema");
+    let mut definitions = match root.remove("definitions") {
+        Some(Value::Object(definitions)) => definitions,
+        _ => Map::new(),
+    };
+    // Inlining the definitions that refer to nothing else can leave others that refer to nothing
+    // else, until only cycles are left
+    let mut inlined = Map::new();
+    loop {
+        let ready: Vec<String> = definitions
+            .iter()
+            .filter(|(_, definition)| !has_ref(definition))
+            .map(|(name, _)| name.clone())
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        for name in ready {
+            let definition = definitions.remove(&name).unwrap();
+            inlined.insert(name, definition);
+        }
+        for definition in definitions.values_mut() {
+            inline(definition, &inlined);
+        }
+    }
+    inline(&mut schema, &inlined);
+    if !definitions.is_empty() {
+        schema["definitions"] = Value::Object(definitions);
+    }
+    strip(&mut schema);
+    schema
+}
+
+fn has_ref(value: &Value) -> bool {
+    match value {
+        Value::Object(object) => object.contains_key("$ref") || object.values().any(has_ref),
+        Value::Array(array) => array.iter().any(has_ref),
+        _ => false,
+    }
+}
+
+// Replace every reference to one of the given definitions with the definition itself, keeping
+// anything next to the reference (i.e a description).
+fn inline(value: &mut Value, definitions: &Map<String, Value>) {
+    if let Some(object) = value.as_object_mut()
+        && let Some(Value::String(reference)) = object.get("$ref")
+        && let Some(Value::Object(definition)) = reference
+            .strip_prefix("#/definitions/")
+            .and_then(|name| definitions.get(name))
+    {
+        let mut replacement = definition.clone();
+        object.remove("$ref");
+        replacement.extend(std::mem::take(object));
+        *value = Value::Object(replacement);
+        return;
+    }
+    match value {
+        Value::Object(object) => object.values_mut().for_each(|v| inline(v, definitions)),
+        Value::Array(array) => array.iter_mut().for_each(|v| inline(v, definitions)),
+        _ => {}
+    }
+}
+
+// Remove titles, and unwrap the single `allOf` that schemars uses to describe a reference, going
+// only into subschemas so that properties named i.e `title` are left alone.
+fn strip(schema: &mut Value) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+    let single = match object.get("allOf") {
+        Some(Value::Array(all)) if all.len() == 1 => all[0]
+            .as_object()
+            .filter(|inner| inner.keys().all(|k| !object.contains_key(k)))
+            .cloned(),
+        _ => None,
+    };
+    if let Some(inner) = single {
+        object.remove("allOf");
+        object.extend(inner);
+    }
+    object.remove("title");
+    for key in ["properties", "definitions"] {
+        if let Some(Value::Object(schemas)) = object.get_mut(key) {
+            schemas.values_mut().for_each(strip);
+        }
+    }
+    for key in [
+        "items",
+        "additionalProperties",
+        "not",
+        "anyOf",
+        "oneOf",
+        "allOf",
+    ] {
+        match object.get_mut(key) {
+            Some(Value::Array(schemas)) => schemas.iter_mut().for_each(strip),
+            Some(schema) => strip(schema),
+            None => {}
+        }
+    }
+}
+
+// The schema of a tool's input as JSON, with any failure naming the tool it's for.
+fn input_schema(tool: &str, schema: &impl serde::Serialize) -> Result<Value, Error> {
+    serde_json::to_value(schema).map_err(|source| Error::Schema {
+        tool: tool.to_string(),
+        source,
+    })
+}
+
+// A bare serde error is hard for the model to act on, so point out which fields were wrong and
+// include the full schema, which is usually enough for it to correct the call on its next try.
+fn invalid_input(
+    name: &str,
+    schema: &impl serde::Serialize,
+    input: &Value,
+    error: serde_json::Error,
+) -> Content {
+    let Ok(schema) = serde_json::to_value(schema) else {
+        return Content::Text(error.to_string());
+    };
+    let mut message = format!("Invalid input for '{}': {}", name, error);
+    for problem in field_problems(&schema, input) {
+        message.push_str(&format!("\n- {}", problem));
+    }
+    message.push_str(&format!(
+        "\nCall '{}' again with input matching its schema:\n{}",
+        name,
+        serde_json::to_string_pretty(&schema).unwrap_or_default()
+    ));
+    Content::Text(message)
+}
+
+// Only the top-level fields with simple types are checked, anything else is left to the error.
+fn field_problems(schema: &Value, input: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    let Some(input) = input.as_object() else {
+        return problems;
+    };
+    for field in schema["required"].as_array().into_iter().flatten() {
+        if let Some(field) = field.as_str().filter(|f| !input.contains_key(*f)) {
+            problems.push(format!("`{}` is required but was not given", field));
+        }
+    }
+    for (field, value) in input {
+        let expected = match &schema["properties"][field]["type"] {
+            Value::String(ty) => vec![ty.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => continue,
+        };
+        let actual = match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        };
+        let matches = expected
+            .iter()
+            .any(|ty| *ty == actual || (*ty == "number" && actual == "integer"));
+        if !matches {
+            problems.push(format!(
+                "`{}` should be of type {} but was {}",
+                field,
+                expected.join(" or "),
+                actual
+            ));
+        }
+    }
+    problems
+}
+
+struct DynamicDynTool<T: DynamicTool>(T);
+
+impl<T: DynamicTool> DynTool for DynamicDynTool<T> {
+    fn name(&self) -> String {
+        self.0.name().to_string()
+    }
+
+    fn is(&self, name: &str) -> bool {
+        self.0.name() == name
+    }
+
+    fn function(&self) -> Result<Function, Error> {
+        Ok(Function::Local {
+            name: self.0.name().to_string(),
+            description: self.0.description().to_string(),
+            input_schema: self.0.input_schema().clone(),
+        })
+    }
+
+    fn call(&self, input: Value, _sink: Sink) -> Result<Vec<Content>, Content> {
+        self.0.call(input)
+    }
+
+    fn is_read_only(&self, _input: &Value) -> bool {
+        false
+    }
+
+    fn preview(&self, _input: &Value) -> Option<String> {
+        None
     }
 }
 
 struct ProviderDynTool<T: ProviderTool>(T);
 
 impl<T: ProviderTool> DynTool for ProviderDynTool<T> {
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
     fn is(&self, name: &str) -> bool {
         self.0.name() == name
     }
@@ -118,9 +877,197 @@ impl<T: ProviderTool> DynTool for ProviderDynTool<T> {
         })
     }
 
-    fn call(&self, input: Value) -> Result<Vec<Content>, Content> {
+    fn call(&self, input: Value, sink: Sink) -> Result<Vec<Content>, Content> {
         let value =
             serde_json::from_value::<T::Input>(input).map_err(|e| Content::Text(e.to_string()))?;
-        self.0.call(value)
+        self.0.call_streaming(value, sink)
+    }
+
+    fn is_read_only(&self, input: &Value) -> bool {
+        serde_json::from_value::<T::Input>(input.clone()).map_or(true, |v| self.0.is_read_only(&v))
+    }
+
+    fn preview(&self, input: &Value) -> Option<String> {
+        serde_json::from_value::<T::Input>(input.clone())
+            .ok()
+            .and_then(|v| self.0.preview(&v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Records when each call starts and ends.
+    struct Logged(Arc<Mutex<Vec<String>>>);
+
+    #[derive(Deserialize, JsonSchema, Debug)]
+    struct LoggedInput {
+        id: u32,
+        write: bool,
+    }
+
+    impl LocalTool for Logged {
+        type Input = LoggedInput;
+
+        fn name(&self) -> &'static str {
+            "logged"
+        }
+
+        fn description(&self) -> &'static str {
+            "Log the call."
+        }
+
+        fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+            self.0.lock().unwrap().push(format!("start {}", input.id));
+            thread::sleep(Duration::from_millis(20));
+            self.0.lock().unwrap().push(format!("end {}", input.id));
+            Ok(Vec::new())
+        }
+
+        fn is_read_only(&self, input: &Self::Input) -> bool {
+            !input.write
+        }
+    }
+
+    fn position(log: &[String], event: &str) -> usize {
+        log.iter().position(|e| e == event).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn only_read_only_calls_run_in_parallel() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let toolbox = Toolbox::new().local(Logged(log.clone()));
+        let calls = vec![
+            ("logged", json!({ "id": 1, "write": false })),
+            ("logged", json!({ "id": 2, "write": false })),
+            ("logged", json!({ "id": 3, "write": true })),
+            ("logged", json!({ "id": 4, "write": false })),
+        ];
+        let results = toolbox
+            .call_all(calls, &Semaphore::new(4), &|_, _| {})
+            .await;
+        assert!(results.iter().all(Result::is_ok));
+
+        let log = log.lock().unwrap();
+        // The reads before the write overlap, and the write runs on its own between them and the
+        // read after it
+        assert!(position(&log, "start 2") < position(&log, "end 1"));
+        assert!(position(&log, "end 1") < position(&log, "start 3"));
+        assert!(position(&log, "end 2") < position(&log, "start 3"));
+        assert!(position(&log, "end 3") < position(&log, "start 4"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn limiter_caps_parallel_calls() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let toolbox = Toolbox::new().local(Logged(log.clone()));
+        let calls = (1..=3)
+            .map(|id| ("logged", json!({ "id": id, "write": false })))
+            .collect();
+        let results = toolbox
+            .call_all(calls, &Semaphore::new(1), &|_, _| {})
+            .await;
+        assert!(results.iter().all(Result::is_ok));
+
+        let log = log.lock().unwrap();
+        assert!(position(&log, "end 1") < position(&log, "start 2"));
+        assert!(position(&log, "end 2") < position(&log, "start 3"));
+    }
+
+    #[test]
+    fn explains_invalid_input() {
+        let schema = serde_json::to_value(schema_for!(LoggedInput)).unwrap();
+        assert_eq!(
+            field_problems(&schema, &json!({ "id": "one" })),
+            [
+                "`write` is required but was not given",
+                "`id` should be of type integer but was string",
+            ]
+        );
+        assert!(field_problems(&schema, &json!({ "id": 1, "write": true })).is_empty());
+
+        let error = serde_json::from_value::<LoggedInput>(json!({ "id": 1 })).unwrap_err();
+        let Content::Text(message) = invalid_input(
+            "logged",
+            &schema_for!(LoggedInput),
+            &json!({ "id": 1 }),
+            error,
+        ) else {
+            panic!("expected a text explanation");
+        };
+        assert!(message.starts_with("Invalid input for 'logged': missing field `write`"));
+        assert!(message.contains("Call 'logged' again with input matching its schema"));
+    }
+
+    #[test]
+    fn schema_errors_name_the_tool() {
+        // JSON objects can only have string keys
+        let broken = HashMap::from([((1, 2), "pair")]);
+        let error = input_schema("broken", &broken).unwrap_err();
+        let Error::Schema { tool, .. } = &error else {
+            panic!("expected a schema error");
+        };
+        assert_eq!(tool, "broken");
+        assert!(error.to_string().contains("tool 'broken'"), "{}", error);
+        assert!(input_schema("logged", &schema_for!(LoggedInput)).is_ok());
+    }
+
+    #[test]
+    fn lean_schemas_inline_definitions_and_drop_annotations() {
+        let schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "EditInput",
+            "type": "object",
+            "properties": {
+                "title": { "type": "string" },
+                "range": {
+                    "description": "The lines to edit",
+                    "allOf": [{ "$ref": "#/definitions/Range" }]
+                },
+                "tree": { "$ref": "#/definitions/Tree" }
+            },
+            "definitions": {
+                "Range": {
+                    "title": "Range",
+                    "type": "object",
+                    "properties": { "start": { "$ref": "#/definitions/Line" } }
+                },
+                "Line": { "title": "Line", "type": "integer" },
+                "Tree": {
+                    "type": "object",
+                    "properties": {
+                        "children": { "type": "array", "items": { "$ref": "#/definitions/Tree" } }
+                    }
+                }
+            }
+        });
+        assert_eq!(
+            lean_schema(schema),
+            json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string" },
+                    "range": {
+                        "description": "The lines to edit",
+                        "type": "object",
+                        "properties": { "start": { "type": "integer" } }
+                    },
+                    "tree": { "$ref": "#/definitions/Tree" }
+                },
+                "definitions": {
+                    "Tree": {
+                        "type": "object",
+                        "properties": {
+                            "children": { "type": "array", "items": { "$ref": "#/definitions/Tree" } }
+                        }
+                    }
+                }
+            })
+        );
     }
 }
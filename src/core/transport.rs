@@ -0,0 +1,279 @@
+use super::Error;
+use super::redact::{MASK, is_secret_name};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+/// Top-level request fields that depend on the machine or session rather than the conversation,
+/// and so are ignored when matching replayed requests by default.
+pub const VOLATILE_FIELDS: [&str; 2] = ["system", "metadata"];
+
+/// An HTTP request to an LLM provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpRequest {
+    #[serde(default)]
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// The method of an `HttpRequest`. Almost every request is a POST, which is the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Method {
+    Get,
+    #[default]
+    Post,
+}
+
+/// The response to an `HttpRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+type TransportFuture<'a> = Pin<Box<dyn Future<Output = Result<HttpResponse, Error>> + Send + 'a>>;
+
+/// A way of sending requests to LLM providers.
+///
+/// Providers send all of their requests through a transport, so that it can be swapped out to
+/// record, replay, or inspect their traffic without touching the network.
+pub trait HttpTransport: Send + Sync {
+    /// Send the request and wait for its response.
+    fn send<'a>(&'a self, request: HttpRequest) -> TransportFuture<'a>;
+}
+
+/// The default transport, which sends requests over the network.
+pub struct ReqwestTransport(Client);
+
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        Self(Client::new())
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn send<'a>(&'a self, request: HttpRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let mut req = match request.method {
+                Method::Get => self.0.get(&request.url),
+                Method::Post => self.0.post(&request.url).body(request.body),
+            };
+            for (name, value) in &request.headers {
+                req = req.header(name, value);
+            }
+            let resp = req.send().await?;
+            let status = resp.status().as_u16();
+            let headers = resp
+                .headers()
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        value.to_str().unwrap_or_default().to_string(),
+                    )
+                })
+                .collect();
+            let body = resp.text().await?;
+            Ok(HttpResponse {
+                status,
+                headers,
+                body,
+            })
+        })
+    }
+}
+
+/// A request and the response it got, as stored by `RecordingTransport`.
+#[derive(Serialize, Deserialize)]
+struct Recording {
+    request: HttpRequest,
+    response: HttpResponse,
+}
+
+/// A transport that appends every request it sends and the response it got to a JSONL file,
+/// which can be replayed later with `ReplayTransport`.
+pub struct RecordingTransport {
+    inner: Arc<dyn HttpTransport>,
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl RecordingTransport {
+    /// Record the traffic sent through `inner` to the file at `path`.
+    pub fn new(inner: Arc<dyn HttpTransport>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl HttpTransport for RecordingTransport {
+    fn send<'a>(&'a self, request: HttpRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let response = self.inner.send(request.clone()).await?;
+            let line = serde_json::to_string(&Recording {
+                request: redact_headers(request),
+                response: response.clone(),
+            })?;
+            let _guard = self.lock.lock().unwrap();
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{}", line)?;
+            Ok(response)
+        })
+    }
+}
+
+// Headers aren't used to match replayed requests, so secrets like the API key can be masked
+// before a recording is written without breaking the replay.
+fn redact_headers(mut request: HttpRequest) -> HttpRequest {
+    for (name, value) in &mut request.headers {
+        if is_secret_name(name) {
+            *value = MASK.to_string();
+        }
+    }
+    request
+}
+
+/// A transport that serves responses recorded by `RecordingTransport` instead of touching the
+/// network, for deterministic tests and debugging.
+///
+/// Requests are matched by their URL and body, ignoring the given top-level body fields. If the
+/// same request was recorded more than once, the responses are served in the order they were
+/// recorded, with the last one repeating.
+pub struct ReplayTransport {
+    responses: HashMap<u64, Vec<HttpResponse>>,
+    served: Mutex<HashMap<u64, usize>>,
+    ignored: Vec<String>,
+}
+
+impl ReplayTransport {
+    /// Load the recordings from the file at `path`, ignoring the given fields when matching.
+    pub fn load(path: impl AsRef<Path>, ignored: &[&str]) -> Result<Self, Error> {
+        let ignored: Vec<String> = ignored.iter().map(|f| f.to_string()).collect();
+        let mut responses: HashMap<u64, Vec<HttpResponse>> = HashMap::new();
+        for line in fs::read_to_string(path)?.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let recording: Recording = serde_json::from_str(line)?;
+            responses
+                .entry(fingerprint(&recording.request, &ignored))
+                .or_default()
+                .push(recording.response);
+        }
+        Ok(Self {
+            responses,
+            served: Mutex::new(HashMap::new()),
+            ignored,
+        })
+    }
+}
+
+impl HttpTransport for ReplayTransport {
+    fn send<'a>(&'a self, request: HttpRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let key = fingerprint(&request, &self.ignored);
+            let responses = self.responses.get(&key).ok_or_else(|| {
+                Error::provider(format!(
+                    "No recorded response for request to {}",
+                    request.url
+                ))
+            })?;
+            let mut served = self.served.lock().unwrap();
+            let count = served.entry(key).or_default();
+            let response = responses[(*count).min(responses.len() - 1)].clone();
+            *count += 1;
+            Ok(response)
+        })
+    }
+}
+
+// JSON objects are serialized with sorted keys, so re-serializing the body gives a canonical form
+// that doesn't depend on field order or whitespace.
+fn fingerprint(request: &HttpRequest, ignored: &[String]) -> u64 {
+    let body = match serde_json::from_str::<Value>(&request.body) {
+        Ok(Value::Object(mut object)) => {
+            for field in ignored {
+                object.remove(field);
+            }
+            Value::Object(object).to_string()
+        }
+        _ => request.body.clone(),
+    };
+    // FNV-1a, which unlike the std hasher is stable across Rust versions
+    format!("{}\n{}", request.url, body)
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl HttpTransport for Echo {
+        fn send<'a>(&'a self, request: HttpRequest) -> TransportFuture<'a> {
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: 200,
+                    headers: Vec::new(),
+                    body: request.body,
+                })
+            })
+        }
+    }
+
+    fn request() -> HttpRequest {
+        HttpRequest {
+            method: Method::Post,
+            url: "https://api.anthropic.com/v1/messages".to_string(),
+            headers: vec![
+                ("x-api-key".to_string(), "sk-ant-secret".to_string()),
+                (
+                    "Authorization".to_string(),
+                    "Bearer gateway-secret".to_string(),
+                ),
+                ("x-gateway-token".to_string(), "token-secret".to_string()),
+                ("anthropic-version".to_string(), "2023-06-01".to_string()),
+            ],
+            body: r#"{"model":"claude"}"#.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn recording_masks_secret_headers() {
+        let path = std::env::temp_dir().join(format!("asimov-record-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let recorder = RecordingTransport::new(Arc::new(Echo), &path);
+        recorder.send(request()).await.unwrap();
+
+        let recorded = fs::read_to_string(&path).unwrap();
+        assert!(!recorded.contains("sk-ant-secret"));
+        assert!(!recorded.contains("gateway-secret"));
+        assert!(!recorded.contains("token-secret"));
+        assert!(recorded.contains("2023-06-01"));
+
+        // The masked headers don't get in the way of replaying the request
+        let replay = ReplayTransport::load(&path, &VOLATILE_FIELDS).unwrap();
+        let response = replay.send(request()).await.unwrap();
+        assert_eq!(response.body, r#"{"model":"claude"}"#);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,526 @@
+use crate::core::{
+    Error, ProviderErrorKind,
+    llm::{
+        self, AssistantContent, Content as LlmContent, Function, Hyperparams,
+        Message as LlmMessage, Model, Pricing, StopReason, Usage as LlmUsage, UserContent,
+    },
+    transport::{HttpRequest, HttpTransport, Method},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::GeminiVersion;
+
+#[derive(Clone)]
+pub struct GeminiModel {
+    transport: Arc<dyn HttpTransport>,
+    api_key: String,
+    model: GeminiVersion,
+    system_prompt: Vec<String>,
+    hyperparams: Hyperparams,
+}
+
+impl GeminiModel {
+    pub fn new(
+        transport: Arc<dyn HttpTransport>,
+        api_key: String,
+        model: GeminiVersion,
+        system_prompt: Vec<String>,
+        hyperparams: Hyperparams,
+    ) -> Self {
+        Self {
+            transport,
+            api_key,
+            model,
+            system_prompt,
+            hyperparams,
+        }
+    }
+}
+
+impl Model for GeminiModel {
+    async fn call(
+        &self,
+        messages: impl AsRef<[LlmMessage]>,
+        functions: impl AsRef<[Function]>,
+    ) -> Result<llm::Completion, Error> {
+        // Gemini identifies function responses by the name of the function rather than the ID
+        // of the call, so we need to remember which function each call was for.
+        let names: HashMap<&str, &str> = messages
+            .as_ref()
+            .iter()
+            .flat_map(|m| match m {
+                LlmMessage::Assistant(content) => content.as_slice(),
+                LlmMessage::User(_) => [].as_slice(),
+            })
+            .filter_map(|c| match c {
+                AssistantContent::FunctionCall { id, name, .. } => {
+                    Some((id.as_str(), name.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let contents = messages
+            .as_ref()
+            .iter()
+            .map(|m| map_llm_message_to_gemini(m, &names))
+            .collect::<Vec<_>>();
+
+        let function_declarations = functions
+            .as_ref()
+            .iter()
+            .filter_map(|f| match f {
+                Function::Local {
+                    name,
+                    description,
+                    input_schema,
+                } => Some(FunctionDeclaration {
+                    name: name.clone(),
+                    description: description.clone(),
+                    parameters: map_schema_to_gemini(input_schema.clone()),
+                }),
+                Function::Provider { name, .. } => {
+                    log::warn!("Gemini does not support provider tools, skipping {}", name);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let payload = GenerateContent {
+            system_instruction: (!self.system_prompt.is_empty()).then(|| SystemInstruction {
+                parts: self.system_prompt.iter().map(|s| Part::text(s)).collect(),
+            }),
+            contents,
+            tools: if function_declarations.is_empty() {
+                Vec::new()
+            } else {
+                vec![Tool {
+                    function_declarations,
+                }]
+            },
+            generation_config: GenerationConfig {
+                max_output_tokens: self.hyperparams.max_tokens,
+                temperature: self.hyperparams.temperature,
+                seed: self.hyperparams.seed,
+            },
+        };
+
+        let body = serde_json::to_string(&payload)?;
+        let req = HttpRequest {
+            method: Method::Post,
+            url: format!(
+                "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent",
+                self.model
+            ),
+            headers: vec![
+                ("x-goog-api-key".to_string(), self.api_key.clone()),
+                ("content-type".to_string(), "application/json".to_string()),
+            ],
+            body,
+        };
+        let resp = self.transport.send(req).await?.body;
+        let response: Response = serde_json::from_str(&resp)?;
+
+        match response {
+            Response::Completion {
+                candidates,
+                usage_metadata,
+            } => {
+                let candidate = candidates
+                    .into_iter()
+                    .next()
+                    .ok_or(Error::provider("Gemini returned no candidates".to_string()))?;
+                let llm_content: Vec<AssistantContent> = candidate
+                    .content
+                    .map(|c| c.parts)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, part)| map_gemini_part_to_llm(part, i))
+                    .collect();
+                let has_calls = llm_content
+                    .iter()
+                    .any(|c| matches!(c, AssistantContent::FunctionCall { .. }));
+                let stop_reason = match candidate.finish_reason.as_deref() {
+                    // Gemini reports function calls as a normal stop
+                    _ if has_calls => StopReason::FunctionCall,
+                    Some("STOP") => StopReason::EndTurn,
+                    Some("MAX_TOKENS") => StopReason::MaxTokens,
+                    other => StopReason::Other(other.unwrap_or("unknown").to_string()),
+                };
+                Ok(llm::Completion {
+                    usage: LlmUsage {
+                        input_tokens: usage_metadata.prompt_token_count,
+                        output_tokens: usage_metadata.candidates_token_count,
+                    },
+                    content: llm_content,
+                    stop_reason,
+                })
+            }
+            Response::Error { error } => Err(error.into()),
+        }
+    }
+
+    fn max_tokens(&self) -> Option<u32> {
+        Some(self.hyperparams.max_tokens)
+    }
+
+    fn pricing(&self) -> Option<Pricing> {
+        Some(match self.model {
+            GeminiVersion::OneDotFivePro => Pricing {
+                input: 1.25,
+                output: 5.0,
+            },
+            GeminiVersion::OneDotFiveFlash => Pricing {
+                input: 0.075,
+                output: 0.3,
+            },
+            GeminiVersion::TwoDotZeroFlash => Pricing {
+                input: 0.1,
+                output: 0.4,
+            },
+        })
+    }
+}
+
+fn map_llm_message_to_gemini(msg: &LlmMessage, names: &HashMap<&str, &str>) -> Content {
+    match msg {
+        LlmMessage::User(content) => Content {
+            role: "user".to_string(),
+            parts: content
+                .iter()
+                .map(|c| map_llm_user_content_to_gemini(c, names))
+                .collect(),
+        },
+        LlmMessage::Assistant(content) => Content {
+            role: "model".to_string(),
+            parts: content
+                .iter()
+                .filter_map(map_llm_assistant_content_to_gemini)
+                .collect(),
+        },
+    }
+}
+
+fn map_llm_user_content_to_gemini(content: &UserContent, names: &HashMap<&str, &str>) -> Part {
+    match content {
+        UserContent::Input(LlmContent::Text(text)) => Part::text(text),
+        UserContent::Input(LlmContent::Image { media_type, data }) => Part {
+            inline_data: Some(Blob {
+                mime_type: media_type.clone(),
+                data: data.clone(),
+            }),
+            ..Part::default()
+        },
+        UserContent::Input(content @ LlmContent::Json(_)) => Part::text(&content.to_string()),
+        UserContent::FunctionResult { id, result } => {
+            // Responses have to be JSON objects, so the text is wrapped in one. Images can't be
+            // nested in them, so they're only described.
+            let response = match result {
+                Ok(content) => json!({
+                    "content": content
+                        .iter()
+                        .map(LlmContent::to_string)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }),
+                Err(content) => json!({ "error": content.to_string() }),
+            };
+            Part {
+                function_response: Some(FunctionResponse {
+                    name: names.get(id.as_str()).unwrap_or(&id.as_str()).to_string(),
+                    response,
+                }),
+                ..Part::default()
+            }
+        }
+    }
+}
+
+fn map_llm_assistant_content_to_gemini(content: &AssistantContent) -> Option<Part> {
+    match content {
+        AssistantContent::Output(content) => Some(Part::text(&content.to_string())),
+        AssistantContent::Cited { text, .. } => Some(Part::text(text)),
+        AssistantContent::FunctionCall { name, input, .. } => Some(Part {
+            function_call: Some(FunctionCall {
+                name: name.clone(),
+                args: input.clone(),
+            }),
+            ..Part::default()
+        }),
+        // Reasoning from other providers (i.e after switching models) means nothing to Gemini
+        AssistantContent::Reasoning { .. } => None,
+    }
+}
+
+fn map_gemini_part_to_llm(part: Part, index: usize) -> Option<AssistantContent> {
+    if let Some(call) = part.function_call {
+        // Gemini doesn't give calls an ID, so we make up a unique one
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        return Some(AssistantContent::FunctionCall {
+            id: format!("gemini-{}-{}", nanos, index),
+            name: call.name,
+            input: call.args,
+        });
+    }
+    part.text
+        .map(|text| AssistantContent::Output(LlmContent::Text(text)))
+}
+
+// Gemini only understands a subset of OpenAPI schemas, so the schemas generated by schemars have
+// to be simplified: references are inlined, unsupported keywords are dropped, nullable types are
+// marked as such, and documented unit enums are flattened into plain string enums.
+fn map_schema_to_gemini(schema: Value) -> Value {
+    let definitions = schema.get("definitions").cloned().unwrap_or(Value::Null);
+    simplify_schema(schema, &definitions)
+}
+
+fn simplify_schema(schema: Value, definitions: &Value) -> Value {
+    let Value::Object(object) = schema else {
+        return schema;
+    };
+    if let Some(Value::String(reference)) = object.get("$ref") {
+        let name = reference.trim_start_matches("#/definitions/");
+        let definition = definitions.get(name).cloned().unwrap_or(Value::Null);
+        return simplify_schema(definition, definitions);
+    }
+    let mut out = Map::new();
+    for (key, value) in object {
+        match key.as_str() {
+            "$schema" | "title" | "definitions" | "additionalProperties" | "default" => {}
+            "type" => match value {
+                Value::Array(types) => {
+                    let types: Vec<Value> = types.into_iter().filter(|t| *t != "null").collect();
+                    out.insert("nullable".to_string(), Value::Bool(true));
+                    out.insert(
+                        "type".to_string(),
+                        types.into_iter().next().unwrap_or_default(),
+                    );
+                }
+                value => {
+                    out.insert(key, value);
+                }
+            },
+            "properties" => {
+                let properties = match value {
+                    Value::Object(properties) => properties
+                        .into_iter()
+                        .map(|(k, v)| (k, simplify_schema(v, definitions)))
+                        .collect(),
+                    _ => Map::new(),
+                };
+                out.insert(key, Value::Object(properties));
+            }
+            "oneOf" | "anyOf" | "allOf" => {
+                let variants: Vec<Value> = match value {
+                    Value::Array(variants) => variants
+                        .into_iter()
+                        .map(|v| simplify_schema(v, definitions))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                let values: Vec<Value> = variants
+                    .iter()
+                    .filter_map(|v| v.get("enum").and_then(|e| e.as_array()))
+                    .flatten()
+                    .cloned()
+                    .collect();
+                if variants.iter().all(|v| v.get("enum").is_some()) && !values.is_empty() {
+                    out.insert("type".to_string(), Value::String("string".to_string()));
+                    out.insert("enum".to_string(), Value::Array(values));
+                } else if variants.len() == 1 && key == "allOf" {
+                    if let Some(Value::Object(variant)) = variants.into_iter().next() {
+                        out.extend(variant);
+                    }
+                } else {
+                    out.insert("anyOf".to_string(), Value::Array(variants));
+                }
+            }
+            _ => {
+                out.insert(key, simplify_schema(value, definitions));
+            }
+        }
+    }
+    Value::Object(out)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<SystemInstruction>,
+    pub contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    pub generation_config: GenerationConfig,
+}
+
+#[derive(Serialize)]
+pub struct SystemInstruction {
+    pub parts: Vec<Part>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Content {
+    #[serde(default)]
+    pub role: String,
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<FunctionResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<Blob>,
+}
+
+impl Part {
+    fn text(text: &str) -> Self {
+        Self {
+            text: Some(text.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FunctionResponse {
+    pub name: String,
+    pub response: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Serialize)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    pub max_output_tokens: u32,
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Response {
+    Error {
+        error: ErrorInfo,
+    },
+    #[serde(rename_all = "camelCase")]
+    Completion {
+        #[serde(default)]
+        candidates: Vec<Candidate>,
+        #[serde(default)]
+        usage_metadata: UsageMetadata,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    pub content: Option<Content>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ErrorInfo {
+    message: String,
+    #[serde(default)]
+    status: String,
+}
+
+impl From<ErrorInfo> for Error {
+    fn from(error: ErrorInfo) -> Error {
+        // Gemini reports the gRPC status of the error
+        let kind = match error.status.as_str() {
+            "INVALID_ARGUMENT" | "FAILED_PRECONDITION" => ProviderErrorKind::InvalidRequest,
+            "UNAUTHENTICATED" => ProviderErrorKind::Authentication,
+            "PERMISSION_DENIED" => ProviderErrorKind::Permission,
+            "NOT_FOUND" => ProviderErrorKind::NotFound,
+            "RESOURCE_EXHAUSTED" => ProviderErrorKind::RateLimit,
+            "UNAVAILABLE" => ProviderErrorKind::Overloaded,
+            "INTERNAL" => ProviderErrorKind::Internal,
+            _ => ProviderErrorKind::Other,
+        };
+        Error::Provider {
+            kind,
+            message: error.message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_keep_their_status() {
+        let error: ErrorInfo = serde_json::from_str(
+            r#"{ "code": 429, "message": "Quota exceeded", "status": "RESOURCE_EXHAUSTED" }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Error::from(error),
+            Error::Provider {
+                kind: ProviderErrorKind::RateLimit,
+                ..
+            }
+        ));
+        let error: ErrorInfo = serde_json::from_str(r#"{ "message": "Unknown" }"#).unwrap();
+        assert!(matches!(
+            Error::from(error),
+            Error::Provider {
+                kind: ProviderErrorKind::Other,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+mod api;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::core::Error;
+use crate::core::llm::{Hyperparams, Model, ModelInfo, Provider};
+use crate::core::transport::{HttpTransport, ReqwestTransport};
+use crate::gemini::api::GeminiModel;
+
+/// An implementation of the `Provider` trait for Google's Gemini models.
+///
+/// Gemini has no provider tools, so only local tools can be used with these models.
+#[derive(Clone)]
+pub struct Gemini {
+    transport: Arc<dyn HttpTransport>,
+    api_key: String,
+}
+
+impl Gemini {
+    /// Create a new Gemini client with the given API key.
+    pub fn new(api_key: String) -> Self {
+        Self {
+            api_key,
+            transport: Arc::new(ReqwestTransport::new()),
+        }
+    }
+
+    /// Send all requests through the given transport instead of directly over the network.
+    pub fn transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.transport = transport;
+        self
+    }
+}
+
+impl Provider<GeminiVersion> for Gemini {
+    async fn obtain(
+        &self,
+        model: GeminiVersion,
+        system_prompt: impl IntoIterator<Item = impl AsRef<str>>,
+        hyperparams: Hyperparams,
+    ) -> impl Model {
+        GeminiModel::new(
+            self.transport.clone(),
+            self.api_key.clone(),
+            model,
+            system_prompt
+                .into_iter()
+                .map(|s| s.as_ref().to_string())
+                .collect(),
+            hyperparams,
+        )
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, Error> {
+        Ok(GeminiVersion::ALL
+            .iter()
+            .map(|v| ModelInfo {
+                id: v.to_string(),
+                display_name: None,
+            })
+            .collect())
+    }
+}
+
+/// The versions of Gemini, Google's flagship LLM.
+#[derive(Clone, Copy, Debug)]
+pub enum GeminiVersion {
+    /// Gemini 1.5 Pro.
+    OneDotFivePro,
+    /// Gemini 1.5 Flash.
+    OneDotFiveFlash,
+    /// Gemini 2.0 Flash.
+    TwoDotZeroFlash,
+}
+
+impl GeminiVersion {
+    /// All known Gemini versions.
+    pub const ALL: [GeminiVersion; 3] = [
+        GeminiVersion::OneDotFivePro,
+        GeminiVersion::OneDotFiveFlash,
+        GeminiVersion::TwoDotZeroFlash,
+    ];
+}
+
+impl fmt::Display for GeminiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            GeminiVersion::OneDotFivePro => "gemini-1.5-pro",
+            GeminiVersion::OneDotFiveFlash => "gemini-1.5-flash",
+            GeminiVersion::TwoDotZeroFlash => "gemini-2.0-flash",
+        })
+    }
+}
+
+impl FromStr for GeminiVersion {
+    type Err = String;
+
+    /// Parse a Gemini version from its model ID, i.e `gemini-2.0-flash`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GeminiVersion::ALL
+            .into_iter()
+            .find(|v| v.to_string() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown model '{}'. Available models: {}",
+                    s,
+                    GeminiVersion::ALL.map(|v| v.to_string()).join(", ")
+                )
+            })
+    }
+}
@@ -1,74 +1,1146 @@
 mod anthropic;
+mod config;
 mod core;
+mod gemini;
+mod tools;
 
-use anthropic::{Anthropic, AnthropicModel};
+use anthropic::{
+    Anthropic, Claude, EditHistoryTool, EditorConfig, LocalEditor, Review, Reviewer,
+    batch::{BatchRequest, BatchStatus},
+};
 use colored::*;
+use config::Config;
 use core::{
-    agent::Agent,
-    llm::{Hyperparams, Provider},
-    tool::Toolbox,
+    Error,
+    agent::{
+        Agent, BatchReviewer, DEFAULT_CONCURRENCY, DEFAULT_MAX_TOOL_FAILURES, Mode, PendingCall,
+        Verbosity, pending_calls,
+    },
+    audit::AuditLog,
+    fallback::Fallback,
+    history::FileHistory,
+    limiter::SharedLimiter,
+    llm::{
+        AssistantContent, BoxedModel, Content, Hyperparams, Message, Model, Provider,
+        ReasoningEffort, TimedMessage, UserContent,
+    },
+    prompt::{PromptTime, SystemPrompt},
+    redact::{self, Redactor},
+    routing::{Heuristic, RoutingModel},
+    tool::{ProviderTool, Toolbox},
+    transport::{
+        HttpTransport, RecordingTransport, ReplayTransport, ReqwestTransport, VOLATILE_FIELDS,
+    },
+};
+use gemini::{Gemini, GeminiVersion};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tools::{
+    ask_user::AskUser, cargo::Cargo, config_tool::ConfigTool, env::Env, format::Format,
+    github::GitHubIssue, manual::Manual, memory::Memory, scratch::Scratch, search::WebSearch,
+    symbols::Symbols, view_files::ViewFiles,
 };
-use std::io::Write;
 
-fn get_system_prompt() -> String {
-    let os_name = std::env::consts::OS;
-    let shell = std::env::var("SHELL").unwrap_or_else(|_| String::from("unknown"));
-    let home_dir = dirs::home_dir()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-    let current_dir = std::env::current_dir()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::any::Any>> {
+    // A .env is optional, but one that can't be read is worth knowing about
+    if let Err(e) = dotenv::dotenv()
+        && !e.not_found()
+    {
+        println!("{}: could not load .env: {}", "err".red(), e);
+    }
+    env_logger::init();
+    let config = Config::load().unwrap_or_else(|e| {
+        println!("{}: {}, using the defaults", "err".red(), e);
+        Config::default()
+    });
+    if let Some(workspace) = &config.workspace
+        && let Err(e) = std::env::set_current_dir(workspace)
+    {
+        println!(
+            "{}: could not work in {}: {}",
+            "err".red(),
+            workspace.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+    let mut transport: Arc<dyn HttpTransport> = Arc::new(ReqwestTransport::new());
+    if let Ok(path) = std::env::var("ASIMOV_REPLAY") {
+        let replay = ReplayTransport::load(&path, &VOLATILE_FIELDS).unwrap_or_else(|e| {
+            println!(
+                "{}: could not load the recordings in {}: {}",
+                "err".red(),
+                path,
+                e
+            );
+            std::process::exit(1);
+        });
+        transport = Arc::new(replay);
+    } else if let Ok(path) = std::env::var("ASIMOV_RECORD") {
+        transport = Arc::new(RecordingTransport::new(transport, path));
+    }
+    let betas = std::env::var("ANTHROPIC_BETA").unwrap_or_default();
+    let editor_config = EditorConfig {
+        dry_run: std::env::args().any(|a| a == "--dry-run"),
+        ..EditorConfig::default()
+    };
+    // Reviewing a whole response at once replaces reviewing each edit
+    let review_batch = std::env::args().any(|a| a == "--review-batch");
+    let review = std::env::args().any(|a| a == "--review") && !review_batch;
+    let model_id = flag_value("--model")
+        .or_else(|| std::env::var("ASIMOV_MODEL").ok())
+        .or(config.model.clone());
+    let choice = model_id.as_deref().map(|id| {
+        choose_model(id).unwrap_or_else(|e| {
+            println!("{}: {}", "err".red(), e);
+            std::process::exit(1);
+        })
+    });
+    let gemini = match choice {
+        Some(ModelChoice::Gemini(version)) => Some(version),
+        _ => None,
+    };
+    let api_key = match std::env::var("ANTHROPIC_API_KEY") {
+        Ok(api_key) => api_key,
+        // Anthropic is only needed to switch to Claude with /model later on
+        Err(_) if gemini.is_some() => String::new(),
+        Err(_) => {
+            println!(
+                "{}: no API key, set ANTHROPIC_API_KEY or add it to a .env file",
+                "err".red()
+            );
+            std::process::exit(1);
+        }
+    };
+    let mut anthropic = Anthropic::new(api_key)
+        .transport(transport.clone())
+        .betas(betas.split(',').map(str::trim).filter(|b| !b.is_empty()))
+        .editor(editor_config);
+    if review {
+        anthropic = anthropic.review(review_edit);
+    }
+    if let Some(base_url) = std::env::var("ANTHROPIC_BASE_URL").ok().or(config.base_url) {
+        anthropic = anthropic.base_url(base_url);
+    }
+    if let Some(bytes) = config.max_request_bytes {
+        anthropic = anthropic.max_request_bytes(bytes);
+    }
+    if let Some(user_id) = &config.user_id {
+        anthropic = anthropic.user_id(user_id);
+    }
+    if let Some(headers) = config.headers.clone() {
+        anthropic = anthropic.headers(headers);
+    }
+    anthropic = anthropic.token_efficient_tools(config.token_efficient_tools.unwrap_or(false));
+    anthropic = anthropic.custom_models_think(config.custom_models_think.unwrap_or(false));
+    if let Some(requests) = config.requests_per_minute {
+        anthropic = anthropic.rate_limit(SharedLimiter::per_minute(requests));
+    }
+    let memory = Memory::in_workspace();
+    let mut system_prompt = SystemPrompt::default();
+    if let Some(persona) = &config.persona {
+        system_prompt = system_prompt.persona(persona);
+    }
+    if config.prompt_time == Some(false) {
+        system_prompt = system_prompt.time(None);
+    } else if let Some(secs) = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+    {
+        // A fixed time keeps the prompt the same between runs, i.e to replay a recorded session
+        let time = UNIX_EPOCH + Duration::from_secs(secs);
+        system_prompt = system_prompt.time(Some(PromptTime::Fixed(time)));
+    }
+    if let Some(notes) = memory.recall() {
+        system_prompt = system_prompt.section("MEMORY FROM PREVIOUS SESSIONS", notes);
+    }
+    if std::env::args().any(|a| a == "--index") || config.index.unwrap_or(false) {
+        let root = std::env::current_dir().unwrap();
+        system_prompt = system_prompt.section("PROJECT FILES", tools::index::overview(&root, 200));
+    }
+    // Without a system prompt, the model gets nothing but the conversation and the tools
+    let system_prompt =
+        (!std::env::args().any(|a| a == "--no-system-prompt")).then(|| system_prompt.build());
+    let max_tokens = config.max_tokens.unwrap_or(1024);
+    let reasoning_effort = match flag_value("--reasoning") {
+        Some(effort) => Some(effort.parse::<ReasoningEffort>().unwrap_or_else(|e| {
+            println!("{}: {}", "err".red(), e);
+            std::process::exit(1);
+        })),
+        None => config.reasoning_effort,
+    };
+    let hyperparams = if std::env::args().any(|a| a == "--deterministic") {
+        Hyperparams {
+            reasoning_effort,
+            ..Hyperparams::deterministic(max_tokens)
+        }
+    } else {
+        Hyperparams {
+            max_tokens,
+            temperature: config.temperature.unwrap_or(0.6),
+            seed: None,
+            reasoning_effort,
+        }
+    };
+    let gemini_key = std::env::var("GEMINI_API_KEY").ok();
+    let has_gemini_key = gemini_key.is_some();
+    if gemini.is_some() && !has_gemini_key {
+        println!(
+            "{}: no API key, set GEMINI_API_KEY or add it to a .env file",
+            "err".red()
+        );
+        std::process::exit(1);
+    }
+    let google = Gemini::new(gemini_key.unwrap_or_default()).transport(transport);
+    // Batches are only supported by Anthropic, so they're sent to the last Claude model used
+    let mut batch_model = None;
+    // What unknown models spend can't be counted towards the budget
+    let mut unpriced = Vec::new();
+    let model = match choice {
+        Some(ModelChoice::Gemini(version)) => {
+            let model = google
+                .obtain(version, system_prompt.clone(), hyperparams)
+                .await;
+            BoxedModel::new(model)
+        }
+        choice => {
+            let claude = match choice {
+                Some(ModelChoice::Claude(claude)) => claude,
+                _ => Claude::ThreeDotSevenSonnet,
+            };
+            batch_model = Some(claude.clone());
+            let model = anthropic
+                .obtain(claude.clone(), system_prompt.clone(), hyperparams)
+                .await;
+            if model.pricing().is_none() {
+                unpriced.push(claude.to_string());
+            }
+            // Short prompts can go to a cheaper model, with the chosen one handling the rest
+            match flag_value("--simple-model") {
+                Some(id) => {
+                    let simple = claude_model(&id).unwrap_or_else(|e| {
+                        println!("{}: {}", "err".red(), e);
+                        std::process::exit(1);
+                    });
+                    let simple_model = anthropic
+                        .obtain(simple.clone(), system_prompt.clone(), hyperparams)
+                        .await;
+                    if simple_model.pricing().is_none() {
+                        unpriced.push(simple.to_string());
+                    }
+                    BoxedModel::new(
+                        RoutingModel::new(Heuristic::default())
+                            .route(simple.to_string(), simple_model)
+                            .route(claude.to_string(), model),
+                    )
+                }
+                None => BoxedModel::new(model),
+            }
+        }
+    };
+    // Calls that fail because the model is overloaded or rate-limited move on to the next model
+    let fallback_ids = match flag_values("--fallback-model") {
+        ids if ids.is_empty() => config.fallback_models.clone().unwrap_or_default(),
+        ids => ids,
+    };
+    let model = if fallback_ids.is_empty() {
+        model
+    } else {
+        let name = model_id
+            .clone()
+            .unwrap_or_else(|| Claude::ThreeDotSevenSonnet.to_string());
+        let mut chain = Fallback::new(name, model);
+        for id in fallback_ids {
+            let fallback = match choose_model(&id) {
+                Ok(ModelChoice::Gemini(_)) if !has_gemini_key => {
+                    println!(
+                        "{}: no API key, set GEMINI_API_KEY or add it to a .env file",
+                        "err".red()
+                    );
+                    std::process::exit(1);
+                }
+                Ok(ModelChoice::Gemini(version)) => BoxedModel::new(
+                    google
+                        .obtain(version, system_prompt.clone(), hyperparams)
+                        .await,
+                ),
+                Ok(ModelChoice::Claude(claude)) => BoxedModel::new(
+                    anthropic
+                        .obtain(claude, system_prompt.clone(), hyperparams)
+                        .await,
+                ),
+                Err(e) => {
+                    println!("{}: {}", "err".red(), e);
+                    std::process::exit(1);
+                }
+            };
+            if fallback.pricing().is_none() {
+                unpriced.push(id.clone());
+            }
+            chain = chain.then(id, fallback);
+        }
+        BoxedModel::new(chain)
+    };
+    let budget_usd = std::env::var("ASIMOV_BUDGET_USD")
+        .ok()
+        .and_then(|b| b.parse().ok())
+        .or(config.budget_usd);
+    warn_unpriced(budget_usd, &unpriced);
+    // The editor runs as a local tool for models that don't have Anthropic's built in, or always
+    // if asked to
+    let local_editor =
+        std::env::args().any(|a| a == "--local-editor") || config.local_editor.unwrap_or(false);
+    let reviewer = review.then(|| Arc::new(review_edit) as Reviewer);
+    let editor = LocalEditor::new(editor_config, reviewer, anthropic.edit_history());
+    let (provided_editor, provider_tools): (Vec<_>, Vec<_>) = model
+        .provider_tools()
+        .into_iter()
+        .partition(|tool| tool.name() == "str_replace_editor");
+    let toolbox = Toolbox::new().provided_all(provider_tools);
+    let toolbox = match provided_editor.into_iter().next() {
+        Some(provided) if !local_editor => toolbox.provided_or_local(provided, editor),
+        _ => toolbox.local(editor),
+    };
+    let toolbox = toolbox
+        .local(EditHistoryTool::new(anthropic.edit_history()))
+        .local(memory)
+        .local(Env)
+        .local(Manual)
+        .local(Cargo)
+        .local(Scratch::new(
+            config.interpreters.clone().unwrap_or_default(),
+        ))
+        .local(Format::new(
+            config.formatter.clone(),
+            anthropic.edit_history(),
+        ))
+        .local(GitHubIssue::new(
+            std::env::var("GITHUB_TOKEN")
+                .ok()
+                .or(config.github_token.clone()),
+        ))
+        .local(Symbols::new(std::env::current_dir().unwrap()))
+        .local(ViewFiles::new(editor_config))
+        .local(WebSearch::new(
+            config
+                .search_endpoint
+                .as_deref()
+                .unwrap_or(tools::search::DEFAULT_ENDPOINT),
+            config.search_api_key.clone(),
+        ));
+    let toolbox = if std::env::args().any(|a| a == "--ask") {
+        toolbox.local(AskUser::new(std::io::stdin().is_terminal()))
+    } else {
+        toolbox
+    };
+    #[cfg(feature = "clipboard")]
+    let toolbox = toolbox.local(tools::clipboard::Clipboard::new());
+    #[cfg(feature = "sql")]
+    let toolbox = match &config.database {
+        Some(url) => toolbox.local(tools::sql::Sql::new(
+            url,
+            config.database_writes.unwrap_or(false),
+        )),
+        None => toolbox,
+    };
+    let mut toolbox = toolbox;
+    for definition in config.custom_tools.clone().unwrap_or_default() {
+        if toolbox.tools().any(|(name, _)| name == definition.name) {
+            println!(
+                "{}: a tool named '{}' already exists",
+                "err".red(),
+                definition.name
+            );
+            continue;
+        }
+        match ConfigTool::new(definition) {
+            Ok(tool) => toolbox = toolbox.dynamic(tool),
+            Err(e) => println!("{}: {}", "err".red(), e),
+        }
+    }
+    if let Some(enabled) = &config.tools {
+        let names: Vec<String> = toolbox.tools().map(|(n, _)| n.to_string()).collect();
+        for name in enabled.iter().filter(|n| !names.contains(n)) {
+            println!("{}: no tool named '{}'", "err".red(), name);
+        }
+        for name in names.iter().filter(|n| !enabled.contains(n)) {
+            toolbox.disable(name);
+        }
+    }
+    if let Some(descriptions) = config.tool_descriptions.clone() {
+        let names: Vec<String> = toolbox.tools().map(|(n, _)| n.to_string()).collect();
+        for name in descriptions.keys().filter(|n| !names.contains(n)) {
+            println!("{}: no tool named '{}'", "err".red(), name);
+        }
+        toolbox = toolbox.descriptions(descriptions);
+    }
+    if let Some(formats) = config.tool_formats.clone() {
+        let names: Vec<String> = toolbox.tools().map(|(n, _)| n.to_string()).collect();
+        for name in formats.keys().filter(|n| !names.contains(n)) {
+            println!("{}: no tool named '{}'", "err".red(), name);
+        }
+        toolbox = toolbox.formats(formats);
+    }
+    if let Some(lean) = config.lean_schemas {
+        toolbox = toolbox.lean_schemas(lean);
+    }
+    let verbosity = if std::env::args().any(|a| a == "--quiet" || a == "-q") {
+        Verbosity::Quiet
+    } else if std::env::args().any(|a| a == "--verbose" || a == "-v") {
+        Verbosity::Verbose
+    } else {
+        config.verbosity.unwrap_or_default()
+    };
+    let audit_log = config
+        .audit_log
+        .as_ref()
+        .and_then(|path| match AuditLog::open(path) {
+            Ok(audit_log) => Some(audit_log),
+            Err(e) => {
+                println!("{}: could not open the audit log: {}", "err".red(), e);
+                None
+            }
+        });
+    let builder = Agent::builder(model, toolbox)
+        .verbosity(verbosity)
+        .markdown(std::env::args().any(|a| a == "--markdown") || config.markdown.unwrap_or(false))
+        .concurrency_limit(config.concurrency.unwrap_or(DEFAULT_CONCURRENCY))
+        .max_tool_failures(
+            config
+                .max_tool_failures
+                .map_or(Some(DEFAULT_MAX_TOOL_FAILURES), |max| {
+                    (max > 0).then_some(max)
+                }),
+        )
+        .mode(if std::env::args().any(|a| a == "--plan") {
+            Mode::Plan
+        } else {
+            Mode::Act
+        })
+        .batch_reviewer(review_batch.then(|| Box::new(review_calls) as BatchReviewer))
+        // Piped output is printed a whole turn at a time, so that it isn't garbled
+        .buffered(std::env::args().any(|a| a == "--buffered") || !std::io::stdout().is_terminal())
+        .budget_usd(budget_usd)
+        .audit_log(audit_log)
+        .redactor(
+            config
+                .redact
+                .iter()
+                .flatten()
+                .fold(Redactor::default(), |redactor, secret| {
+                    redactor.pattern(redact::literal(secret.clone()))
+                }),
+        );
+    let resume = flag_value("--resume");
+    if resume.is_some() && std::env::var("ASIMOV_HISTORY").is_ok() {
+        println!(
+            "{}: --resume can't be used with ASIMOV_HISTORY, which picks up its own history",
+            "err".red()
+        );
+        std::process::exit(1);
+    }
+    // Any calls the saved session was in the middle of are only run if the user says so, as with
+    // /load
+    let mut agent = match resume.map(|path| load_history(&path)) {
+        None => builder.build(),
+        Some(Ok(history)) if ask_resume(&history) => match builder.resume(history).await {
+            Ok((agent, _)) => agent,
+            Err(e) => {
+                println!("{}: could not resume the session: {}", "err".red(), e);
+                std::process::exit(1);
+            }
+        },
+        Some(Ok(history)) => {
+            let mut agent = builder.build();
+            if let Err(e) = agent.set_history(history) {
+                println!("{}: could not load the session: {}", "err".red(), e);
+                std::process::exit(1);
+            }
+            agent
+        }
+        Some(Err(e)) => {
+            println!("{}: could not load the session: {}", "err".red(), e);
+            std::process::exit(1);
+        }
+    };
+    if let Ok(path) = std::env::var("ASIMOV_HISTORY") {
+        agent
+            .set_history_store(FileHistory::open(path).unwrap())
+            .unwrap();
+    }
+    // Set with /prefill, and used for the next prompt
+    let mut prefill: Option<String> = None;
+    loop {
+        print!("{} ", "you:".blue());
+        std::io::stdout().flush().unwrap();
+        // Stdin ended, i.e with Ctrl-D or at the end of piped input
+        let Some(input) = read_input() else {
+            println!();
+            break;
+        };
+        if input.trim().is_empty() {
+            continue;
+        }
+        let command = match Command::parse(&input) {
+            Some(Ok(command)) => command,
+            Some(Err(e)) => {
+                println!("{}: {}", "err".red(), e);
+                continue;
+            }
+            None => {
+                let start = Instant::now();
+                let files = mentioned_files(&input);
+                let result = match (prefill.take(), files.is_empty()) {
+                    (Some(text), true) => agent.go_with_prefill(input.to_string(), text).await,
+                    (Some(text), false) => {
+                        prefill = Some(text);
+                        println!(
+                            "{}: files can't be attached to a prefilled prompt",
+                            "err".red()
+                        );
+                        continue;
+                    }
+                    (None, true) => agent.go(input.to_string()).await,
+                    (None, false) => agent.go_with_files(input.to_string(), &files).await,
+                };
+                match result {
+                    Ok(_) if verbosity == Verbosity::Quiet => {}
+                    Ok(usage) => {
+                        let summary = format!(
+                            "[{} input tokens, {} output tokens, {:.1}s]",
+                            usage.input_tokens,
+                            usage.output_tokens,
+                            start.elapsed().as_secs_f64()
+                        );
+                        println!("{}", summary.dimmed());
+                    }
+                    Err(e) => println!("{}: {}", "err".red(), e),
+                }
+                continue;
+            }
+        };
+        match command {
+            Command::Reset => match agent.reset() {
+                Ok(()) => println!("{}: history cleared", "session".blue()),
+                Err(e) => println!("{}: {}", "err".red(), e),
+            },
+            Command::Save(path) => match save_history(&agent.history(), path) {
+                Ok(()) => println!("{}: saved to {}", "session".blue(), path),
+                Err(e) => println!("{}: {}", "err".red(), e),
+            },
+            // Any calls the saved session was in the middle of are only run if the user says so,
+            // since the file could come from anywhere
+            Command::Load(path) => match load_history(path) {
+                Ok(history) => {
+                    let resumed = if ask_resume(&history) {
+                        agent.resume_history(history).await.map(|_| ())
+                    } else {
+                        agent.set_history(history)
+                    };
+                    match resumed {
+                        Ok(()) => println!("{}: loaded from {}", "session".blue(), path),
+                        Err(e) => println!("{}: {}", "err".red(), e),
+                    }
+                }
+                Err(e) => println!("{}: {}", "err".red(), e),
+            },
+            Command::Usage => {
+                let usage = agent.usage();
+                println!(
+                    "{}: {} input tokens, {} output tokens, ${:.4}",
+                    "usage".blue(),
+                    usage.input_tokens,
+                    usage.output_tokens,
+                    agent.spent_usd()
+                );
+            }
+            Command::Stats => {
+                for (name, stats) in agent.tool_stats() {
+                    println!(
+                        "{}: {} calls, {} errors, {} bytes",
+                        name.blue(),
+                        stats.calls(),
+                        stats.errors,
+                        stats.bytes
+                    );
+                }
+            }
+            Command::Estimate(prompt) => match agent.estimate(prompt).await {
+                Ok(Some(estimate)) => {
+                    let output = estimate
+                        .max_output_tokens
+                        .map(|max| format!("0-{}", max))
+                        .unwrap_or_else(|| "an unknown number of".to_string());
+                    let cost = estimate
+                        .cost_usd
+                        .map(|(low, high)| format!(", ${:.4}-${:.4}", low, high))
+                        .unwrap_or_default();
+                    println!(
+                        "{}: {} input tokens, {} output tokens{} for the first request",
+                        "estimate".blue(),
+                        estimate.input_tokens,
+                        output,
+                        cost
+                    );
+                    println!(
+                        "{}: every round of tool calls sends another request, so the task will cost more",
+                        "estimate".blue()
+                    );
+                }
+                Ok(None) => println!("{}: this model can't count tokens", "err".red()),
+                Err(e) => println!("{}: {}", "err".red(), e),
+            },
+            Command::History => print_history(&agent.history()),
+            Command::Export(path) => match std::fs::write(path, agent.export_markdown()) {
+                Ok(()) => println!("{}: exported to {}", "session".blue(), path),
+                Err(e) => println!("{}: {}", "err".red(), e),
+            },
+            Command::Tools("") => print_tools(agent.toolbox()),
+            Command::Tools(name) => match toggle_tool(agent.toolbox_mut(), name) {
+                Ok(()) => print_tools(agent.toolbox()),
+                Err(e) => println!("{}: {}", "err".red(), e),
+            },
+            Command::Model(name) => {
+                // Provider tools are versioned per-model, so they need to be swapped out too.
+                match choose_model(name) {
+                    Ok(ModelChoice::Gemini(_)) if !has_gemini_key => println!(
+                        "{}: no API key, set GEMINI_API_KEY or add it to a .env file",
+                        "err".red()
+                    ),
+                    Ok(ModelChoice::Gemini(version)) => {
+                        let model = google
+                            .obtain(version, system_prompt.clone(), hyperparams)
+                            .await;
+                        agent.set_model(BoxedModel::new(model));
+                        println!("{}: switched to {}", "model".blue(), version);
+                    }
+                    Ok(ModelChoice::Claude(claude)) => {
+                        let model = anthropic
+                            .obtain(claude.clone(), system_prompt.clone(), hyperparams)
+                            .await;
+                        for tool in model.provider_tools() {
+                            if local_editor && tool.name() == "str_replace_editor" {
+                                continue;
+                            }
+                            agent.toolbox_mut().replace_provided(tool);
+                        }
+                        if model.pricing().is_none() {
+                            warn_unpriced(budget_usd, &[claude.to_string()]);
+                        }
+                        agent.set_model(BoxedModel::new(model));
+                        batch_model = Some(claude.clone());
+                        println!("{}: switched to {}", "model".blue(), claude);
+                    }
+                    Err(e) => println!("{}: {}", "err".red(), e),
+                }
+            }
+            Command::Models => match anthropic.list_models().await {
+                Ok(models) => {
+                    for model in models {
+                        // Any model can be switched to with /model, but the costs of those unknown
+                        // to this build aren't tracked
+                        let status = match model.id.parse::<Claude>() {
+                            Ok(_) => "known".green(),
+                            Err(_) => "custom".dimmed(),
+                        };
+                        let name = model.display_name.unwrap_or_default();
+                        println!("{}: {} {} [{}]", "model".blue(), model.id, name, status);
+                    }
+                }
+                Err(e) => println!("{}: {}", "err".red(), e),
+            },
+            Command::Batch(args) => match &batch_model {
+                Some(claude) => {
+                    let requests = BatchPrompts {
+                        model: claude.clone(),
+                        system_prompt: system_prompt.iter().cloned().collect(),
+                        hyperparams,
+                    };
+                    if let Err(e) = batch(&anthropic, requests, args).await {
+                        println!("{}: {}", "err".red(), e);
+                    }
+                }
+                None => println!(
+                    "{}: batches can only be sent to Claude, switch to it with /model first",
+                    "err".red()
+                ),
+            },
+            Command::Plan if agent.mode() == Mode::Plan => {
+                println!(
+                    "{}: already planning, enter /act to run every tool",
+                    "mode".blue()
+                );
+            }
+            Command::Plan => {
+                agent.set_mode(Mode::Plan);
+                println!("{}: planning, only read-only tools will run", "mode".blue());
+            }
+            Command::Act if agent.mode() == Mode::Act => {
+                println!("{}: already acting, every tool will run", "mode".blue());
+            }
+            Command::Act => {
+                agent.set_mode(Mode::Act);
+                println!("{}: acting, every tool will run", "mode".blue());
+            }
+            Command::Prefill(text) => {
+                println!(
+                    "{}: the next answer starts with {:?}",
+                    "prefill".blue(),
+                    text
+                );
+                prefill = Some(text.to_string());
+            }
+            Command::Quit => break,
+        }
+    }
+    Ok(())
+}
 
-    // This is just cline's system prompt, minus the custom tool calling (we assume the LLM can call tools without any coaxing)
-    format!(
-        "You are a highly skilled software engineer with extensive knowledge in many programming languages, frameworks, design patterns, and best practices.
+/// Show the diff of an edit the agent wants to make and ask the user whether to write it. Anything
+/// other than yes or no is taken as a rejection, and passed on to the model as feedback.
+fn review_edit(path: &Path, diff: &str) -> Review {
+    // Tools can run in parallel, so make sure only one review is on screen at a time.
+    static REVIEWING: Mutex<()> = Mutex::new(());
+    let _guard = REVIEWING.lock().unwrap();
+    println!("{}: {}", "review".yellow(), path.display());
+    print_diff(diff);
+    match ask_apply() {
+        Ok(()) => Review::Accept,
+        Err(feedback) => Review::Reject(feedback),
+    }
+}
 
-SYSTEM INFORMATION:
+/// Show every change the agent wants to make in a response and ask the user whether to make all
+/// of them. Calls without a preview (i.e commands) are shown as their input.
+fn review_calls(calls: &[PendingCall]) -> Result<(), String> {
+    println!(
+        "{}: {} changes",
+        "review".yellow(),
+        calls.len().to_string().bold()
+    );
+    for call in calls {
+        match &call.preview {
+            Some(preview) => {
+                println!("{}", call.name.bold());
+                print_diff(preview);
+            }
+            None => println!("{} {}", call.name.bold(), call.input),
+        }
+    }
+    ask_apply()
+}
 
-Operating System: {}
-Default Shell: {}
-Home Directory: {}
-Current Working Directory: {}
+fn print_diff(diff: &str) {
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
 
-OBJECTIVE
+// Anything other than yes or no is feedback for the model.
+fn ask_apply() -> Result<(), String> {
+    print!("{} ", "apply? [y/n/feedback]".yellow());
+    std::io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).unwrap();
+    match answer.trim() {
+        "" | "y" | "yes" => Ok(()),
+        "n" | "no" => Err("No reason was given.".to_string()),
+        feedback => Err(feedback.to_string()),
+    }
+}
 
-You accomplish a given task iteratively, breaking it down into clear steps and working through them methodically.
+// Calls that aren't resumed are marked as interrupted instead.
+fn ask_resume(history: &[TimedMessage]) -> bool {
+    let pending = pending_calls(history);
+    if pending.is_empty() {
+        return false;
+    }
+    println!(
+        "{}: the session was saved in the middle of {} calls:",
+        "session".blue(),
+        pending.len()
+    );
+    for (_, name, input) in &pending {
+        println!("  {} {}", name.red(), input.to_string().dimmed());
+    }
+    print!("{} ", "run them? [y/n]".yellow());
+    std::io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).unwrap();
+    matches!(answer.trim(), "y" | "yes")
+}
 
-1. Analyze the user's task and set clear, achievable goals to accomplish it. Prioritize these goals in a logical order.
-2. Work through these goals sequentially, utilizing available tools one at a time as necessary. Each goal should correspond to a distinct step in your problem-solving process. You will be informed on the work completed and what's remaining as you go.
-3. The user may provide feedback, which you can use to make improvements and try again. But DO NOT continue in pointless back and forth conversations, i.e. don't end your responses with questions or offers for further assistance.",
-        os_name,
-        shell,
-        home_dir,
-        current_dir
-    )
+/// The files mentioned in a prompt as `@path`, which are attached to it. Anything after an `@`
+/// that isn't a file (i.e an email address or a decorator) is left alone.
+fn mentioned_files(input: &str) -> Vec<PathBuf> {
+    input
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|path| PathBuf::from(path.trim_end_matches([',', '.', ';', ':', '!', '?', ')'])))
+        .filter(|path| path.is_file())
+        .collect()
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::any::Any>> {
-    dotenv::dotenv().unwrap();
-    env_logger::init();
-    let anthropic = Anthropic::new(std::env::var("ANTHROPIC_API_KEY").unwrap());
-    let model = anthropic
-        .obtain(
-            anthropic::Claude::ThreeDotSevenSonnet,
-            Some(get_system_prompt()),
-            Hyperparams {
-                max_tokens: 1024,
-                temperature: 0.6,
-            },
-        )
-        .await;
-    let toolbox = Toolbox::new().provided(model.editor());
-    let mut agent = Agent::new(model, toolbox);
+/// Warn that what the given models spend isn't counted towards the budget, if there is one, since
+/// their pricing isn't known.
+fn warn_unpriced(budget_usd: Option<f64>, models: &[String]) {
+    if let Some(budget) = budget_usd
+        && !models.is_empty()
+    {
+        println!(
+            "{}: the pricing of {} isn't known, so what it spends isn't counted towards the ${:.2} \
+            budget",
+            "budget".yellow(),
+            models.join(", "),
+            budget
+        );
+    }
+}
+
+/// A model picked by its ID, which is done the same way for `--model` and `/model`.
+enum ModelChoice {
+    Claude(Claude),
+    Gemini(GeminiVersion),
+}
+
+/// Pick the model with the given ID. Gemini's models are picked by their own IDs, and anything
+/// else is taken to be a Claude model.
+fn choose_model(id: &str) -> Result<ModelChoice, String> {
+    match id.parse::<GeminiVersion>() {
+        Ok(version) => Ok(ModelChoice::Gemini(version)),
+        Err(_) => claude_model(id).map(ModelChoice::Claude),
+    }
+}
+
+/// The Claude model with the given ID. IDs this build doesn't know are sent to Anthropic as is,
+/// with a warning listing the known models, since a typo would otherwise only fail on the next
+/// request.
+fn claude_model(id: &str) -> Result<Claude, String> {
+    let claude = Claude::custom(id)?;
+    if let Claude::Custom(id) = &claude {
+        let known: Vec<String> = Claude::ALL
+            .iter()
+            .map(ToString::to_string)
+            .chain(GeminiVersion::ALL.iter().map(ToString::to_string))
+            .collect();
+        println!(
+            "{}: '{}' isn't a known model, so it's sent to Anthropic as is. Known models: {}",
+            "model".yellow(),
+            id,
+            known.join(", ")
+        );
+    }
+    Ok(claude)
+}
+
+/// The value following the given command-line flag, i.e `--model <id>`.
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|a| a != flag);
+    args.next()?;
+    args.next()
+}
+
+/// The values following every use of a command-line flag that can be given more than once.
+fn flag_values(flag: &str) -> Vec<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+/// A line that opens or closes a multi-line block of input.
+const FENCE: &str = "\"\"\"";
+
+/// Read a prompt from stdin. Normally this is a single line, but a line ending in `\` continues
+/// onto the next line, and a line containing only `"""` starts a block that runs until the next
+/// `"""` line, which is handy for pasting code or error traces. Returns `None` once stdin has
+/// ended.
+fn read_input() -> Option<String> {
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    // A read error is treated like the end of input, since no more can be read either way
+    if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+        return None;
+    }
+    let mut input = String::new();
+    if line.trim() == FENCE {
+        loop {
+            line.clear();
+            prompt_continuation();
+            if stdin.read_line(&mut line).unwrap_or(0) == 0 || line.trim() == FENCE {
+                return Some(input);
+            }
+            input.push_str(&line);
+        }
+    }
     loop {
-        print!("{} ", "you:".blue());
-        std::io::stdout().flush().unwrap();
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        agent.go(input.to_string()).await.unwrap();
+        match line.trim_end().strip_suffix('\\') {
+            Some(continued) => {
+                input.push_str(continued);
+                input.push('\n');
+            }
+            None => {
+                input.push_str(&line);
+                return Some(input);
+            }
+        }
+        line.clear();
+        prompt_continuation();
+        if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+            return Some(input);
+        }
+    }
+}
+
+fn prompt_continuation() {
+    print!("{} ", "...".blue());
+    std::io::stdout().flush().unwrap();
+}
+
+/// A REPL command, entered as a line starting with `/`.
+enum Command<'a> {
+    /// `/reset`: Clear the conversation history.
+    Reset,
+    /// `/save <path>`: Save the conversation history to a JSON file.
+    Save(&'a str),
+    /// `/load <path>`: Replace the conversation history with one saved to a JSON file, asking
+    /// whether to run any function calls it was in the middle of.
+    Load(&'a str),
+    /// `/usage`: Print the tokens used this session.
+    Usage,
+    /// `/stats`: Print how each tool has been used this session.
+    Stats,
+    /// `/estimate <prompt>`: Estimate what the first request for the prompt would cost, without
+    /// sending it.
+    Estimate(&'a str),
+    /// `/history`: Print the conversation history.
+    History,
+    /// `/export <path>`: Write the conversation history to a Markdown file.
+    Export(&'a str),
+    /// `/tools [name]`: List the tools, or toggle the tool with the given name.
+    Tools(&'a str),
+    /// `/model <id>`: Switch to another model, Claude or Gemini, keeping the conversation history.
+    Model(&'a str),
+    /// `/models`: List the models the provider offers.
+    Models,
+    /// `/batch <path>`: Submit every line of a file as a separate prompt in a batch, which is
+    /// answered without tools at a lower cost. `/batch <id>` checks on a batch and prints its
+    /// results once it has ended, and `/batch cancel <id>` stops it.
+    Batch(&'a str),
+    /// `/plan`: Only let the agent use read-only tools, so that it has to present a plan first.
+    Plan,
+    /// `/act`: Let the agent use every tool again.
+    Act,
+    /// `/prefill <text>`: Start the agent's answer to the next prompt with the given text, i.e `{`
+    /// to have it answer with JSON.
+    Prefill(&'a str),
+    /// `/quit`: Exit the REPL.
+    Quit,
+}
+
+impl<'a> Command<'a> {
+    /// Parse a command from a line of input. Returns `None` if the line is a prompt for the agent.
+    fn parse(line: &'a str) -> Option<Result<Command<'a>, String>> {
+        let line = line.trim().strip_prefix('/')?;
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim();
+        let command = match (name, args.is_empty()) {
+            ("reset", _) => Command::Reset,
+            ("save", false) => Command::Save(args),
+            ("load", false) => Command::Load(args),
+            ("usage", _) => Command::Usage,
+            ("stats", _) => Command::Stats,
+            ("estimate", false) => Command::Estimate(args),
+            ("history", _) => Command::History,
+            ("export", false) => Command::Export(args),
+            ("tools", _) => Command::Tools(args),
+            ("model", _) => Command::Model(args),
+            ("models", _) => Command::Models,
+            ("batch", false) => Command::Batch(args),
+            ("plan", _) => Command::Plan,
+            ("act", _) => Command::Act,
+            ("prefill", false) => Command::Prefill(args),
+            ("quit", _) => Command::Quit,
+            ("save" | "load" | "export", true) => {
+                return Some(Err(format!("Usage: /{} <path>", name)));
+            }
+            ("estimate", true) => return Some(Err("Usage: /estimate <prompt>".to_string())),
+            ("prefill", true) => return Some(Err("Usage: /prefill <text>".to_string())),
+            ("batch", true) => {
+                return Some(Err(
+                    "Usage: /batch <path>, /batch <id> or /batch cancel <id>".to_string(),
+                ));
+            }
+            _ => return Some(Err(format!("Unknown command '/{}'", name))),
+        };
+        Some(Ok(command))
+    }
+}
+
+/// What every prompt submitted with `/batch` is sent with.
+struct BatchPrompts {
+    model: Claude,
+    system_prompt: Vec<String>,
+    hyperparams: Hyperparams,
+}
+
+/// Run a `/batch` command.
+async fn batch(anthropic: &Anthropic, prompts: BatchPrompts, args: &str) -> Result<(), Error> {
+    if let Some(id) = args.strip_prefix("cancel ") {
+        anthropic.batch_handle(id.trim()).cancel().await?;
+        println!("{}: canceling {}", "batch".blue(), id.trim());
+        return Ok(());
+    }
+    if Path::new(args).is_file() {
+        let requests: Vec<BatchRequest> = std::fs::read_to_string(args)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| BatchRequest {
+                custom_id: format!("prompt-{}", i + 1),
+                model: prompts.model.clone(),
+                system_prompt: prompts.system_prompt.clone(),
+                hyperparams: prompts.hyperparams,
+                messages: vec![Message::User(vec![UserContent::Input(Content::Text(
+                    line.to_string(),
+                ))])],
+                functions: Vec::new(),
+            })
+            .collect();
+        let count = requests.len();
+        let handle = anthropic.batch(requests).await?;
+        println!(
+            "{}: submitted {} prompts as {}, check on it with /batch {}",
+            "batch".blue(),
+            count,
+            handle.id(),
+            handle.id()
+        );
+        return Ok(());
+    }
+    let handle = anthropic.batch_handle(args);
+    let status = handle.status().await?;
+    if status != BatchStatus::Ended {
+        println!("{}: {} is {:?}", "batch".blue(), args, status);
+        return Ok(());
+    }
+    let mut results = handle.results().await?;
+    // Prompts are numbered in the order they were in the file
+    results.sort_by_key(|(id, _)| {
+        id.strip_prefix("prompt-")
+            .and_then(|n| n.parse::<usize>().ok())
+    });
+    for (id, result) in results {
+        println!("{}: {}", "batch".blue(), id);
+        match result {
+            Ok(completion) => {
+                for content in completion.content {
+                    match content {
+                        AssistantContent::Output(content) => {
+                            println!("{}: {}", "agent".green(), content)
+                        }
+                        AssistantContent::Cited { text, .. } => {
+                            println!("{}: {}", "agent".green(), text)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Err(e) => println!("{}: {}", "err".red(), e),
+        }
+    }
+    Ok(())
+}
+
+fn save_history(history: &[TimedMessage], path: &str) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(history)?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+fn load_history(path: &str) -> Result<Vec<TimedMessage>, Error> {
+    let json = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+fn print_history(history: &[TimedMessage]) {
+    for TimedMessage { message, .. } in history {
+        match message {
+            Message::User(content) => {
+                for content in content {
+                    match content {
+                        UserContent::Input(content) => {
+                            println!("{} {}", "you:".blue(), content.to_string().trim_end())
+                        }
+                        UserContent::FunctionResult { id, result } => {
+                            let status = if result.is_ok() {
+                                "ok".green()
+                            } else {
+                                "err".red()
+                            };
+                            println!("{}: {} -> {}", "result".red(), id, status);
+                        }
+                    }
+                }
+            }
+            Message::Assistant(content) => {
+                for content in content {
+                    match content {
+                        AssistantContent::Output(content) => {
+                            println!("{}: {}", "agent".green(), content)
+                        }
+                        AssistantContent::FunctionCall { id, name, input } => {
+                            println!("{}: {} ({}) {}", "tool".red(), name, id, input)
+                        }
+                        AssistantContent::Reasoning { text, .. } => {
+                            println!("{}: {}", "thinking".dimmed(), text.dimmed())
+                        }
+                        AssistantContent::Cited { text, citations } => {
+                            println!("{}: {}", "agent".green(), text);
+                            for citation in citations {
+                                println!("  {} {}", "source:".dimmed(), citation);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lists the tools in the toolbox, or toggles the tool with the given name if one is provided.
+/// Enable the tool with the given name if it's disabled, or disable it if it isn't.
+fn toggle_tool(toolbox: &mut Toolbox, name: &str) -> Result<(), String> {
+    let enabled = toolbox.tools().any(|(n, enabled)| n == name && enabled);
+    let found = if enabled {
+        toolbox.disable(name)
+    } else {
+        toolbox.enable(name)
+    };
+    if found {
+        Ok(())
+    } else {
+        Err(format!("no tool named '{}'", name))
+    }
+}
+
+fn print_tools(toolbox: &Toolbox) {
+    for (name, enabled) in toolbox.tools() {
+        let status = if enabled { "on".green() } else { "off".red() };
+        println!("{}: {} [{}]", "tool".red(), name, status);
     }
 }
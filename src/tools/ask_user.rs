@@ -0,0 +1,75 @@
+use crate::core::{llm::Content, tool::LocalTool};
+use colored::*;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// A tool that asks the user a question on the terminal and waits for their answer.
+///
+/// This only works when someone is there to answer. Without a terminal, every question fails with
+/// an error, so that the model carries on with its best guess instead of waiting forever.
+pub struct AskUser {
+    interactive: bool,
+    // Tools can run in parallel, so questions are asked one at a time
+    asking: Mutex<()>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct AskUserInput {
+    /// The question to ask. It should make sense on its own, without the rest of your response.
+    question: String,
+}
+
+impl AskUser {
+    /// Create a tool that asks questions on stdin if it is interactive, and otherwise refuses to.
+    pub fn new(interactive: bool) -> Self {
+        Self {
+            interactive,
+            asking: Mutex::new(()),
+        }
+    }
+}
+
+impl LocalTool for AskUser {
+    type Input = AskUserInput;
+
+    fn name(&self) -> &'static str {
+        "ask_user"
+    }
+
+    fn description(&self) -> &'static str {
+        "Ask the user a question and get back their answer. Only use this when you genuinely can't \
+        continue without information that only the user has, i.e to choose between approaches \
+        that are equally reasonable, and not to confirm what you were already asked to do. Ask \
+        one clear, specific question at a time."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        if !self.interactive {
+            return Err(Content::Text(
+                "No user is available to answer. Make a reasonable assumption, say what it was, \
+                and continue."
+                    .to_string(),
+            ));
+        }
+        let _guard = self.asking.lock().unwrap();
+        print!("{}: {}\n> ", "question".yellow(), input.question.trim());
+        io::stdout()
+            .flush()
+            .map_err(|e| Content::Text(e.to_string()))?;
+        let mut answer = String::new();
+        match io::stdin().read_line(&mut answer) {
+            Ok(0) => Err(Content::Text("The user didn't answer.".to_string())),
+            Ok(_) if answer.trim().is_empty() => {
+                Err(Content::Text("The user gave no answer.".to_string()))
+            }
+            Ok(_) => Ok(vec![Content::Text(answer.trim().to_string())]),
+            Err(e) => Err(Content::Text(format!("Could not read the answer: {}", e))),
+        }
+    }
+
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        true
+    }
+}
@@ -0,0 +1,203 @@
+use crate::core::{llm::Content, tool::LocalTool};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::process::{Command, Stdio};
+
+/// The most diagnostics returned from one build. The rest are usually knock-on effects of the
+/// first few, so they're only counted.
+const MAX_DIAGNOSTICS: usize = 50;
+
+/// A tool that builds a Rust project with cargo and returns its diagnostics as a structured list of
+/// locations and messages, rather than cargo's own output.
+///
+/// Diagnostics are read from `--message-format=json`, deduplicated (the same warning is reported
+/// once per target it's built for) and capped, errors first. The result is `Content::Json`, so
+/// the toolbox decides how the model sees it.
+pub struct Cargo;
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct CargoInput {
+    /// What to run: `check` (the default) to type check, `build` to also produce binaries, or
+    /// `clippy` to also lint.
+    command: Option<CargoCommand>,
+    /// The package to build, in a workspace. Defaults to the package in the current directory, or
+    /// the whole workspace.
+    package: Option<String>,
+    /// Whether to also build tests, examples and benchmarks. Defaults to false.
+    all_targets: Option<bool>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CargoCommand {
+    #[default]
+    Check,
+    Build,
+    Clippy,
+}
+
+impl CargoCommand {
+    fn as_str(self) -> &'static str {
+        match self {
+            CargoCommand::Check => "check",
+            CargoCommand::Build => "build",
+            CargoCommand::Clippy => "clippy",
+        }
+    }
+}
+
+/// A single diagnostic, at the primary location it points to.
+#[derive(Serialize, Clone, PartialEq, Eq, Hash)]
+struct Diagnostic {
+    file: String,
+    line: usize,
+    column: usize,
+    level: String,
+    message: String,
+}
+
+/// Parse the diagnostics out of cargo's JSON messages, one per line. Anything that isn't a
+/// compiler message is skipped, as are the summaries rustc adds (i.e "aborting due to 2 previous
+/// errors"), which have no location.
+fn parse(output: &str) -> Vec<Diagnostic> {
+    let mut seen = HashSet::new();
+    let mut diagnostics = Vec::new();
+    for line in output.lines() {
+        let Ok(message) = serde_json::from_str::<CargoMessage>(line) else {
+            continue;
+        };
+        let Some(message) = message
+            .message
+            .filter(|_| message.reason == "compiler-message")
+        else {
+            continue;
+        };
+        let Some(span) = message.spans.iter().find(|s| s.is_primary) else {
+            continue;
+        };
+        let mut text = message.message;
+        if let Some(code) = message.code {
+            text = format!("[{}] {}", code.code, text);
+        }
+        // Help with a suggestion is often the whole fix, so the first one is kept
+        if let Some(help) = message
+            .children
+            .iter()
+            .find(|c| c.level == "help" && !c.message.is_empty())
+        {
+            text = format!("{} (help: {})", text, help.message);
+        }
+        let diagnostic = Diagnostic {
+            file: span.file_name.clone(),
+            line: span.line_start,
+            column: span.column_start,
+            level: message.level,
+            message: text,
+        };
+        if seen.insert(diagnostic.clone()) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    // Errors are what stop the build, so they come first
+    diagnostics.sort_by_key(|d| !d.level.starts_with("error"));
+    diagnostics
+}
+
+impl LocalTool for Cargo {
+    type Input = CargoInput;
+
+    fn name(&self) -> &'static str {
+        "cargo"
+    }
+
+    fn description(&self) -> &'static str {
+        "Build the Rust project in the workspace with cargo and get back its errors and warnings, \
+        errors first, each with its file, line, column, level and message. Use this after editing \
+        Rust code to find exactly what to fix, then view and edit each location. `check` is the \
+        fastest way to find errors."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        let command = input.command.unwrap_or_default();
+        let mut cargo = Command::new("cargo");
+        cargo
+            .arg(command.as_str())
+            .arg("--message-format=json")
+            .stdin(Stdio::null());
+        if let Some(package) = &input.package {
+            cargo.args(["--package", package]);
+        }
+        if input.all_targets.unwrap_or(false) {
+            cargo.arg("--all-targets");
+        }
+        let output = cargo
+            .output()
+            .map_err(|e| Content::Text(format!("Could not run cargo: {}", e)))?;
+        let diagnostics = parse(&String::from_utf8_lossy(&output.stdout));
+
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.level.starts_with("error"))
+            .count();
+        let mut result = json!({
+            "command": command.as_str(),
+            "success": output.status.success(),
+            "errors": errors,
+            "warnings": diagnostics.len() - errors,
+            "diagnostics": &diagnostics[..diagnostics.len().min(MAX_DIAGNOSTICS)],
+        });
+        if diagnostics.len() > MAX_DIAGNOSTICS {
+            result["omitted"] = json!(diagnostics.len() - MAX_DIAGNOSTICS);
+        }
+        // A failure without any diagnostics is a problem with cargo itself (i.e a bad manifest),
+        // which it only reports on stderr
+        if !output.status.success() && errors == 0 {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let lines: Vec<_> = stderr.lines().collect();
+            result["stderr"] = json!(lines[lines.len().saturating_sub(20)..].join("\n"));
+        }
+        if output.status.success() {
+            Ok(vec![Content::Json(result)])
+        } else {
+            Err(Content::Json(result))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    message: String,
+    level: String,
+    code: Option<Code>,
+    #[serde(default)]
+    spans: Vec<Span>,
+    #[serde(default)]
+    children: Vec<Child>,
+}
+
+#[derive(Deserialize)]
+struct Code {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: usize,
+    column_start: usize,
+    is_primary: bool,
+}
+
+#[derive(Deserialize)]
+struct Child {
+    message: String,
+    level: String,
+}
@@ -0,0 +1,78 @@
+use crate::core::{llm::Content, tool::LocalTool};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Mutex;
+
+/// A tool that lets the agent read from and write to the system clipboard.
+pub struct Clipboard {
+    // Opened on first use and then kept around, since on some platforms (like X11) the contents we
+    // write only stay available for as long as the clipboard handle lives.
+    inner: Mutex<Option<arboard::Clipboard>>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ClipboardInput {
+    /// Read the text currently on the clipboard.
+    Read,
+    /// Replace the contents of the clipboard with the given text.
+    Write { text: String },
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(None),
+        }
+    }
+
+    fn with_clipboard<T>(
+        &self,
+        f: impl FnOnce(&mut arboard::Clipboard) -> Result<T, arboard::Error>,
+    ) -> Result<T, Content> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_none() {
+            let clipboard = arboard::Clipboard::new().map_err(|e| {
+                Content::Text(format!("No clipboard is available on this system: {}", e))
+            })?;
+            *inner = Some(clipboard);
+        }
+        f(inner.as_mut().unwrap()).map_err(|e| Content::Text(format!("Clipboard error: {}", e)))
+    }
+}
+
+impl LocalTool for Clipboard {
+    type Input = ClipboardInput;
+
+    fn name(&self) -> &'static str {
+        "clipboard"
+    }
+
+    fn description(&self) -> &'static str {
+        "Access the user's system clipboard. Use `read` to get the text the user has copied, such as \
+        an error message or stack trace they want you to look at, and `write` to put text on the \
+        clipboard so the user can paste it elsewhere. Only write to the clipboard when the user asks \
+        for it, since it replaces whatever they had copied."
+    }
+
+    fn is_read_only(&self, input: &Self::Input) -> bool {
+        matches!(input, ClipboardInput::Read)
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        match input {
+            ClipboardInput::Read => {
+                let text = self.with_clipboard(|c| c.get_text())?;
+                Ok(vec![Content::Text(text)])
+            }
+            ClipboardInput::Write { text } => {
+                let len = text.chars().count();
+                self.with_clipboard(|c| c.set_text(text))?;
+                Ok(vec![Content::Text(format!(
+                    "Wrote {} characters to the clipboard.",
+                    len
+                ))])
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use super::truncate_tail;
+use crate::core::{llm::Content, tool::DynamicTool};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::process::{Command, Stdio};
+
+/// A tool defined in the config as a shell command, i.e:
+///
+/// ```toml
+/// [[custom_tools]]
+/// name = "test"
+/// description = "Run the tests of a single package."
+/// command = "cargo test -p {package}"
+///
+/// [custom_tools.input_schema]
+/// type = "object"
+/// properties.package = { type = "string", description = "The package to test." }
+/// required = ["package"]
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ToolDefinition {
+    /// The name the model calls the tool by.
+    pub name: String,
+    /// What the tool does and when to use it.
+    pub description: String,
+    /// The command to run with `sh -c`. Each `{field}` is replaced with that field of the input,
+    /// quoted for the shell, so placeholders must not be quoted again. Write `{{` and `}}` for
+    /// literal braces.
+    pub command: String,
+    /// The JSON schema of the input. Defaults to an object without any fields.
+    #[serde(default = "empty_schema")]
+    pub input_schema: Value,
+}
+
+fn empty_schema() -> Value {
+    json!({ "type": "object", "properties": {} })
+}
+
+/// A tool that runs a command from a `ToolDefinition`, returning what it printed.
+pub struct ConfigTool {
+    definition: ToolDefinition,
+    template: Vec<Part>,
+}
+
+enum Part {
+    Literal(String),
+    Field(String),
+}
+
+impl ConfigTool {
+    /// Create the tool, checking that its name is valid, that its schema describes an object, and
+    /// that every placeholder in its command is a field of that object.
+    pub fn new(definition: ToolDefinition) -> Result<Self, String> {
+        let name = &definition.name;
+        if name.is_empty()
+            || name.len() > 64
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!(
+                "Invalid tool name '{}': use up to 64 letters, digits, '_' and '-'",
+                name
+            ));
+        }
+        let schema = &definition.input_schema;
+        if schema["type"] != "object" {
+            return Err(format!(
+                "The input schema of '{}' must have type \"object\"",
+                name
+            ));
+        }
+        let template = parse(&definition.command).map_err(|e| format!("In '{}': {}", name, e))?;
+        for part in &template {
+            if let Part::Field(field) = part
+                && schema["properties"].get(field).is_none()
+            {
+                return Err(format!(
+                    "The command of '{}' uses {{{}}}, which isn't in its input schema",
+                    name, field
+                ));
+            }
+        }
+        Ok(Self {
+            definition,
+            template,
+        })
+    }
+
+    fn render(&self, input: &Value) -> String {
+        self.template
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Field(field) => match &input[field] {
+                    Value::Null => quote(""),
+                    Value::String(s) => quote(s),
+                    value => quote(&value.to_string()),
+                },
+            })
+            .collect()
+    }
+}
+
+fn parse(command: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut field = String::new();
+                let closed = loop {
+                    match chars.next() {
+                        Some('}') => break true,
+                        Some(c) => field.push(c),
+                        None => break false,
+                    }
+                };
+                if !closed || field.is_empty() || field.contains('{') {
+                    return Err(format!("Invalid placeholder '{{{}' in the command", field));
+                }
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+                parts.push(Part::Field(field));
+            }
+            '}' => return Err("Unmatched '}' in the command, write '}}' instead".to_string()),
+            c => literal.push(c),
+        }
+    }
+    parts.push(Part::Literal(literal));
+    Ok(parts)
+}
+
+// Single quotes keep everything literal, so only single quotes themselves need escaping.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+impl DynamicTool for ConfigTool {
+    fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    fn description(&self) -> &str {
+        &self.definition.description
+    }
+
+    fn input_schema(&self) -> &Value {
+        &self.definition.input_schema
+    }
+
+    fn call(&self, input: Value) -> Result<Vec<Content>, Content> {
+        let required = self.definition.input_schema["required"].as_array();
+        if let Some(missing) = required
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .find(|field| input.get(field).is_none_or(Value::is_null))
+        {
+            return Err(Content::Text(format!(
+                "The '{}' field is required.",
+                missing
+            )));
+        }
+        let command = self.render(&input);
+        log::debug!("Running {}", command);
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| Content::Text(format!("Could not start sh: {}", e)))?;
+        let stdout = truncate_tail(String::from_utf8_lossy(&output.stdout).into_owned());
+        if !output.status.success() {
+            let stderr = truncate_tail(String::from_utf8_lossy(&output.stderr).into_owned());
+            return Err(Content::Text(format!(
+                "{}{}\n[Command failed with {}]",
+                stdout, stderr, output.status
+            )));
+        }
+        Ok(vec![Content::Text(if stdout.is_empty() {
+            "(no output)".to_string()
+        } else {
+            stdout
+        })])
+    }
+}
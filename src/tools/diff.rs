@@ -0,0 +1,303 @@
+use std::path::Path;
+
+/// The number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Produce a unified diff of the changes between two versions of the file at the given path.
+pub fn unified(path: &Path, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let lines = diff_lines(&old_lines, &new_lines);
+
+    let changes: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| !matches!(l, Line::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return format!("No changes to {}.", path.display());
+    }
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path.display(), path.display());
+    // Group changes that are close enough together that their context would overlap.
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        match groups.last_mut() {
+            Some((_, last)) if i - *last <= CONTEXT * 2 => *last = i,
+            _ => groups.push((i, i)),
+        }
+    }
+    for (first, last) in groups {
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(lines.len());
+        let old_start = lines[..start]
+            .iter()
+            .filter(|l| !matches!(l, Line::Added(_)))
+            .count();
+        let new_start = lines[..start]
+            .iter()
+            .filter(|l| !matches!(l, Line::Removed(_)))
+            .count();
+        let hunk = &lines[start..end];
+        let old_len = hunk.iter().filter(|l| !matches!(l, Line::Added(_))).count();
+        let new_len = hunk
+            .iter()
+            .filter(|l| !matches!(l, Line::Removed(_)))
+            .count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + 1,
+            old_len,
+            new_start + 1,
+            new_len
+        ));
+        for line in hunk {
+            let (prefix, text) = match line {
+                Line::Same(text) => (' ', text),
+                Line::Removed(text) => ('-', text),
+                Line::Added(text) => ('+', text),
+            };
+            out.push(prefix);
+            out.push_str(text);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Above this many lines left after trimming the common prefix and suffix, the differing lines
+/// are shown as a whole replacement rather than spending the time to find a minimal diff.
+const MAX_DIFF_LINES: usize = 20_000;
+
+// Edits tend to be small and localized, so the common prefix and suffix are trimmed first, and
+// the rest is diffed with the linear-space variant of Myers' algorithm.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    let (prefix, suffix) = common_ends(old, new);
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    lines.extend(old[..prefix].iter().copied().map(Line::Same));
+    if a.len() + b.len() > MAX_DIFF_LINES {
+        replace(a, b, &mut lines);
+    } else {
+        let mut v = Frontiers::new(a.len() + b.len());
+        conquer(a, b, &mut v, &mut lines);
+    }
+    lines.extend(old[old.len() - suffix..].iter().copied().map(Line::Same));
+    // Splits can put an addition before the removal it replaces, so show removals first in every
+    // run of changes, as other diffs do
+    for run in lines.split_mut(|l| matches!(l, Line::Same(_))) {
+        run.sort_by_key(|l| matches!(l, Line::Added(_)));
+    }
+    lines
+}
+
+/// The number of lines the two sides start and end with in common, without overlapping.
+fn common_ends(a: &[&str], b: &[&str]) -> (usize, usize) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    (prefix, suffix)
+}
+
+fn replace<'a>(a: &[&'a str], b: &[&'a str], lines: &mut Vec<Line<'a>>) {
+    lines.extend(a.iter().copied().map(Line::Removed));
+    lines.extend(b.iter().copied().map(Line::Added));
+}
+
+/// Diff two sides by splitting them at the middle of a shortest edit script and diffing each
+/// half, which needs only two frontiers of memory rather than a table of every pair of lines.
+fn conquer<'a>(a: &[&'a str], b: &[&'a str], v: &mut Frontiers, lines: &mut Vec<Line<'a>>) {
+    let (prefix, suffix) = common_ends(a, b);
+    lines.extend(a[..prefix].iter().copied().map(Line::Same));
+    let (inner_a, inner_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    match middle_snake(inner_a, inner_b, v) {
+        Some((x, y)) => {
+            conquer(&inner_a[..x], &inner_b[..y], v, lines);
+            conquer(&inner_a[x..], &inner_b[y..], v, lines);
+        }
+        None => replace(inner_a, inner_b, lines),
+    }
+    lines.extend(a[a.len() - suffix..].iter().copied().map(Line::Same));
+}
+
+/// The furthest reaching x on each diagonal k = x - y, for the forward and backward searches.
+struct Frontiers {
+    forward: Vec<usize>,
+    backward: Vec<usize>,
+    offset: isize,
+}
+
+impl Frontiers {
+    fn new(max: usize) -> Self {
+        Self {
+            forward: vec![0; 2 * max + 3],
+            backward: vec![0; 2 * max + 3],
+            offset: max as isize + 1,
+        }
+    }
+
+    fn index(&self, k: isize) -> usize {
+        (k + self.offset) as usize
+    }
+}
+
+/// Find where the forward and backward searches for a shortest edit script meet, returning a
+/// point on it that splits both sides into smaller problems. Returns `None` when either side is
+/// empty, since there is nothing left to split. Both sides must differ in their first and last
+/// lines.
+fn middle_snake(a: &[&str], b: &[&str], v: &mut Frontiers) -> Option<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    if n == 0 || m == 0 {
+        return None;
+    }
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let (f1, b1) = (v.index(1), v.index(1));
+    v.forward[f1] = 0;
+    v.backward[b1] = 0;
+    for d in 0..=(n + m + 1) / 2 {
+        for k in (-d..=d).rev().step_by(2) {
+            let (up, down) = (v.index(k + 1), v.index(k - 1));
+            let mut x = if k == -d || (k != d && v.forward[down] < v.forward[up]) {
+                v.forward[up] as isize
+            } else {
+                v.forward[down] as isize + 1
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            let i = v.index(k);
+            v.forward[i] = x as usize;
+            if odd && (k - delta).abs() < d {
+                let j = v.index(delta - k);
+                if x + v.backward[j] as isize >= n {
+                    return split(x0, y0, n, m);
+                }
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let (up, down) = (v.index(k + 1), v.index(k - 1));
+            let mut x = if k == -d || (k != d && v.backward[down] < v.backward[up]) {
+                v.backward[up] as isize
+            } else {
+                v.backward[down] as isize + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            let i = v.index(k);
+            v.backward[i] = x as usize;
+            if !odd && (k - delta).abs() <= d {
+                let j = v.index(delta - k);
+                if x + v.forward[j] as isize >= n {
+                    return split(n - x, m - y, n, m);
+                }
+            }
+        }
+    }
+    None
+}
+
+// A split at either end wouldn't make the problem any smaller.
+fn split(x: isize, y: isize, n: isize, m: isize) -> Option<(usize, usize)> {
+    let inside = (0..=n).contains(&x) && (0..=m).contains(&y);
+    (inside && (x, y) != (0, 0) && (x, y) != (n, m)).then_some((x as usize, y as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sides<'a>(lines: &[Line<'a>]) -> (Vec<&'a str>, Vec<&'a str>) {
+        let (mut old, mut new) = (Vec::new(), Vec::new());
+        for line in lines {
+            match *line {
+                Line::Same(text) => {
+                    old.push(text);
+                    new.push(text);
+                }
+                Line::Removed(text) => old.push(text),
+                Line::Added(text) => new.push(text),
+            }
+        }
+        (old, new)
+    }
+
+    fn lcs(a: &[&str], b: &[&str]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i][j] = if a[i] == b[j] {
+                    table[i + 1][j + 1] + 1
+                } else {
+                    table[i + 1][j].max(table[i][j + 1])
+                };
+            }
+        }
+        table[0][0]
+    }
+
+    #[test]
+    fn finds_shortest_diffs() {
+        // Small alphabets make for lots of ambiguous matches
+        let mut seed = 7u64;
+        let mut next = move |bound: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        let words = ["a", "b", "c", "d"];
+        for _ in 0..500 {
+            let old: Vec<&str> = (0..next(12)).map(|_| words[next(4) as usize]).collect();
+            let new: Vec<&str> = (0..next(12)).map(|_| words[next(4) as usize]).collect();
+            let lines = diff_lines(&old, &new);
+            assert_eq!(sides(&lines), (old.clone(), new.clone()));
+            let same = lines.iter().filter(|l| matches!(l, Line::Same(_))).count();
+            assert_eq!(same, lcs(&old, &new), "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn replaces_huge_changes_whole() {
+        let old: Vec<String> = (0..MAX_DIFF_LINES).map(|i| format!("old {}", i)).collect();
+        let new: Vec<String> = (0..MAX_DIFF_LINES).map(|i| format!("new {}", i)).collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new: Vec<&str> = new.iter().map(String::as_str).collect();
+        let lines = diff_lines(&old, &new);
+        assert_eq!(sides(&lines), (old.clone(), new.clone()));
+        assert!(
+            lines[..old.len()]
+                .iter()
+                .all(|l| matches!(l, Line::Removed(_)))
+        );
+    }
+
+    #[test]
+    fn shows_hunks_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n";
+        assert_eq!(
+            unified(Path::new("f"), old, new),
+            "--- a/f\n+++ b/f\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+        assert_eq!(unified(Path::new("f"), old, old), "No changes to f.");
+    }
+}
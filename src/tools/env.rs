@@ -0,0 +1,106 @@
+use crate::core::{llm::Content, redact, tool::LocalTool};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// A read-only tool that lets the agent inspect the environment it's running in beyond what the
+/// system prompt already says, like environment variables and which commands are installed.
+pub struct Env;
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct EnvInput {
+    /// The names of environment variables to report, i.e `RUST_LOG` or `PATH`. Variables that may
+    /// contain secrets are redacted.
+    #[serde(default)]
+    vars: Vec<String>,
+    /// The names of executables to look for on the `PATH`, i.e `cargo` or `python3`.
+    #[serde(default)]
+    commands: Vec<String>,
+}
+
+impl Env {
+    /// Find the executable with the given bare name on the `PATH`.
+    pub(crate) fn find_executable(name: &str) -> Option<PathBuf> {
+        // Only bare names are looked up, anything else isn't a question about the PATH
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return None;
+        }
+        let path = env::var_os("PATH")?;
+        env::split_paths(&path).find_map(|dir| {
+            let candidate = dir.join(name);
+            if is_executable(&candidate) {
+                return Some(candidate);
+            }
+            #[cfg(windows)]
+            for ext in ["exe", "cmd", "bat"] {
+                let candidate = candidate.with_extension(ext);
+                if is_executable(&candidate) {
+                    return Some(candidate);
+                }
+            }
+            None
+        })
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+impl LocalTool for Env {
+    type Input = EnvInput;
+
+    fn name(&self) -> &'static str {
+        "inspect_environment"
+    }
+
+    fn description(&self) -> &'static str {
+        "Inspect the environment you are running in. Always reports the current working directory, \
+        and can also report the values of the given environment variables and whether the given \
+        commands are installed on the PATH. Use this to check things like the toolchain that is \
+        available before running or suggesting commands. Variables that may hold secrets, such as \
+        API keys and tokens, are redacted."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        let cwd = env::current_dir()
+            .map_err(|e| Content::Text(format!("Could not get the current directory: {}", e)))?;
+        let mut report = format!("Current directory: {}", cwd.display());
+        if !input.vars.is_empty() {
+            report.push_str("\n\nEnvironment variables:");
+            for name in &input.vars {
+                let value = match env::var(name) {
+                    // Notably, this keeps the agent from reading the API key it was started with
+                    _ if redact::is_secret_name(name) => "[redacted]".to_string(),
+                    Ok(value) => value,
+                    Err(env::VarError::NotPresent) => "(not set)".to_string(),
+                    Err(env::VarError::NotUnicode(_)) => "(not valid unicode)".to_string(),
+                };
+                report.push_str(&format!("\n{}={}", name, value));
+            }
+        }
+        if !input.commands.is_empty() {
+            report.push_str("\n\nCommands:");
+            for name in &input.commands {
+                match Env::find_executable(name) {
+                    Some(path) => report.push_str(&format!("\n{}: {}", name, path.display())),
+                    None => report.push_str(&format!("\n{}: not found", name)),
+                }
+            }
+        }
+        Ok(vec![Content::Text(report)])
+    }
+
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        true
+    }
+}
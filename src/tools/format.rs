@@ -0,0 +1,269 @@
+use super::env::Env;
+use super::truncate_tail;
+use crate::anthropic::EditHistory;
+use crate::core::{llm::Content, tool::LocalTool};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// A tool that runs a code formatter over a file or directory, and reports which files it changed.
+///
+/// The formatter is either a configured command or, by default, picked from the type of the file
+/// (or the project files in the directory): `rustfmt`/`cargo fmt`, `black` or `prettier`. The
+/// files it changed in a directory are found with git, which only has to look at the files that
+/// differ from the index. Every file it changes is recorded in the editor's history, so
+/// formatting can be undone like any other edit.
+pub struct Format {
+    command: Option<String>,
+    history: EditHistory,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct FormatInput {
+    /// The file or directory to format. Defaults to the whole workspace.
+    path: Option<PathBuf>,
+}
+
+/// A formatter to run.
+struct Formatter {
+    program: String,
+    args: Vec<String>,
+}
+
+const PYTHON: &[&str] = &["py", "pyi"];
+const PRETTIER: &[&str] = &[
+    "js", "jsx", "mjs", "cjs", "ts", "tsx", "json", "css", "scss", "less", "html", "vue", "md",
+    "yaml", "yml",
+];
+
+impl Format {
+    /// Create a tool that records the files it changes in the given history. If a command is
+    /// given, it is run with `sh -c` instead of picking a formatter, with `{path}` replaced by the
+    /// path to format (if it has one).
+    pub fn new(command: Option<String>, history: EditHistory) -> Self {
+        Self { command, history }
+    }
+
+    fn formatter(&self, path: &Path) -> Result<Formatter, String> {
+        if let Some(command) = &self.command {
+            let path = format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"));
+            return Ok(Formatter {
+                program: "sh".to_string(),
+                args: vec!["-c".to_string(), command.replace("{path}", &path)],
+            });
+        }
+        let path_arg = path.to_string_lossy().to_string();
+        let formatter = |program: &str, args: &[&str]| Formatter {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        };
+        if path.is_file() {
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            return match extension {
+                "rs" => Ok(Formatter {
+                    program: "rustfmt".to_string(),
+                    args: vec!["--edition".to_string(), edition(path), path_arg],
+                }),
+                _ if PYTHON.contains(&extension) => Ok(formatter("black", &["--quiet", &path_arg])),
+                _ if PRETTIER.contains(&extension) => Ok(formatter(
+                    "prettier",
+                    &["--write", "--log-level", "warn", &path_arg],
+                )),
+                _ => Err(format!(
+                    "No formatter is known for {:?} files. Ask the user to configure one.",
+                    path
+                )),
+            };
+        }
+        if path.join("Cargo.toml").is_file() {
+            Ok(formatter("cargo", &["fmt"]))
+        } else if ["pyproject.toml", "setup.py", "setup.cfg"]
+            .iter()
+            .any(|f| path.join(f).is_file())
+        {
+            Ok(formatter("black", &["--quiet", "."]))
+        } else if path.join("package.json").is_file() {
+            Ok(formatter(
+                "prettier",
+                &["--write", "--log-level", "warn", "."],
+            ))
+        } else {
+            Err(format!(
+                "Could not tell how to format {:?}: it has no Cargo.toml, pyproject.toml or \
+                package.json. Format a single file instead, or ask the user to configure a \
+                formatter.",
+                path
+            ))
+        }
+    }
+}
+
+// rustfmt formats as the 2015 edition unless told otherwise, which can't parse newer code, so the
+// edition is taken from the nearest Cargo.toml.
+fn edition(path: &Path) -> String {
+    path.ancestors()
+        .skip(1)
+        .map(|dir| dir.join("Cargo.toml"))
+        .find_map(|manifest| fs::read_to_string(manifest).ok())
+        .and_then(|manifest| manifest.parse::<toml::Table>().ok())
+        .and_then(|manifest| {
+            manifest
+                .get("package")?
+                .get("edition")?
+                .as_str()
+                .map(String::from)
+        })
+        .unwrap_or_else(|| "2021".to_string())
+}
+
+impl LocalTool for Format {
+    type Input = FormatInput;
+
+    fn name(&self) -> &'static str {
+        "format"
+    }
+
+    fn description(&self) -> &'static str {
+        "Run the project's code formatter over a file or directory (the whole workspace by \
+        default), and get back which files it changed. Use this after editing code so that it \
+        matches the project's style. Formatting can be undone with the editor, like any other \
+        edit."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        let path = input.path.unwrap_or_else(|| PathBuf::from("."));
+        if !path.exists() {
+            return Err(Content::Text(format!("{:?} does not exist.", path)));
+        }
+        let formatter = self.formatter(&path).map_err(Content::Text)?;
+        if Env::find_executable(&formatter.program).is_none() {
+            return Err(Content::Text(format!(
+                "`{}` is not installed, so {:?} can't be formatted.",
+                formatter.program, path
+            )));
+        }
+
+        // Files that already differ from the index are kept as they are now, since git only knows
+        // what they were before they were edited
+        let before = if path.is_file() {
+            Some(vec![path.clone()])
+        } else {
+            dirty_files(&path)
+        };
+        let snapshots: HashMap<PathBuf, Vec<u8>> = before
+            .iter()
+            .flatten()
+            .filter_map(|file| Some((file.clone(), fs::read(file).ok()?)))
+            .collect();
+
+        // Directories are formatted from inside, since that's where the project files are
+        let mut command = Command::new(&formatter.program);
+        command.args(&formatter.args).stdin(Stdio::null());
+        if path.is_dir() && self.command.is_none() {
+            command.current_dir(&path);
+        }
+        log::debug!("Running {} {:?}", formatter.program, formatter.args);
+        let output = command
+            .output()
+            .map_err(|e| Content::Text(format!("Could not run `{}`: {}", formatter.program, e)))?;
+
+        let Some(before) = before else {
+            let done = format!(
+                "Formatted {:?}, but it isn't in a git repository, so the changed files can't be \
+                listed or undone.",
+                path
+            );
+            return finish(&formatter, &output, done);
+        };
+        let mut files = before;
+        for file in dirty_files(&path).unwrap_or_default() {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files.sort();
+        let mut changed = Vec::new();
+        for file in files {
+            let Some(before) = snapshots.get(&file).cloned().or_else(|| indexed(&file)) else {
+                continue;
+            };
+            if fs::read(&file).ok().as_ref() == Some(&before) {
+                continue;
+            }
+            // Formatters only ever work on text, so anything else is left out of the history
+            if let Ok(before) = String::from_utf8(before) {
+                self.history.record(&file, Some(before));
+            }
+            changed.push(file.display().to_string());
+        }
+        let changed = if changed.is_empty() {
+            "No files were changed.".to_string()
+        } else {
+            format!("Changed {} files:\n{}", changed.len(), changed.join("\n"))
+        };
+        finish(&formatter, &output, changed)
+    }
+}
+
+// Report what was changed, alongside the formatter's output if it failed.
+fn finish(
+    formatter: &Formatter,
+    output: &Output,
+    changed: String,
+) -> Result<Vec<Content>, Content> {
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Content::Text(format!(
+            "`{}` failed with {}:\n{}\n{}",
+            formatter.program,
+            output.status,
+            truncate_tail(format!("{}{}", stdout, stderr).trim().to_string()),
+            changed
+        )));
+    }
+    Ok(vec![Content::Text(changed)])
+}
+
+/// The files under the path that differ from the index, including untracked ones, relative to the
+/// current directory. `None` if the path isn't in a git repository.
+fn dirty_files(path: &Path) -> Option<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for args in [
+        &["diff", "--name-only", "--relative", "-z", "--"][..],
+        &["ls-files", "--others", "--exclude-standard", "-z", "--"],
+    ] {
+        let output = Command::new("git")
+            .args(args)
+            .arg(path)
+            .stdin(Stdio::null())
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        files.extend(
+            output
+                .stdout
+                .split(|b| *b == 0)
+                .filter(|f| !f.is_empty())
+                .map(|f| PathBuf::from(String::from_utf8_lossy(f).into_owned())),
+        );
+    }
+    Some(files)
+}
+
+/// The contents of the file in the index, which are what a file that doesn't differ from it was
+/// before it was formatted.
+fn indexed(file: &Path) -> Option<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("show")
+        .arg(format!(":./{}", file.display()))
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then_some(output.stdout)
+}
@@ -0,0 +1,211 @@
+use super::truncate_head;
+use crate::core::{llm::Content, tool::LocalTool};
+use reqwest::{Client, StatusCode};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use tokio::runtime::Handle;
+
+const API_URL: &str = "https://api.github.com";
+
+/// A read-only tool that fetches a GitHub issue or pull request, with its comments.
+///
+/// Public repositories work without a token, but GitHub only allows 60 requests an hour without
+/// one, and private repositories need one.
+pub struct GitHubIssue {
+    token: Option<String>,
+    client: Client,
+    // Tools are called on threads outside of the runtime, so requests are run on this handle
+    runtime: Handle,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct GitHubIssueInput {
+    /// The repository, as `owner/name` or its URL.
+    repo: String,
+    /// The number of the issue or pull request.
+    number: u64,
+}
+
+impl GitHubIssue {
+    /// Create a tool that authenticates with the given token, if any.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            token,
+            client: Client::new(),
+            runtime: Handle::current(),
+        }
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, String> {
+        let mut request = self
+            .client
+            .get(format!("{}{}", API_URL, path))
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .header("User-Agent", "asimov");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        self.runtime.block_on(async {
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from)
+            };
+            let out_of_requests = header("x-ratelimit-remaining").as_deref() == Some("0");
+            let reset = header("x-ratelimit-reset");
+            let body = response.text().await.map_err(|e| e.to_string())?;
+            match status {
+                _ if status.is_success() => {
+                    serde_json::from_str(&body).map_err(|e| format!("Unexpected response: {}", e))
+                }
+                StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS if out_of_requests => {
+                    Err(format!(
+                        "GitHub's rate limit was reached{}. {}",
+                        reset
+                            .map(|r| format!(" and resets at Unix time {}", r))
+                            .unwrap_or_default(),
+                        if self.token.is_some() {
+                            "Wait for it to reset before trying again."
+                        } else {
+                            "Ask the user to set GITHUB_TOKEN for a higher limit."
+                        }
+                    ))
+                }
+                StatusCode::UNAUTHORIZED => Err(
+                    "GitHub rejected the token. Ask the user to check that GITHUB_TOKEN is valid."
+                        .to_string(),
+                ),
+                StatusCode::NOT_FOUND if self.token.is_none() => Err(
+                    "Not found. If the repository is private, ask the user to set GITHUB_TOKEN."
+                        .to_string(),
+                ),
+                StatusCode::NOT_FOUND => Err("Not found.".to_string()),
+                _ => Err(format!("GitHub returned {}: {}", status, body.trim())),
+            }
+        })
+    }
+}
+
+fn repo_name(repo: &str) -> Option<&str> {
+    let repo = repo.trim().trim_end_matches('/');
+    let repo = repo
+        .strip_prefix("https://github.com/")
+        .or_else(|| repo.strip_prefix("github.com/"))
+        .unwrap_or(repo);
+    let repo = repo.strip_suffix(".git").unwrap_or(repo);
+    let (owner, name) = repo.split_once('/')?;
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    };
+    (valid(owner) && valid(name)).then_some(repo)
+}
+
+impl LocalTool for GitHubIssue {
+    type Input = GitHubIssueInput;
+
+    fn name(&self) -> &'static str {
+        "github_issue"
+    }
+
+    fn description(&self) -> &'static str {
+        "Fetch a GitHub issue or pull request by repository and number, getting back its title, \
+        state, labels, description and comments. Use this when the user refers to an issue or pull \
+        request, to work from what it actually says. This does not include the diff of a pull \
+        request."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        let repo = repo_name(&input.repo).ok_or_else(|| {
+            Content::Text(format!(
+                "'{}' is not a repository. Give it as `owner/name`.",
+                input.repo
+            ))
+        })?;
+        let path = format!("/repos/{}/issues/{}", repo, input.number);
+        let issue: Issue = self.get(&path).map_err(Content::Text)?;
+        let comments: Vec<Comment> = if issue.comments > 0 {
+            self.get(&format!("{}/comments?per_page=100", path))
+                .map_err(Content::Text)?
+        } else {
+            Vec::new()
+        };
+
+        let kind = if issue.pull_request.is_some() {
+            "Pull request"
+        } else {
+            "Issue"
+        };
+        let mut text = format!(
+            "{} {}#{}: {}\nState: {}\nAuthor: @{}",
+            kind, repo, input.number, issue.title, issue.state, issue.user.login
+        );
+        if !issue.labels.is_empty() {
+            let labels: Vec<_> = issue.labels.iter().map(|l| l.name.as_str()).collect();
+            text.push_str(&format!("\nLabels: {}", labels.join(", ")));
+        }
+        text.push_str(&format!(
+            "\n\n{}",
+            issue.body.as_deref().unwrap_or("(no description)").trim()
+        ));
+        for comment in &comments {
+            text.push_str(&format!(
+                "\n\n--- @{} at {} ---\n{}",
+                comment.user.login,
+                comment.created_at,
+                comment.body.as_deref().unwrap_or_default().trim()
+            ));
+        }
+        if issue.comments as usize > comments.len() {
+            text.push_str(&format!(
+                "\n\n[Only the first {} of {} comments are shown]",
+                comments.len(),
+                issue.comments
+            ));
+        }
+        Ok(vec![Content::Text(truncate_head(text))])
+    }
+
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct Issue {
+    title: String,
+    state: String,
+    body: Option<String>,
+    user: User,
+    #[serde(default)]
+    labels: Vec<Label>,
+    comments: u64,
+    pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Comment {
+    user: User,
+    body: Option<String>,
+    created_at: String,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+}
@@ -0,0 +1,72 @@
+use super::walk;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The most files that will be looked at when indexing.
+const MAX_FILES: usize = 5000;
+/// Directories with more files than this have them summarized instead of listed.
+const MAX_LISTED_FILES: usize = 12;
+
+/// Build a compact overview of the files in the workspace, so that the agent starts out knowing
+/// roughly how the project is laid out instead of spending its first turns exploring.
+///
+/// Each directory gets one line listing its files, or counting them by extension if there are
+/// many. The overview is cut off after `max_lines` lines.
+pub fn overview(root: &Path, max_lines: usize) -> String {
+    let files = walk::files(root, MAX_FILES);
+    let mut dirs: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for file in &files {
+        let relative = file.strip_prefix(root).unwrap_or(file);
+        let dir = relative.parent().unwrap_or(Path::new("")).to_path_buf();
+        let name = relative.file_name().unwrap_or_default();
+        dirs.entry(dir)
+            .or_default()
+            .push(name.to_string_lossy().into_owned());
+    }
+
+    let mut lines = Vec::new();
+    for (dir, names) in &dirs {
+        let dir = if dir.as_os_str().is_empty() {
+            "./".to_string()
+        } else {
+            format!("{}/", dir.display())
+        };
+        let contents = if names.len() <= MAX_LISTED_FILES {
+            names.join(", ")
+        } else {
+            summarize(names)
+        };
+        lines.push(format!("{} {}", dir, contents));
+    }
+    if lines.len() > max_lines {
+        let skipped = lines.len() - max_lines;
+        lines.truncate(max_lines);
+        lines.push(format!("... and {} more directories", skipped));
+    }
+    if files.len() >= MAX_FILES {
+        lines.push(format!(
+            "(Only the first {} files were indexed.)",
+            MAX_FILES
+        ));
+    }
+    lines.join("\n")
+}
+
+// i.e "(40 files: 31 .rs, 6 .toml, 3 other)"
+fn summarize(names: &[String]) -> String {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for name in names {
+        let ext = match Path::new(name).extension() {
+            Some(ext) => format!(".{}", ext.to_string_lossy()),
+            None => "other".to_string(),
+        };
+        *counts.entry(ext).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let counts: Vec<String> = counts
+        .into_iter()
+        .map(|(ext, count)| format!("{} {}", count, ext))
+        .collect();
+    format!("({} files: {})", names.len(), counts.join(", "))
+}
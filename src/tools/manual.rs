@@ -0,0 +1,169 @@
+use super::env::Env;
+use super::truncate_head;
+use crate::core::{llm::Content, tool::LocalTool};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long looking up documentation may take before it is given up on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A read-only tool that looks up how to use a command, from its man page or its `--help` output.
+///
+/// Only installed commands can be looked up, and they are run directly rather than through a
+/// shell, with no input and a short timeout.
+pub struct Manual;
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ManualInput {
+    /// The command to look up, i.e `rg` or `git-rebase`. Must be a bare name, not a path.
+    command: String,
+    /// Where to look: `man` for the man page, or `help` for the command's `--help` output.
+    /// Defaults to `man`, falling back to `help` if there is no man page.
+    source: Option<Source>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Man,
+    Help,
+}
+
+impl Manual {
+    fn man(page: &str) -> Result<String, String> {
+        let mut command = Command::new("man");
+        command
+            .arg(page)
+            .env("MANPAGER", "cat")
+            .env("MANWIDTH", "100");
+        run(command).map(|page| strip_overstrike(&page))
+    }
+
+    fn help(name: &str) -> Result<String, String> {
+        if Env::find_executable(name).is_none() {
+            return Err(format!("'{}' is not installed on the PATH", name));
+        }
+        let mut command = Command::new(name);
+        command.arg("--help");
+        run(command)
+    }
+}
+
+fn run(mut command: Command) -> Result<String, String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    // Man pages easily fill a pipe, so both are drained while waiting
+    let (status, stdout, stderr) = thread::scope(|scope| {
+        let stdout = scope.spawn(move || {
+            let mut out = Vec::new();
+            let _ = stdout.read_to_end(&mut out);
+            out
+        });
+        let stderr = scope.spawn(move || {
+            let mut out = Vec::new();
+            let _ = stderr.read_to_end(&mut out);
+            out
+        });
+        let start = Instant::now();
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Some(status),
+                Ok(None) if start.elapsed() > TIMEOUT => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break None;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(20)),
+                Err(_) => break None,
+            }
+        };
+        let stdout = String::from_utf8_lossy(&stdout.join().unwrap_or_default()).into_owned();
+        let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned();
+        (status, stdout, stderr)
+    });
+    match status {
+        // Plenty of commands print their help to stderr, or exit with an error after printing it
+        _ if !stdout.trim().is_empty() => Ok(stdout),
+        Some(status) if status.success() => Ok(stderr),
+        Some(status) if !stderr.trim().is_empty() => Err(format!("{} ({})", stderr.trim(), status)),
+        Some(status) => Err(format!("failed with {}", status)),
+        None => Err(format!("timed out after {} seconds", TIMEOUT.as_secs())),
+    }
+}
+
+// Without a terminal, man may still emphasize text by printing characters over each other with
+// backspaces, which is only noise to the model.
+fn strip_overstrike(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\u{8}' {
+            out.pop();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+impl LocalTool for Manual {
+    type Input = ManualInput;
+
+    fn name(&self) -> &'static str {
+        "manual"
+    }
+
+    fn description(&self) -> &'static str {
+        "Look up the documentation of an installed command, from its man page or its --help \
+        output. Use this to check which flags a command actually supports before running or \
+        suggesting it, instead of guessing."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        let name = input.command.trim();
+        // The name is passed as a single argument, but anything that isn't a plain name is
+        // rejected outright so that only installed commands can ever be run.
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c))
+            || name.starts_with('-')
+        {
+            return Err(Content::Text(format!(
+                "'{}' is not a command name. Give just the name, i.e `grep`.",
+                name
+            )));
+        }
+        let result = match input.source {
+            Some(Source::Man) => Manual::man(name),
+            Some(Source::Help) => Manual::help(name),
+            None => Manual::man(name).or_else(|man| {
+                Manual::help(name).map_err(|help| format!("{}, and {}", man.trim(), help))
+            }),
+        };
+        match result {
+            Ok(text) if !text.trim().is_empty() => Ok(vec![Content::Text(truncate_head(text))]),
+            Ok(_) => Err(Content::Text(format!(
+                "No documentation was found for '{}'.",
+                name
+            ))),
+            Err(e) => Err(Content::Text(format!(
+                "Could not look up '{}': {}",
+                name, e
+            ))),
+        }
+    }
+
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        true
+    }
+}
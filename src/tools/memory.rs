@@ -0,0 +1,165 @@
+use crate::core::{llm::Content, tool::LocalTool};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// A tool that lets the agent remember notes across sessions, backed by a JSON file.
+pub struct Memory {
+    path: PathBuf,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct MemoryInput {
+    /// The command to run.
+    command: MemoryCommand,
+    /// The note to remember. Required for `remember`.
+    note: Option<String>,
+    /// The ID of the note to forget. Required for `forget`.
+    id: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryCommand {
+    /// Store a new note.
+    Remember,
+    /// List all stored notes alongside their IDs.
+    Recall,
+    /// Delete the note with the given ID.
+    Forget,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Notes {
+    next_id: u64,
+    notes: Vec<Note>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Note {
+    id: u64,
+    text: String,
+}
+
+impl Memory {
+    /// Create a memory backed by the file at the given path.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Create a memory backed by `.asimov/memory.json` in the current directory.
+    pub fn in_workspace() -> Self {
+        Self::new(".asimov/memory.json")
+    }
+
+    /// Render all stored notes, or `None` if there are none. Useful for priming the agent with
+    /// what it remembered in previous sessions.
+    pub fn recall(&self) -> Option<String> {
+        let notes = self.load();
+        if notes.notes.is_empty() {
+            return None;
+        }
+        Some(
+            notes
+                .notes
+                .iter()
+                .map(|n| format!("[{}] {}", n.id, n.text))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
+    // A missing or corrupt memory file shouldn't break the agent, so we just start fresh.
+    fn load(&self) -> Notes {
+        match fs::read_to_string(&self.path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                log::warn!(
+                    "Memory file {:?} is corrupt, starting fresh: {}",
+                    self.path,
+                    e
+                );
+                Notes::default()
+            }),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    log::warn!("Could not read memory file {:?}: {}", self.path, e);
+                }
+                Notes::default()
+            }
+        }
+    }
+
+    // Write to a temporary file first and then rename it over the real one, so that an
+    // interrupted write never leaves a half-written memory file behind.
+    fn save(&self, notes: &Notes) -> Result<(), Content> {
+        let json = serde_json::to_string_pretty(notes).map_err(|e| Content::Text(e.to_string()))?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| self.io_error_to_content(e))?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json).map_err(|e| self.io_error_to_content(e))?;
+        fs::rename(&tmp, &self.path).map_err(|e| self.io_error_to_content(e))
+    }
+
+    fn io_error_to_content(&self, err: io::Error) -> Content {
+        Content::Text(format!(
+            "I/O error for memory file {:?}: {}",
+            self.path, err
+        ))
+    }
+}
+
+impl LocalTool for Memory {
+    type Input = MemoryInput;
+
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn description(&self) -> &'static str {
+        "Long-term memory that persists across sessions. Use `remember` to store a short note about \
+        the user's project or preferences that will be useful in future sessions, `recall` to list \
+        all stored notes with their IDs, and `forget` to delete a note that is no longer true. \
+        Notes should be concise, self-contained facts."
+    }
+
+    fn is_read_only(&self, input: &Self::Input) -> bool {
+        matches!(input.command, MemoryCommand::Recall)
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        match input.command {
+            MemoryCommand::Remember => {
+                let text = input.note.ok_or(Content::Text(
+                    "A note is required to remember something.".to_string(),
+                ))?;
+                let mut notes = self.load();
+                notes.next_id += 1;
+                let id = notes.next_id;
+                notes.notes.push(Note { id, text });
+                self.save(&notes)?;
+                Ok(vec![Content::Text(format!("Remembered note {}.", id))])
+            }
+            MemoryCommand::Recall => {
+                Ok(vec![Content::Text(self.recall().unwrap_or_else(|| {
+                    "No notes have been remembered yet.".to_string()
+                }))])
+            }
+            MemoryCommand::Forget => {
+                let id = input.id.ok_or(Content::Text(
+                    "The ID of the note to forget is required.".to_string(),
+                ))?;
+                let mut notes = self.load();
+                let count = notes.notes.len();
+                notes.notes.retain(|n| n.id != id);
+                if notes.notes.len() == count {
+                    return Err(Content::Text(format!("There is no note with ID {}.", id)));
+                }
+                self.save(&notes)?;
+                Ok(vec![Content::Text(format!("Forgot note {}.", id))])
+            }
+        }
+    }
+}
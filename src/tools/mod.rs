@@ -0,0 +1,51 @@
+pub mod ask_user;
+pub mod cargo;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod config_tool;
+pub mod diff;
+pub mod env;
+pub mod format;
+pub mod github;
+pub mod index;
+pub mod manual;
+pub mod memory;
+pub mod scratch;
+pub mod search;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod symbols;
+pub mod view_files;
+pub mod walk;
+
+/// The most output a tool returns from a single call, so that one noisy command or long document
+/// doesn't flood the context.
+pub const MAX_OUTPUT_CHARS: usize = 30_000;
+
+/// Cut output down to `MAX_OUTPUT_CHARS`, keeping its end. Use this for command output, where the
+/// errors and summaries are usually last.
+pub fn truncate_tail(output: String) -> String {
+    let len = output.chars().count();
+    if len <= MAX_OUTPUT_CHARS {
+        return output;
+    }
+    let tail: String = output.chars().skip(len - MAX_OUTPUT_CHARS).collect();
+    format!(
+        "[Output truncated, showing the last {} of {} characters]\n{}",
+        MAX_OUTPUT_CHARS, len, tail
+    )
+}
+
+/// Cut text down to `MAX_OUTPUT_CHARS`, keeping its start. Use this for documents, where what
+/// matters most comes first.
+pub fn truncate_head(text: String) -> String {
+    let len = text.chars().count();
+    if len <= MAX_OUTPUT_CHARS {
+        return text;
+    }
+    let head: String = text.chars().take(MAX_OUTPUT_CHARS).collect();
+    format!(
+        "{}\n[Truncated, showing the first {} of {} characters]",
+        head, MAX_OUTPUT_CHARS, len
+    )
+}
@@ -0,0 +1,121 @@
+use crate::anthropic::Bash;
+use crate::core::{
+    llm::Content,
+    tool::{LocalTool, Sink},
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The interpreters snippets are run with by default, by the extension of their file.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("py", "python3 {path}"),
+    ("js", "node {path}"),
+    ("ts", "npx --yes tsx {path}"),
+    ("rb", "ruby {path}"),
+    ("sh", "bash {path}"),
+    ("pl", "perl {path}"),
+    ("lua", "lua {path}"),
+];
+
+/// A tool that runs a snippet of code from a temporary file, so that the model can try something
+/// out without leaving files like `test.py` in the workspace.
+///
+/// The file is written to the OS's temp directory and always removed afterwards, even if the
+/// snippet fails or times out. It runs in the current directory, so it can still read the
+/// project's files, with the same timeout and output limit as the bash tool.
+pub struct Scratch {
+    interpreters: HashMap<String, String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ScratchInput {
+    /// The code to run.
+    code: String,
+    /// The extension of the file the code is written to, without the dot (i.e `py` or `js`), which
+    /// decides how it's run.
+    extension: String,
+}
+
+// Removes the file when dropped, so that it's cleaned up however the call ends
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            log::warn!("Could not remove the scratch file {:?}: {}", self.0, e);
+        }
+    }
+}
+
+impl Scratch {
+    /// Create a tool that runs snippets with the given commands, by file extension, on top of the
+    /// defaults. Each is run with `bash -c`, with `{path}` replaced by the path to the snippet.
+    pub fn new(interpreters: HashMap<String, String>) -> Self {
+        let mut all: HashMap<String, String> = INTERPRETERS
+            .iter()
+            .map(|(extension, command)| (extension.to_string(), command.to_string()))
+            .collect();
+        all.extend(interpreters);
+        Self { interpreters: all }
+    }
+
+    fn write(&self, code: &str, extension: &str) -> Result<TempFile, Content> {
+        // Unique within the process, and the process ID keeps parallel sessions apart
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "asimov-scratch-{}-{}.{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| Content::Text(format!("Could not create {:?}: {}", path, e)))?;
+        let temp = TempFile(path);
+        file.write_all(code.as_bytes())
+            .map_err(|e| Content::Text(format!("Could not write {:?}: {}", temp.0, e)))?;
+        Ok(temp)
+    }
+}
+
+impl LocalTool for Scratch {
+    type Input = ScratchInput;
+
+    fn name(&self) -> &'static str {
+        "scratch"
+    }
+
+    fn description(&self) -> &'static str {
+        "Run a snippet of code from a temporary file outside the workspace, which is deleted \
+        afterwards, and get back its output. Use this to try out an idea or check how something \
+        behaves, instead of creating throwaway files in the project. The snippet runs in the \
+        current directory, so it can read the project's files."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        self.call_streaming(input, &|_| {})
+    }
+
+    fn call_streaming(&self, input: Self::Input, sink: Sink) -> Result<Vec<Content>, Content> {
+        let extension = input.extension.trim_start_matches('.');
+        let Some(interpreter) = self.interpreters.get(extension) else {
+            let mut known: Vec<_> = self.interpreters.keys().map(String::as_str).collect();
+            known.sort();
+            return Err(Content::Text(format!(
+                "There is no interpreter for .{} files. Use one of: {}",
+                extension,
+                known.join(", ")
+            )));
+        };
+        let file = self.write(&input.code, extension)?;
+        let path = format!("'{}'", file.0.to_string_lossy().replace('\'', r"'\''"));
+        Bash::run(&interpreter.replace("{path}", &path), sink)
+    }
+}
@@ -0,0 +1,167 @@
+use crate::core::{llm::Content, tool::LocalTool};
+use reqwest::Client;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::runtime::Handle;
+
+/// The search API used when no endpoint is configured, which is Brave's web search.
+pub const DEFAULT_ENDPOINT: &str = "https://api.search.brave.com/res/v1/web/search";
+/// How many results are returned when the model doesn't ask for a number.
+const DEFAULT_RESULTS: usize = 5;
+/// The most results returned by a single search.
+const MAX_RESULTS: usize = 10;
+
+/// A tool that lets the agent search the web with any model, returning the title, URL and a
+/// snippet of each result. Unlike Anthropic's server-side `web_search`, searches are run locally.
+///
+/// Searches go to an API compatible with Brave's web search: a GET with `q` and `count` parameters
+/// and the key in the `X-Subscription-Token` header, answered with `web.results`. Results are
+/// cached for the life of the tool, so repeating a search doesn't cost another request.
+pub struct WebSearch {
+    endpoint: String,
+    api_key: Option<String>,
+    client: Client,
+    // Tools are called on threads outside of the runtime, so requests are run on this handle
+    runtime: Handle,
+    cache: Mutex<HashMap<(String, usize), String>>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct WebSearchInput {
+    /// The search query, as it would be typed into a search engine.
+    query: String,
+    /// How many results to return, from 1 to 10. Defaults to 5.
+    count: Option<usize>,
+}
+
+impl WebSearch {
+    /// Create a tool that searches with the API at `endpoint` using the given key. Without a key,
+    /// every search fails with an error explaining how to configure one.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn new(endpoint: impl Into<String>, api_key: Option<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            api_key,
+            client: Client::new(),
+            runtime: Handle::current(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn search(&self, api_key: &str, query: &str, count: usize) -> Result<String, String> {
+        let request = self
+            .client
+            .get(&self.endpoint)
+            .query(&[("q", query), ("count", &count.to_string())])
+            .header("Accept", "application/json")
+            .header("X-Subscription-Token", api_key);
+        let body = self.runtime.block_on(async {
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            let body = response.text().await.map_err(|e| e.to_string())?;
+            if !status.is_success() {
+                return Err(format!("{}: {}", status, body.trim()));
+            }
+            Ok(body)
+        })?;
+        let response: SearchResponse =
+            serde_json::from_str(&body).map_err(|e| format!("Unexpected response: {}", e))?;
+        let results = response.web.map(|w| w.results).unwrap_or_default();
+        if results.is_empty() {
+            return Ok(format!("No results for '{}'.", query));
+        }
+        Ok(results
+            .iter()
+            .take(count)
+            .enumerate()
+            .map(|(i, r)| {
+                format!(
+                    "{}. {}\n   {}\n   {}",
+                    i + 1,
+                    r.title,
+                    r.url,
+                    strip_tags(&r.description)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+// Snippets mark the matched words with <strong>, which is only noise to the model.
+fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+impl LocalTool for WebSearch {
+    type Input = WebSearchInput;
+
+    fn name(&self) -> &'static str {
+        "search"
+    }
+
+    fn description(&self) -> &'static str {
+        "Search the web and get back a ranked list of results, each with its title, URL and a short \
+        snippet. Use this to find documentation, references or recent information that you don't \
+        already know. Snippets are short, so read the pages themselves before relying on them. \
+        Repeating an identical search returns the same results without searching again."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        let Some(api_key) = &self.api_key else {
+            return Err(Content::Text(
+                "Web search isn't configured. Ask the user to set `search_api_key` in their \
+                .asimov.toml."
+                    .to_string(),
+            ));
+        };
+        let count = input.count.unwrap_or(DEFAULT_RESULTS).clamp(1, MAX_RESULTS);
+        let key = (input.query.trim().to_string(), count);
+        if let Some(results) = self.cache.lock().unwrap().get(&key) {
+            log::debug!("Serving cached results for '{}'", key.0);
+            return Ok(vec![Content::Text(results.clone())]);
+        }
+        // Failed searches aren't cached, since they may well succeed when retried
+        let results = self
+            .search(api_key, &key.0, count)
+            .map_err(|e| Content::Text(format!("Search failed: {}", e)))?;
+        self.cache.lock().unwrap().insert(key, results.clone());
+        Ok(vec![Content::Text(results)])
+    }
+
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    web: Option<WebResults>,
+}
+
+#[derive(Deserialize)]
+struct WebResults {
+    #[serde(default)]
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    title: String,
+    url: String,
+    #[serde(default)]
+    description: String,
+}
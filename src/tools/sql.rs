@@ -0,0 +1,242 @@
+use crate::core::{llm::Content, tool::LocalTool};
+use rusqlite::{Connection, OpenFlags, types::ValueRef};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Mutex;
+
+/// The most rows returned by a single query.
+const MAX_ROWS: usize = 200;
+/// The most bytes of table returned by a single query.
+const MAX_BYTES: usize = 30000;
+
+/// A tool that lets the agent query a SQLite database.
+pub struct Sql {
+    url: String,
+    allow_writes: bool,
+    // Opened on first use and then reused for every query, so that an unused database costs
+    // nothing and temporary tables and the page cache survive between calls.
+    connection: Mutex<Option<Connection>>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct SqlInput {
+    /// The SQL statement to run.
+    query: String,
+}
+
+impl Sql {
+    /// Create a tool that queries the database at the given path or `sqlite://` URL. Unless
+    /// `allow_writes` is set, the database is opened read-only and only queries are accepted.
+    pub fn new(url: impl Into<String>, allow_writes: bool) -> Self {
+        Self {
+            url: url.into(),
+            allow_writes,
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&Connection) -> Result<T, Content>,
+    ) -> Result<T, Content> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            let path = self.url.strip_prefix("sqlite://").unwrap_or(&self.url);
+            let flags = if self.allow_writes {
+                OpenFlags::default()
+            } else {
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            };
+            let opened = Connection::open_with_flags(path, flags).map_err(|e| {
+                Content::Text(format!("Could not open the database {}: {}", self.url, e))
+            })?;
+            *connection = Some(opened);
+        }
+        f(connection.as_ref().unwrap())
+    }
+
+    /// Whether the statement only reads, as SQLite itself judges it once it's compiled. A
+    /// statement that doesn't compile can't do anything, so SQLite's error is returned instead for
+    /// the model to fix it.
+    fn reads_only(&self, query: &str) -> Result<bool, Content> {
+        // SQLite counts ATTACH as read-only since it leaves the database itself alone, but it
+        // creates the attached file if it doesn't exist
+        if keyword(query) == "ATTACH" {
+            return Ok(false);
+        }
+        self.with_connection(|connection| {
+            connection
+                .prepare(query)
+                .map(|statement| statement.readonly())
+                .map_err(sql_error)
+        })
+    }
+}
+
+// The first keyword of a statement, which says what kind of statement it is.
+fn keyword(query: &str) -> String {
+    query
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_uppercase()
+}
+
+fn sql_error(e: rusqlite::Error) -> Content {
+    Content::Text(format!("SQL error: {}", e))
+}
+
+fn render(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).replace('\n', "\\n"),
+        ValueRef::Blob(b) => format!("<{} byte blob>", b.len()),
+    }
+}
+
+/// Render rows as a table with `|`-separated, padded columns.
+fn table(columns: &[String], rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| {
+            rows.iter()
+                .map(|r| r[i].chars().count())
+                .chain(Some(columns[i].chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:w$}", c, w = w))
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+    let mut out = line(columns);
+    out.push('\n');
+    out.push_str(
+        &widths
+            .iter()
+            .map(|w| "-".repeat(*w))
+            .collect::<Vec<_>>()
+            .join("-+-"),
+    );
+    for row in rows {
+        out.push('\n');
+        out.push_str(&line(row));
+    }
+    out
+}
+
+impl LocalTool for Sql {
+    type Input = SqlInput;
+
+    fn name(&self) -> &'static str {
+        "sql"
+    }
+
+    fn description(&self) -> &'static str {
+        "Run a single SQL statement against the user's SQLite database and get the resulting rows \
+        back as a table. Results are capped at 200 rows, so use LIMIT, WHERE and aggregates to keep \
+        them small. To learn the schema, query `sqlite_schema`. Unless the user has allowed writes, \
+        only read-only statements like SELECT are accepted."
+    }
+
+    // Statements that fail to compile only ever give an error, so they're safe to run.
+    fn is_read_only(&self, input: &Self::Input) -> bool {
+        self.reads_only(&input.query).unwrap_or(true)
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        if !self.allow_writes && !self.reads_only(&input.query)? {
+            return Err(Content::Text(
+                "Only read-only statements (like SELECT) are allowed, since the user hasn't enabled \
+                writes."
+                    .to_string(),
+            ));
+        }
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare(&input.query).map_err(sql_error)?;
+            let columns: Vec<String> = statement
+                .column_names()
+                .into_iter()
+                .map(String::from)
+                .collect();
+            if columns.is_empty() {
+                let changed = statement.execute([]).map_err(sql_error)?;
+                return Ok(vec![Content::Text(format!("{} rows changed.", changed))]);
+            }
+            let mut rows = statement.query([]).map_err(sql_error)?;
+            let mut out = Vec::new();
+            let mut bytes = 0;
+            let mut truncated = false;
+            while let Some(row) = rows.next().map_err(sql_error)? {
+                let cells: Vec<String> = (0..columns.len())
+                    .map(|i| row.get_ref(i).map(render))
+                    .collect::<Result<_, _>>()
+                    .map_err(sql_error)?;
+                bytes += cells.iter().map(|c| c.len() + 3).sum::<usize>();
+                if out.len() == MAX_ROWS || bytes > MAX_BYTES {
+                    truncated = true;
+                    break;
+                }
+                out.push(cells);
+            }
+            let mut text = table(&columns, &out);
+            if truncated {
+                text.push_str(&format!(
+                    "\n[Truncated to the first {} rows, narrow the query to see more.]",
+                    out.len()
+                ));
+            } else {
+                text.push_str(&format!("\n({} rows)", out.len()));
+            }
+            Ok(vec![Content::Text(text)])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn judges_statements_by_what_they_do() {
+        let path = std::env::temp_dir().join(format!("asimov-sql-{}.db", std::process::id()));
+        let setup = Connection::open(&path).unwrap();
+        setup
+            .execute_batch("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT);")
+            .unwrap();
+        let sql = Sql::new(path.to_string_lossy(), false);
+        let read_only = |query: &str| {
+            sql.is_read_only(&SqlInput {
+                query: query.to_string(),
+            })
+        };
+        assert!(read_only("SELECT * FROM notes"));
+        assert!(read_only("  with n AS (SELECT 1) SELECT * FROM n"));
+        assert!(!read_only("WITH n AS (SELECT 1) DELETE FROM notes"));
+        assert!(!read_only("INSERT INTO notes (body) VALUES ('x')"));
+        assert!(!read_only("ATTACH 'other.db' AS other"));
+        let refused = sql.call(SqlInput {
+            query: "WITH n AS (SELECT 1) DELETE FROM notes".to_string(),
+        });
+        assert!(matches!(refused, Err(Content::Text(e)) if e.starts_with("Only read-only")));
+
+        // The model is shown what's wrong with a statement that doesn't compile
+        let broken = sql.call(SqlInput {
+            query: "SELECT * FROM missing".to_string(),
+        });
+        assert!(
+            matches!(&broken, Err(Content::Text(e)) if e.starts_with("SQL error") && e.contains("missing")),
+            "{:?}",
+            broken
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+use super::walk;
+use crate::core::{llm::Content, tool::LocalTool};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// The most files that will be searched in one call.
+const MAX_FILES: usize = 5000;
+/// The most definitions that will be returned in one call.
+const MAX_RESULTS: usize = 50;
+/// Files larger than this are probably generated or data, and are skipped.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// A tool that finds where functions, types, and other symbols are defined in the workspace.
+///
+/// This works across languages by looking for declaration keywords (`fn`, `class`, `def`, etc.)
+/// followed by the symbol's name, rather than actually parsing anything. It's crude, but it's
+/// usually enough to jump straight to a definition.
+pub struct Symbols {
+    root: PathBuf,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct SymbolsInput {
+    /// The exact name of the symbol to find, i.e `Toolbox` or `parse_args`.
+    symbol: String,
+    /// Only find symbols of this kind.
+    kind: Option<SymbolKind>,
+    /// A directory within the workspace to limit the search to. Defaults to the whole workspace.
+    path: Option<PathBuf>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolKind {
+    /// Functions and methods.
+    Function,
+    /// Structs, enums, classes, and type aliases.
+    Type,
+    /// Traits, interfaces, and protocols.
+    Trait,
+    /// Modules and namespaces.
+    Module,
+    /// Constants and statics.
+    Constant,
+}
+
+impl SymbolKind {
+    const ALL: [SymbolKind; 5] = [
+        SymbolKind::Function,
+        SymbolKind::Type,
+        SymbolKind::Trait,
+        SymbolKind::Module,
+        SymbolKind::Constant,
+    ];
+
+    fn keywords(self) -> &'static [&'static str] {
+        match self {
+            SymbolKind::Function => &["fn", "def", "function", "func", "fun"],
+            SymbolKind::Type => &["struct", "enum", "class", "type", "union", "typedef"],
+            SymbolKind::Trait => &["trait", "interface", "protocol"],
+            SymbolKind::Module => &["mod", "module", "namespace", "package"],
+            SymbolKind::Constant => &["const", "static", "let", "var", "val"],
+        }
+    }
+}
+
+impl Symbols {
+    /// Create a new symbol search limited to the given workspace directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Find the kind of symbol defined by `line` with the given name, if any.
+    fn definition(line: &str, symbol: &str, kind: Option<SymbolKind>) -> Option<SymbolKind> {
+        let line = line.trim_start();
+        if line.starts_with("//") || line.starts_with('#') || line.starts_with('*') {
+            return None;
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        tokens.windows(2).find_map(|pair| {
+            let name: String = pair[1]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+                .collect();
+            if name != symbol {
+                return None;
+            }
+            SymbolKind::ALL
+                .into_iter()
+                .filter(|k| kind.is_none_or(|kind| kind == *k))
+                .find(|k| k.keywords().contains(&pair[0]))
+        })
+    }
+}
+
+impl LocalTool for Symbols {
+    type Input = SymbolsInput;
+
+    fn name(&self) -> &'static str {
+        "find_symbol"
+    }
+
+    fn description(&self) -> &'static str {
+        "Find where a function, type, trait, module, or constant is defined in the workspace. \
+        Returns the file and line of each definition alongside the line itself. Prefer this over \
+        viewing files one by one when you know the name of what you're looking for. Matching is \
+        based on declaration keywords, so it works for most languages but may occasionally miss \
+        unusual declarations."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        let root = self
+            .root
+            .canonicalize()
+            .map_err(|e| Content::Text(format!("Could not read the workspace: {}", e)))?;
+        let dir = match &input.path {
+            Some(path) => root
+                .join(path)
+                .canonicalize()
+                .map_err(|e| Content::Text(format!("Could not read {:?}: {}", path, e)))?,
+            None => root.clone(),
+        };
+        if !dir.starts_with(&root) {
+            return Err(Content::Text(format!(
+                "{:?} is outside of the workspace.",
+                dir
+            )));
+        }
+
+        let mut results = Vec::new();
+        'files: for file in walk::files(&dir, MAX_FILES) {
+            if fs::metadata(&file)
+                .map(|m| m.len() > MAX_FILE_BYTES)
+                .unwrap_or(true)
+            {
+                continue;
+            }
+            // Binary files won't be valid UTF-8, so they're skipped here too
+            let Ok(content) = fs::read_to_string(&file) else {
+                continue;
+            };
+            for (i, line) in content.lines().enumerate() {
+                if Self::definition(line, &input.symbol, input.kind).is_some() {
+                    let relative = file.strip_prefix(&root).unwrap_or(&file);
+                    results.push(format!("{}:{}: {}", relative.display(), i + 1, line.trim()));
+                    if results.len() >= MAX_RESULTS {
+                        break 'files;
+                    }
+                }
+            }
+        }
+
+        if results.is_empty() {
+            return Ok(vec![Content::Text(format!(
+                "No definitions of '{}' were found.",
+                input.symbol
+            ))]);
+        }
+        let mut text = results.join("\n");
+        if results.len() >= MAX_RESULTS {
+            text.push_str(&format!(
+                "\n\n[Stopped after {} results. Narrow the search with kind or path.]",
+                MAX_RESULTS
+            ));
+        }
+        Ok(vec![Content::Text(text)])
+    }
+
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        true
+    }
+}
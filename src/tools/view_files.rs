@@ -0,0 +1,150 @@
+use super::truncate_head;
+use crate::anthropic::{EditorConfig, read_file};
+use crate::core::{llm::Content, tool::LocalTool};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// A tool that views several files in one call, saving a round-trip per file when the agent
+/// already knows what it needs to read.
+pub struct ViewFiles {
+    config: EditorConfig,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ViewFilesInput {
+    /// The paths of the files to view, in order.
+    paths: Vec<PathBuf>,
+    /// 1-based start and end lines (inclusive) to view in every file. Defaults to whole files.
+    view_range: Option<[u64; 2]>,
+}
+
+impl ViewFiles {
+    /// Create the tool, reading files with the same limits as the editor. At most
+    /// `max_view_lines` lines are returned across all files in one call.
+    pub fn new(config: EditorConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl LocalTool for ViewFiles {
+    type Input = ViewFilesInput;
+
+    fn name(&self) -> &'static str {
+        "view_files"
+    }
+
+    fn description(&self) -> &'static str {
+        "View the contents of several files at once, each labeled with its path. Prefer this over \
+        viewing files one by one when you already know you need to read more than one. A file that \
+        can't be read doesn't stop the others from being shown. Output is capped, so files past the \
+        cap are skipped and should be viewed on their own."
+    }
+
+    fn call(&self, input: Self::Input) -> Result<Vec<Content>, Content> {
+        if input.paths.is_empty() {
+            return Err(Content::Text("At least one path is required.".to_string()));
+        }
+        let mut remaining = self.config.max_view_lines;
+        let mut out = String::new();
+        for path in &input.paths {
+            out.push_str(&format!("=== {} ===\n", path.display()));
+            if remaining == 0 {
+                out.push_str(
+                    "[Skipped, the output limit was reached. View this file on its own.]\n",
+                );
+                continue;
+            }
+            let content = match read_file(path, self.config.max_file_bytes) {
+                Ok(content) => content,
+                Err(e) => {
+                    out.push_str(&format!("[Could not read file: {}]\n", e));
+                    continue;
+                }
+            };
+            let lines: Vec<&str> = content.lines().collect();
+            let (start, end) = match input.view_range {
+                Some([start, end]) => (
+                    start.saturating_sub(1) as usize,
+                    (end as usize).min(lines.len()),
+                ),
+                None => (0, lines.len()),
+            };
+            if start >= end {
+                out.push_str(&format!(
+                    "[Invalid view range for file with {} lines.]\n",
+                    lines.len()
+                ));
+                continue;
+            }
+            let capped_end = end.min(start + remaining);
+            for line in &lines[start..capped_end] {
+                out.push_str(line);
+                out.push('\n');
+            }
+            remaining -= capped_end - start;
+            if capped_end < end {
+                out.push_str(&format!(
+                    "[Showing lines {}-{} of {}, the output limit was reached.]\n",
+                    start + 1,
+                    capped_end,
+                    lines.len()
+                ));
+            }
+        }
+        // Long lines can get past the line limit, so the output is capped as a whole too
+        Ok(vec![Content::Text(truncate_head(out))])
+    }
+
+    fn is_read_only(&self, _input: &Self::Input) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::MAX_OUTPUT_CHARS;
+    use std::fs;
+
+    fn view(tool: &ViewFiles, paths: &[PathBuf]) -> String {
+        let input = ViewFilesInput {
+            paths: paths.to_vec(),
+            view_range: None,
+        };
+        match tool.call(input).unwrap().as_slice() {
+            [Content::Text(text)] => text.clone(),
+            other => panic!("expected text, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn files_are_read_like_the_editor_reads_them() {
+        let dir = std::env::temp_dir().join(format!("asimov-view-files-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let latin1 = dir.join("latin1.txt");
+        fs::write(&latin1, b"caf\xe9\n").unwrap();
+        let large = dir.join("large.txt");
+        fs::write(&large, "x".repeat(64)).unwrap();
+        let binary = dir.join("binary.bin");
+        fs::write(&binary, b"\x00\x01\x02").unwrap();
+        let tool = ViewFiles::new(EditorConfig {
+            max_file_bytes: 32,
+            ..EditorConfig::default()
+        });
+
+        let out = view(&tool, &[large.clone(), binary, dir.join("missing"), latin1]);
+        assert!(out.contains("too large: 64 bytes, limit 32"), "{}", out);
+        assert!(out.contains("binary file"), "{}", out);
+        assert!(out.ends_with("café\n"), "{}", out);
+
+        // Lines can be long enough to get past the line limit, but not the output cap
+        fs::write(&large, "x".repeat(MAX_OUTPUT_CHARS * 2)).unwrap();
+        let tool = ViewFiles::new(EditorConfig::default());
+        let out = view(&tool, &[large]);
+        assert!(out.contains("[Truncated, showing the first"), "{}", out);
+        assert!(out.len() < MAX_OUTPUT_CHARS + 100);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories that are never worth searching, even if they aren't ignored.
+const SKIPPED_DIRS: [&str; 2] = ["target", "node_modules"];
+
+/// Walk all files under `root` in a stable order, stopping after `limit` files.
+///
+/// Hidden files, common build directories, and anything ignored by the `.gitignore` at the root
+/// are skipped.
+pub fn files(root: &Path, limit: usize) -> Vec<PathBuf> {
+    let ignore = Ignore::load(root);
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries: Vec<_> = read_dir.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        // Reversed, since the stack pops directories in the opposite order
+        entries.sort_by(|a, b| b.cmp(a));
+        for path in entries {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let is_dir = path.is_dir();
+            if name.starts_with('.') || ignore.is_ignored(relative, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if !SKIPPED_DIRS.contains(&&*name) {
+                    dirs.push(path);
+                }
+            } else {
+                files.push(path);
+            }
+        }
+        if files.len() >= limit {
+            files.truncate(limit);
+            break;
+        }
+    }
+    files.sort();
+    files
+}
+
+/// A minimal `.gitignore` matcher. Only plain, `*`, `**`, anchored (`/foo`), and directory
+/// (`foo/`) patterns are supported, which covers the vast majority of real-world ignore files.
+struct Ignore {
+    patterns: Vec<Pattern>,
+}
+
+struct Pattern {
+    glob: String,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl Ignore {
+    fn load(root: &Path) -> Self {
+        let patterns = fs::read_to_string(root.join(".gitignore"))
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            // Negations aren't supported, so it's safer to not ignore anything they mention
+            .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('!'))
+            .map(|l| {
+                let dir_only = l.ends_with('/');
+                let l = l.trim_end_matches('/');
+                let anchored = l.starts_with('/') || l.contains('/');
+                Pattern {
+                    glob: l.trim_start_matches('/').to_string(),
+                    anchored,
+                    dir_only,
+                }
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        let path = relative.to_string_lossy().replace('\\', "/");
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        self.patterns.iter().any(|p| {
+            (is_dir || !p.dir_only)
+                && if p.anchored {
+                    glob_match(p.glob.as_bytes(), path.as_bytes())
+                } else {
+                    glob_match(p.glob.as_bytes(), name.as_bytes())
+                }
+        })
+    }
+}
+
+// `*` and `?` match anything but a separator, while `**` matches anything at all.
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob_match(rest, &text[i..])),
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && glob_match(rest, tail)),
+        [c, rest @ ..] => matches!(text, [t, tail @ ..] if t == c && glob_match(rest, tail)),
+    }
+}